@@ -0,0 +1,1196 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, Mutex};
+
+use crate::defi_protocol::{self, DeFiProtocol};
+use crate::dex_engine::{DEXEngine, DepthUpdate, Order, OrderEvent, OrderRequest, OrderSide, OrderType, Ticker, TimeInForce, Trade};
+use crate::engine_cluster::EngineCluster;
+use crate::nft_marketplace::{ListingType, NFTMarketplace, NFTMetadata};
+
+const PENDING_ORDER_INTERVAL: Duration = Duration::from_secs(1);
+const EVENT_BUFFER: usize = 4096;
+const DEFAULT_DEPTH: usize = 20;
+const DEFAULT_LIMIT: usize = 50;
+
+/// JSON-RPC 2.0 error codes the server answers with.
+pub mod error_codes {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+    pub const ENGINE_ERROR: i64 = -32000; // The engine refused the call; the message says why
+    pub const UNAUTHORIZED: i64 = -32001;
+    pub const FORBIDDEN: i64 = -32002; // Authenticated, but not as someone allowed to make the call
+}
+
+/// Who presented a token: a trader acting on their own account, or an operator who credits
+/// deposits once the funds have actually arrived.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Caller {
+    Trader(String),
+    Operator,
+}
+
+impl Caller {
+    fn trader(&self) -> Option<&str> {
+        match self {
+            Caller::Trader(trader) => Some(trader),
+            Caller::Operator => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<String> for RpcError {
+    fn from(message: String) -> Self {
+        Self::new(error_codes::ENGINE_ERROR, message)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    id: Option<Value>, // Absent for notifications, which get no response
+}
+
+/// WebSocket streams. Order updates only reach the trader who owns the order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Trades,
+    Depth,
+    Ticker,
+    Orders,
+}
+
+impl Channel {
+    fn method(&self) -> &'static str {
+        match self {
+            Channel::Trades => "trades",
+            Channel::Depth => "depth",
+            Channel::Ticker => "ticker",
+            Channel::Orders => "orders",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum StreamEvent {
+    Trade(Trade),
+    Depth(DepthUpdate),
+    Ticker(Ticker),
+    Order(Order),
+}
+
+impl StreamEvent {
+    fn channel(&self) -> Channel {
+        match self {
+            StreamEvent::Trade(_) => Channel::Trades,
+            StreamEvent::Depth(_) => Channel::Depth,
+            StreamEvent::Ticker(_) => Channel::Ticker,
+            StreamEvent::Order(_) => Channel::Orders,
+        }
+    }
+
+    fn symbol(&self) -> &str {
+        match self {
+            StreamEvent::Trade(trade) => &trade.symbol,
+            StreamEvent::Depth(update) => &update.symbol,
+            StreamEvent::Ticker(ticker) => &ticker.symbol,
+            StreamEvent::Order(order) => &order.symbol,
+        }
+    }
+
+    fn notification(&self) -> Value {
+        let params = match self {
+            StreamEvent::Trade(trade) => serde_json::to_value(trade),
+            StreamEvent::Depth(update) => serde_json::to_value(update),
+            StreamEvent::Ticker(ticker) => serde_json::to_value(ticker),
+            StreamEvent::Order(order) => serde_json::to_value(order),
+        };
+        notification(self.channel().method(), params.unwrap_or(Value::Null))
+    }
+}
+
+/// Turns the engine's per-symbol feeds into stream events. The engine hands out std channels, so
+/// they are drained after every call that can change the books rather than waited on.
+#[derive(Default)]
+struct Feed {
+    depth: HashMap<String, Receiver<DepthUpdate>>,
+    order_events: HashMap<String, Receiver<OrderEvent>>,
+    trades_seen: Option<usize>, // None until the first drain; trades before it are history, not news
+}
+
+impl Feed {
+    fn publish(&mut self, engine: &mut DEXEngine, events: &broadcast::Sender<StreamEvent>) {
+        for spec in engine.get_symbol_specs() {
+            if !self.depth.contains_key(&spec.symbol) {
+                if let Ok(receiver) = engine.subscribe_depth(&spec.symbol) {
+                    self.depth.insert(spec.symbol.clone(), receiver);
+                }
+            }
+            if !self.order_events.contains_key(&spec.symbol) {
+                if let Ok(receiver) = engine.subscribe_order_events(&spec.symbol) {
+                    self.order_events.insert(spec.symbol.clone(), receiver);
+                }
+            }
+        }
+
+        let mut touched = BTreeSet::new();
+        let trades = engine.get_trades();
+        for trade in &trades[self.trades_seen.unwrap_or(trades.len())..] {
+            touched.insert(trade.symbol.clone());
+            let _ = events.send(StreamEvent::Trade(trade.clone()));
+        }
+        self.trades_seen = Some(trades.len());
+
+        let mut symbols: Vec<&String> = self.depth.keys().collect();
+        symbols.sort();
+        for symbol in symbols {
+            for update in self.depth[symbol].try_iter() {
+                touched.insert(update.symbol.clone());
+                let _ = events.send(StreamEvent::Depth(update));
+            }
+        }
+
+        // One update per order carrying its state after the call, however many events it had
+        let mut order_ids: Vec<String> = Vec::new();
+        let mut symbols: Vec<&String> = self.order_events.keys().collect();
+        symbols.sort();
+        for symbol in symbols {
+            for event in self.order_events[symbol].try_iter() {
+                if !order_ids.contains(&event.order_id) {
+                    order_ids.push(event.order_id);
+                }
+            }
+        }
+        for order in order_ids.iter().filter_map(|order_id| engine.get_order(order_id)) {
+            let _ = events.send(StreamEvent::Order(order));
+        }
+
+        for ticker in touched.iter().filter_map(|symbol| engine.get_ticker(symbol)) {
+            let _ = events.send(StreamEvent::Ticker(ticker));
+        }
+    }
+}
+
+/// What one WebSocket connection is listening to.
+struct Subscriptions {
+    caller: Option<Caller>,
+    channels: HashSet<(Channel, Option<String>)>, // A None symbol covers every symbol
+}
+
+impl Subscriptions {
+    fn wants(&self, event: &StreamEvent) -> bool {
+        if let StreamEvent::Order(order) = event {
+            if self.caller.as_ref().and_then(Caller::trader) != Some(order.trader.as_str()) {
+                return false;
+            }
+        }
+        let channel = event.channel();
+        self.channels.iter()
+            .any(|(subscribed, symbol)| *subscribed == channel && symbol.as_deref().is_none_or(|s| s == event.symbol()))
+    }
+}
+
+#[derive(Deserialize)]
+struct SubscribeParams {
+    channel: Channel,
+    #[serde(default)]
+    symbol: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AuthParams {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct SymbolParams {
+    symbol: String,
+}
+
+#[derive(Deserialize)]
+struct DepthParams {
+    symbol: String,
+    #[serde(default = "default_depth")]
+    depth: usize,
+}
+
+#[derive(Deserialize)]
+struct SymbolLimitParams {
+    symbol: String,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+#[derive(Deserialize)]
+struct LimitParams {
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+#[derive(Deserialize)]
+struct PlaceOrderParams {
+    symbol: String,
+    side: OrderSide,
+    order_type: OrderType,
+    quantity: Decimal,
+    #[serde(default)]
+    price: Option<Decimal>,
+    #[serde(default)]
+    stop_price: Option<Decimal>,
+    #[serde(default = "default_time_in_force")]
+    time_in_force: TimeInForce,
+    #[serde(default)]
+    expire_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct AmendOrderParams {
+    order_id: String,
+    #[serde(default)]
+    quantity: Option<Decimal>,
+    #[serde(default)]
+    price: Option<Decimal>,
+}
+
+#[derive(Deserialize)]
+struct OrderIdParams {
+    order_id: String,
+}
+
+#[derive(Deserialize)]
+struct CurrencyParams {
+    currency: String,
+}
+
+#[derive(Deserialize)]
+struct DepositParams {
+    user: String,
+    currency: String,
+    amount: Decimal,
+}
+
+#[derive(Deserialize)]
+struct TokenDepositParams {
+    user: String,
+    token: String,
+    amount: Decimal,
+}
+
+#[derive(Deserialize)]
+struct CurrencyAmountParams {
+    currency: String,
+    amount: Decimal,
+}
+
+#[derive(Deserialize)]
+struct TokenParams {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct TokenAmountParams {
+    token: String,
+    amount: Decimal,
+}
+
+#[derive(Deserialize)]
+struct PoolParams {
+    pool_id: String,
+}
+
+#[derive(Deserialize)]
+struct AmountOutParams {
+    pool_id: String,
+    amount_in: Decimal,
+    token_in: String,
+}
+
+#[derive(Deserialize)]
+struct AddLiquidityParams {
+    pool_id: String,
+    amount_a: Decimal,
+    amount_b: Decimal,
+}
+
+#[derive(Deserialize)]
+struct RemoveLiquidityParams {
+    pool_id: String,
+    liquidity: Decimal,
+}
+
+#[derive(Deserialize)]
+struct DeFiOrderParams {
+    symbol: String,
+    side: defi_protocol::OrderSide,
+    order_type: defi_protocol::OrderType,
+    amount: Decimal,
+    #[serde(default)]
+    price: Option<Decimal>,
+}
+
+#[derive(Deserialize)]
+struct TokenIdParams {
+    token_id: String,
+}
+
+#[derive(Deserialize)]
+struct OwnerParams {
+    owner: String,
+}
+
+#[derive(Deserialize)]
+struct AddressParams {
+    address: String,
+}
+
+#[derive(Deserialize)]
+struct ListingParams {
+    listing_id: String,
+}
+
+#[derive(Deserialize)]
+struct MintParams {
+    contract_address: String,
+    metadata: NFTMetadata,
+    royalty_percentage: Decimal,
+}
+
+#[derive(Deserialize)]
+struct CreateListingParams {
+    token_id: String,
+    listing_type: ListingType,
+    price: Decimal,
+    currency: String,
+    #[serde(default)]
+    duration_days: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct BidParams {
+    listing_id: String,
+    amount: Decimal,
+    currency: String,
+}
+
+#[derive(Deserialize)]
+struct AcceptBidParams {
+    listing_id: String,
+    bid_id: String,
+}
+
+#[derive(Deserialize)]
+struct TransferParams {
+    token_id: String,
+    to: String,
+}
+
+fn default_depth() -> usize {
+    DEFAULT_DEPTH
+}
+
+fn default_limit() -> usize {
+    DEFAULT_LIMIT
+}
+
+fn default_time_in_force() -> TimeInForce {
+    TimeInForce::GTC
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Methods without parameters may leave them out altogether
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(error_codes::INVALID_PARAMS, e.to_string()))
+}
+
+fn to_result<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::new(error_codes::INTERNAL_ERROR, e.to_string()))
+}
+
+fn authenticated(trader: Option<&str>) -> Result<&str, RpcError> {
+    trader.ok_or_else(|| RpcError::new(error_codes::UNAUTHORIZED, "Authentication required"))
+}
+
+fn operator(caller: Option<&Caller>) -> Result<(), RpcError> {
+    match caller {
+        Some(Caller::Operator) => Ok(()),
+        Some(Caller::Trader(_)) => Err(RpcError::new(error_codes::FORBIDDEN, "Only operators can credit deposits")),
+        None => Err(RpcError::new(error_codes::UNAUTHORIZED, "Authentication required")),
+    }
+}
+
+/// Amounts moved out of an account; a negative one would move funds in.
+fn positive(amount: Decimal) -> Result<Decimal, RpcError> {
+    if amount <= Decimal::ZERO {
+        return Err(RpcError::new(error_codes::INVALID_PARAMS, "Amount must be positive"));
+    }
+    Ok(amount)
+}
+
+fn method_not_found(method: &str) -> RpcError {
+    RpcError::new(error_codes::METHOD_NOT_FOUND, format!("Unknown method {}", method))
+}
+
+fn response(id: Value, outcome: Result<Value, RpcError>) -> Value {
+    match outcome {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(error) => json!({"jsonrpc": "2.0", "id": id, "error": error}),
+    }
+}
+
+fn error_response(error: RpcError) -> Value {
+    response(Value::Null, Err(error))
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({"jsonrpc": "2.0", "method": method, "params": params})
+}
+
+fn parse_request(value: Value) -> Result<RpcRequest, Value> {
+    let request: RpcRequest = serde_json::from_value(value)
+        .map_err(|e| error_response(RpcError::new(error_codes::INVALID_REQUEST, e.to_string())))?;
+    if request.jsonrpc != "2.0" {
+        return Err(error_response(RpcError::new(error_codes::INVALID_REQUEST, "Only JSON-RPC 2.0 is supported")));
+    }
+    Ok(request)
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// The `dex.*` calls about one symbol or one order, made on the engine the cluster runs it on.
+fn call_dex(engine: &mut DEXEngine, method: &str, request: Value, caller: Option<&Caller>) -> Result<Value, RpcError> {
+    let trader = caller.and_then(Caller::trader);
+    match method {
+        "get_ticker" => {
+            let request: SymbolParams = params(request)?;
+            to_result(engine.get_ticker(&request.symbol))
+        }
+        "get_depth" => {
+            let request: DepthParams = params(request)?;
+            let snapshot = engine.get_depth_snapshot(&request.symbol, request.depth)
+                .ok_or_else(|| "Symbol not supported".to_string())?;
+            to_result(snapshot)
+        }
+        "get_recent_trades" => {
+            let request: SymbolLimitParams = params(request)?;
+            to_result(engine.get_recent_trades(&request.symbol, request.limit))
+        }
+        "get_order" => {
+            let trader = authenticated(trader)?;
+            let request: OrderIdParams = params(request)?;
+            let order = engine.get_order(&request.order_id)
+                .filter(|order| order.trader == trader)
+                .ok_or_else(|| "Order not found".to_string())?;
+            to_result(order)
+        }
+        "place_order" => {
+            let trader = authenticated(trader)?;
+            let request: PlaceOrderParams = params(request)?;
+            to_result(engine.place_order(OrderRequest {
+                time_in_force: request.time_in_force,
+                expire_at: request.expire_at,
+                ..OrderRequest::new(trader.to_string(), request.symbol, request.side, request.order_type,
+                                    request.quantity, request.price, request.stop_price)
+            })?)
+        }
+        "amend_order" => {
+            let trader = authenticated(trader)?;
+            let request: AmendOrderParams = params(request)?;
+            to_result(engine.amend_order(&request.order_id, trader, request.quantity, request.price)?)
+        }
+        "cancel_order" => {
+            let trader = authenticated(trader)?;
+            let request: OrderIdParams = params(request)?;
+            engine.cancel_order(&request.order_id, trader)?;
+            to_result(engine.get_order(&request.order_id))
+        }
+        _ => Err(method_not_found(&format!("dex.{}", method))),
+    }
+}
+
+fn call_defi(protocol: &mut DeFiProtocol, method: &str, request: Value, caller: Option<&Caller>) -> Result<Value, RpcError> {
+    let trader = caller.and_then(Caller::trader);
+    match method {
+        "get_pool" => {
+            let request: PoolParams = params(request)?;
+            to_result(protocol.get_pool_info(&request.pool_id)?)
+        }
+        "get_pool_price" => {
+            let request: PoolParams = params(request)?;
+            to_result(protocol.get_pool_price(&request.pool_id)?)
+        }
+        "get_amount_out" => {
+            let request: AmountOutParams = params(request)?;
+            to_result(protocol.get_amount_out(&request.pool_id, request.amount_in, &request.token_in)?)
+        }
+        "get_stats" => to_result(protocol.get_protocol_stats()),
+        "get_order_book" => {
+            let request: SymbolParams = params(request)?;
+            let (bids, asks) = protocol.get_order_book(&request.symbol);
+            Ok(json!({"bids": to_result(bids)?, "asks": to_result(asks)?}))
+        }
+        "get_recent_trades" => {
+            let request: LimitParams = params(request)?;
+            to_result(protocol.get_recent_trades(request.limit))
+        }
+        "get_positions" => to_result(protocol.get_user_positions(authenticated(trader)?)),
+        "get_balance" => {
+            let trader = authenticated(trader)?;
+            let request: TokenParams = params(request)?;
+            to_result(protocol.get_user_balance(trader, &request.token))
+        }
+        "deposit" => {
+            operator(caller)?;
+            let request: TokenDepositParams = params(request)?;
+            protocol.deposit_token(&request.user, &request.token, request.amount);
+            to_result(protocol.get_user_balance(&request.user, &request.token))
+        }
+        "withdraw" => {
+            let trader = authenticated(trader)?;
+            let request: TokenAmountParams = params(request)?;
+            protocol.withdraw_token(trader, &request.token, positive(request.amount)?)?;
+            to_result(protocol.get_user_balance(trader, &request.token))
+        }
+        "add_liquidity" => {
+            let trader = authenticated(trader)?;
+            let request: AddLiquidityParams = params(request)?;
+            to_result(protocol.add_liquidity(&request.pool_id, trader, request.amount_a, request.amount_b)?)
+        }
+        "remove_liquidity" => {
+            let trader = authenticated(trader)?;
+            let request: RemoveLiquidityParams = params(request)?;
+            let (amount_a, amount_b) = protocol.remove_liquidity(&request.pool_id, trader, request.liquidity)?;
+            Ok(json!({"amount_a": to_result(amount_a)?, "amount_b": to_result(amount_b)?}))
+        }
+        "swap" => {
+            let trader = authenticated(trader)?;
+            let request: AmountOutParams = params(request)?;
+            to_result(protocol.swap(&request.pool_id, trader, request.amount_in, &request.token_in)?)
+        }
+        "place_order" => {
+            let trader = authenticated(trader)?;
+            let request: DeFiOrderParams = params(request)?;
+            to_result(protocol.place_order(trader, request.side, request.order_type, &request.symbol,
+                                           request.amount, request.price)?)
+        }
+        "cancel_order" => {
+            let trader = authenticated(trader)?;
+            let request: OrderIdParams = params(request)?;
+            protocol.cancel_order(&request.order_id, trader)?;
+            Ok(Value::Bool(true))
+        }
+        _ => Err(method_not_found(&format!("defi.{}", method))),
+    }
+}
+
+fn call_nft(marketplace: &mut NFTMarketplace, method: &str, request: Value, caller: Option<&Caller>) -> Result<Value, RpcError> {
+    let trader = caller.and_then(Caller::trader);
+    match method {
+        "get_nft" => {
+            let request: TokenIdParams = params(request)?;
+            to_result(marketplace.get_nft(&request.token_id)?)
+        }
+        "get_user_nfts" => {
+            let request: OwnerParams = params(request)?;
+            to_result(marketplace.get_user_nfts(&request.owner))
+        }
+        "get_listing" => {
+            let request: ListingParams = params(request)?;
+            to_result(marketplace.get_listing(&request.listing_id)?)
+        }
+        "get_active_listings" => to_result(marketplace.get_active_listings()),
+        "get_listing_bids" => {
+            let request: ListingParams = params(request)?;
+            to_result(marketplace.get_listing_bids(&request.listing_id)?)
+        }
+        "get_collection" => {
+            let request: AddressParams = params(request)?;
+            to_result(marketplace.get_collection(&request.address)?)
+        }
+        "get_top_collections" => {
+            let request: LimitParams = params(request)?;
+            to_result(marketplace.get_top_collections(request.limit))
+        }
+        "get_recent_transactions" => {
+            let request: LimitParams = params(request)?;
+            to_result(marketplace.get_recent_transactions(request.limit))
+        }
+        "get_stats" => to_result(marketplace.get_market_stats()),
+        "get_balance" => {
+            let trader = authenticated(trader)?;
+            let request: CurrencyParams = params(request)?;
+            to_result(marketplace.get_user_balance(trader, &request.currency))
+        }
+        "deposit" => {
+            operator(caller)?;
+            let request: DepositParams = params(request)?;
+            marketplace.deposit_funds(&request.user, &request.currency, request.amount);
+            to_result(marketplace.get_user_balance(&request.user, &request.currency))
+        }
+        "withdraw" => {
+            let trader = authenticated(trader)?;
+            let request: CurrencyAmountParams = params(request)?;
+            marketplace.withdraw_funds(trader, &request.currency, positive(request.amount)?)?;
+            to_result(marketplace.get_user_balance(trader, &request.currency))
+        }
+        "mint" => {
+            let trader = authenticated(trader)?;
+            let request: MintParams = params(request)?;
+            to_result(marketplace.mint_nft(&request.contract_address, trader, request.metadata,
+                                           request.royalty_percentage)?)
+        }
+        "create_listing" => {
+            let trader = authenticated(trader)?;
+            let request: CreateListingParams = params(request)?;
+            to_result(marketplace.create_listing(&request.token_id, trader, request.listing_type, request.price,
+                                                 request.currency, request.duration_days)?)
+        }
+        "cancel_listing" => {
+            let trader = authenticated(trader)?;
+            let request: ListingParams = params(request)?;
+            marketplace.cancel_listing(&request.listing_id, trader)?;
+            to_result(marketplace.get_listing(&request.listing_id)?)
+        }
+        "place_bid" => {
+            let trader = authenticated(trader)?;
+            let request: BidParams = params(request)?;
+            to_result(marketplace.place_bid(&request.listing_id, trader, request.amount, &request.currency)?)
+        }
+        "accept_bid" => {
+            let trader = authenticated(trader)?;
+            let request: AcceptBidParams = params(request)?;
+            to_result(marketplace.accept_bid(&request.listing_id, &request.bid_id, trader)?)
+        }
+        "cancel_bid" => {
+            let trader = authenticated(trader)?;
+            let request: AcceptBidParams = params(request)?;
+            marketplace.cancel_bid(&request.listing_id, &request.bid_id, trader)?;
+            to_result(marketplace.get_listing(&request.listing_id)?)
+        }
+        "buy_now" => {
+            let trader = authenticated(trader)?;
+            let request: ListingParams = params(request)?;
+            to_result(marketplace.buy_now(&request.listing_id, trader)?)
+        }
+        "transfer" => {
+            let trader = authenticated(trader)?;
+            let request: TransferParams = params(request)?;
+            to_result(marketplace.transfer_nft(&request.token_id, trader, &request.to)?)
+        }
+        _ => Err(method_not_found(&format!("nft.{}", method))),
+    }
+}
+
+/// JSON-RPC 2.0 server in front of the exchange engines. Calls are POSTed to `/rpc`, singly or in
+/// batches, and named `dex.*`, `defi.*` or `nft.*` after the engine method they run, with the
+/// engine's own serde types as params and results. DEX calls go to the symbol's engine in an
+/// `EngineCluster`, so calls on different symbols do not wait on each other. Callers authenticate with an
+/// `Authorization: Bearer` token registered through `add_trader` or `add_operator`; reads work
+/// without one. Traders act on their own account only, and only operators credit deposits.
+///
+/// `/ws` takes the same calls plus `auth`, `subscribe` and `unsubscribe`, and pushes `trades`,
+/// `depth`, `ticker` and `orders` notifications for what a connection subscribed to. Subscribing
+/// to depth answers with a snapshot; drop updates at or below its sequence. A `lagged`
+/// notification means the connection fell behind and missed updates, so take a new snapshot.
+pub struct ApiServer {
+    cluster: Arc<EngineCluster>,
+    defi: Arc<Mutex<DeFiProtocol>>,
+    nft: Arc<Mutex<NFTMarketplace>>,
+    tokens: Mutex<HashMap<String, Caller>>,
+    feeds: Mutex<HashMap<String, Arc<std::sync::Mutex<Feed>>>>, // Locked on the symbol's own task
+    events: broadcast::Sender<StreamEvent>,
+}
+
+impl ApiServer {
+    pub fn new(cluster: Arc<EngineCluster>, defi: Arc<Mutex<DeFiProtocol>>, nft: Arc<Mutex<NFTMarketplace>>) -> Self {
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        Self {
+            cluster,
+            defi,
+            nft,
+            tokens: Mutex::new(HashMap::new()),
+            feeds: Mutex::new(HashMap::new()),
+            events,
+        }
+    }
+
+    /// Lets whoever presents this token trade as `trader`.
+    pub async fn add_trader(&self, token: &str, trader: &str) {
+        self.tokens.lock().await.insert(token.to_string(), Caller::Trader(trader.to_string()));
+    }
+
+    /// Lets whoever presents this token credit deposits to any account.
+    pub async fn add_operator(&self, token: &str) {
+        self.tokens.lock().await.insert(token.to_string(), Caller::Operator);
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/rpc", post(handle_rpc))
+            .route("/ws", get(handle_ws))
+            .with_state(self)
+    }
+
+    /// Serves HTTP and WebSocket clients until the listener fails. Expiries and other timed
+    /// order changes are processed in the background meanwhile.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Result<(), String> {
+        let server = self.clone();
+        let timer = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PENDING_ORDER_INTERVAL);
+            loop {
+                interval.tick().await;
+                server.process_pending_orders().await;
+            }
+        });
+        let result = axum::serve(listener, self.router()).await.map_err(|e| e.to_string());
+        timer.abort();
+        result
+    }
+
+    async fn process_pending_orders(&self) {
+        for symbol in self.cluster.get_symbols().await {
+            let _ = self.on_symbol(&symbol, |engine| {
+                engine.process_pending_orders();
+                Ok(Value::Null)
+            }).await;
+        }
+    }
+
+    /// Runs `call` on the symbol's engine and publishes what it changed.
+    async fn on_symbol<F>(&self, symbol: &str, call: F) -> Result<Value, RpcError>
+    where
+        F: FnOnce(&mut DEXEngine) -> Result<Value, RpcError> + Send + 'static,
+    {
+        if !self.cluster.is_listed(symbol).await {
+            return Err("Symbol not supported".to_string().into());
+        }
+        let feed = self.feeds.lock().await.entry(symbol.to_string()).or_default().clone();
+        let events = self.events.clone();
+        self.cluster.with_symbol(symbol, move |engine| {
+            let mut feed = feed.lock().unwrap();
+            // Catch up first so changes made by other holders of the cluster are not pinned on this call
+            feed.publish(engine, &events);
+            let outcome = call(engine);
+            feed.publish(engine, &events);
+            outcome
+        }).await?
+    }
+
+    /// Answers the `dex.*` calls that span symbols from the cluster and its ledger, and sends the
+    /// rest to the engine of the symbol or order they name.
+    async fn call_dex(&self, method: &str, request: Value, caller: Option<&Caller>) -> Result<Value, RpcError> {
+        let trader = caller.and_then(Caller::trader);
+        let symbol = match method {
+            "get_symbols" => return to_result(self.cluster.get_symbol_specs().await),
+            "get_tickers" => return to_result(self.cluster.get_tickers().await),
+            "get_orders" => return to_result(self.cluster.get_user_orders(authenticated(trader)?).await),
+            "get_balances" => return to_result(self.cluster.get_user_balances(authenticated(trader)?).await),
+            "deposit" => {
+                operator(caller)?;
+                let request: DepositParams = params(request)?;
+                self.cluster.deposit(&request.user, &request.currency, request.amount).await;
+                return to_result(self.cluster.get_balance(&request.user, &request.currency).await);
+            }
+            "withdraw" => {
+                let trader = authenticated(trader)?;
+                let request: CurrencyAmountParams = params(request)?;
+                self.cluster.withdraw(trader, &request.currency, positive(request.amount)?).await?;
+                return to_result(self.cluster.get_balance(trader, &request.currency).await);
+            }
+            "get_ticker" | "get_depth" | "get_recent_trades" => params::<SymbolParams>(request.clone())?.symbol,
+            "place_order" => {
+                authenticated(trader)?;
+                params::<SymbolParams>(request.clone())?.symbol
+            }
+            "get_order" | "amend_order" | "cancel_order" => {
+                authenticated(trader)?;
+                let order_id = params::<OrderIdParams>(request.clone())?.order_id;
+                self.cluster.symbol_of(&order_id).await.ok_or_else(|| "Order not found".to_string())?
+            }
+            _ => return Err(method_not_found(&format!("dex.{}", method))),
+        };
+        let (method, caller) = (method.to_string(), caller.cloned());
+        self.on_symbol(&symbol, move |engine| call_dex(engine, &method, request, caller.as_ref())).await
+    }
+
+    async fn caller_for(&self, token: &str) -> Option<Caller> {
+        self.tokens.lock().await.get(token).cloned()
+    }
+
+    async fn dispatch(&self, method: &str, request: Value, caller: Option<&Caller>) -> Result<Value, RpcError> {
+        match method.split_once('.') {
+            Some(("dex", method)) => self.call_dex(method, request, caller).await,
+            Some(("defi", method)) => call_defi(&mut *self.defi.lock().await, method, request, caller),
+            Some(("nft", method)) => call_nft(&mut *self.nft.lock().await, method, request, caller),
+            _ => Err(method_not_found(method)),
+        }
+    }
+
+    async fn handle_request(&self, request: Value, caller: Option<&Caller>) -> Option<Value> {
+        let request = match parse_request(request) {
+            Ok(request) => request,
+            Err(reply) => return Some(reply),
+        };
+        let outcome = self.dispatch(&request.method, request.params, caller).await;
+        request.id.map(|id| response(id, outcome))
+    }
+
+    async fn handle_payload(&self, payload: &str, caller: Option<&Caller>) -> Option<Value> {
+        let payload: Value = match serde_json::from_str(payload) {
+            Ok(payload) => payload,
+            Err(e) => return Some(error_response(RpcError::new(error_codes::PARSE_ERROR, e.to_string()))),
+        };
+        let Value::Array(batch) = payload else {
+            return self.handle_request(payload, caller).await;
+        };
+        if batch.is_empty() {
+            return Some(error_response(RpcError::new(error_codes::INVALID_REQUEST, "Empty batch")));
+        }
+        let mut replies = Vec::new();
+        for request in batch {
+            replies.extend(self.handle_request(request, caller).await);
+        }
+        (!replies.is_empty()).then_some(Value::Array(replies))
+    }
+
+    async fn handle_socket_message(&self, text: &str, subscriptions: &mut Subscriptions) -> Option<Value> {
+        let request = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(RpcError::new(error_codes::PARSE_ERROR, e.to_string()))),
+        };
+        let request = match parse_request(request) {
+            Ok(request) => request,
+            Err(reply) => return Some(reply),
+        };
+        let outcome = match request.method.as_str() {
+            "auth" => self.authenticate(request.params, subscriptions).await,
+            "subscribe" => self.subscribe(request.params, subscriptions).await,
+            "unsubscribe" => params(request.params).map(|request: SubscribeParams| {
+                Value::Bool(subscriptions.channels.remove(&(request.channel, request.symbol)))
+            }),
+            method => self.dispatch(method, request.params, subscriptions.caller.as_ref()).await,
+        };
+        request.id.map(|id| response(id, outcome))
+    }
+
+    async fn authenticate(&self, request: Value, subscriptions: &mut Subscriptions) -> Result<Value, RpcError> {
+        let request: AuthParams = params(request)?;
+        let caller = self.caller_for(&request.token).await
+            .ok_or_else(|| RpcError::new(error_codes::UNAUTHORIZED, "Unknown token"))?;
+        let result = match &caller {
+            Caller::Trader(trader) => json!({"trader": trader}),
+            Caller::Operator => json!({"operator": true}),
+        };
+        subscriptions.caller = Some(caller);
+        Ok(result)
+    }
+
+    async fn subscribe(&self, request: Value, subscriptions: &mut Subscriptions) -> Result<Value, RpcError> {
+        let request: SubscribeParams = params(request)?;
+        if request.channel == Channel::Orders {
+            authenticated(subscriptions.caller.as_ref().and_then(Caller::trader))?;
+        }
+        let result = if request.channel == Channel::Depth {
+            let symbol = request.symbol.as_deref()
+                .ok_or_else(|| RpcError::new(error_codes::INVALID_PARAMS, "Depth subscriptions need a symbol"))?;
+            let snapshot = self.cluster.get_depth_snapshot(symbol, usize::MAX).await
+                .ok_or_else(|| "Symbol not supported".to_string())?;
+            to_result(snapshot)?
+        } else {
+            Value::Bool(true)
+        };
+        subscriptions.channels.insert((request.channel, request.symbol));
+        Ok(result)
+    }
+
+    async fn run_socket(self: Arc<Self>, mut socket: WebSocket, caller: Option<Caller>) {
+        // Listen before the first subscription so no update falls between a snapshot and the stream
+        let mut events = self.events.subscribe();
+        let mut subscriptions = Subscriptions {
+            caller,
+            channels: HashSet::new(),
+        };
+        loop {
+            let reply = tokio::select! {
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Text(text))) => self.handle_socket_message(text.as_str(), &mut subscriptions).await,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => None, // Pings are answered underneath
+                },
+                event = events.recv() => match event {
+                    Ok(event) => subscriptions.wants(&event).then(|| event.notification()),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => Some(notification("lagged", json!({"missed": missed}))),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
+            if let Some(reply) = reply {
+                if socket.send(Message::text(reply.to_string())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn unauthorized() -> Response {
+    let error = error_response(RpcError::new(error_codes::UNAUTHORIZED, "Unknown token"));
+    (StatusCode::UNAUTHORIZED, Json(error)).into_response()
+}
+
+async fn handle_rpc(State(server): State<Arc<ApiServer>>, headers: HeaderMap, body: String) -> Response {
+    let caller = match bearer_token(&headers) {
+        Some(token) => match server.caller_for(token).await {
+            Some(caller) => Some(caller),
+            None => return unauthorized(),
+        },
+        None => None,
+    };
+    match server.handle_payload(&body, caller.as_ref()).await {
+        Some(reply) => Json(reply).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+async fn handle_ws(State(server): State<Arc<ApiServer>>, headers: HeaderMap, upgrade: WebSocketUpgrade) -> Response {
+    // Browsers cannot set headers on a WebSocket handshake; they send an `auth` call instead
+    let caller = match bearer_token(&headers) {
+        Some(token) => match server.caller_for(token).await {
+            Some(caller) => Some(caller),
+            None => return unauthorized(),
+        },
+        None => None,
+    };
+    upgrade.on_upgrade(move |socket| server.run_socket(socket, caller))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite;
+
+    async fn start_server() -> (String, Arc<Mutex<DeFiProtocol>>) {
+        let cluster = EngineCluster::new();
+        cluster.add_symbol("BTC/USDT".to_string()).await.unwrap();
+        cluster.add_symbol("ETH/USDT".to_string()).await.unwrap();
+        let mut defi = DeFiProtocol::new();
+        defi.create_pool("ETH".to_string(), "USDC".to_string(), Decimal::from(100), Decimal::from(200000)).unwrap();
+        let defi = Arc::new(Mutex::new(defi));
+        let server = Arc::new(ApiServer::new(Arc::new(cluster), defi.clone(),
+                                             Arc::new(Mutex::new(NFTMarketplace::new()))));
+        server.add_trader("alice-token", "alice").await;
+        server.add_trader("bob-token", "bob").await;
+        server.add_operator("operator-token").await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(server.serve(listener));
+        (address, defi)
+    }
+
+    async fn post(address: &str, token: Option<&str>, body: Value) -> (u16, Value) {
+        let body = body.to_string();
+        let authorization = token.map(|token| format!("Authorization: Bearer {}\r\n", token)).unwrap_or_default();
+        let request = format!("POST /rpc HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                              address, authorization, body.len(), body);
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut raw = String::new();
+        stream.read_to_string(&mut raw).await.unwrap();
+        let (head, body) = raw.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    fn call(id: u64, method: &str, params: Value) -> Value {
+        json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+    }
+
+    #[tokio::test]
+    async fn test_json_rpc_over_http() {
+        let (address, defi) = start_server().await;
+
+        // Traders cannot credit themselves in any engine; deposits come from an operator
+        for method in ["dex.deposit", "nft.deposit"] {
+            let (_, reply) = post(&address, Some("alice-token"), call(1, method, json!({"user": "alice", "currency": "BTC", "amount": "2"}))).await;
+            assert_eq!(reply["error"]["code"], json!(error_codes::FORBIDDEN));
+        }
+        let (_, reply) = post(&address, Some("alice-token"), call(1, "defi.deposit", json!({"user": "alice", "token": "ETH", "amount": "2"}))).await;
+        assert_eq!(reply["error"]["code"], json!(error_codes::FORBIDDEN));
+        let (_, reply) = post(&address, None, call(1, "dex.deposit", json!({"user": "alice", "currency": "BTC", "amount": "2"}))).await;
+        assert_eq!(reply["error"]["code"], json!(error_codes::UNAUTHORIZED));
+
+        let (_, reply) = post(&address, Some("operator-token"), call(1, "dex.deposit", json!({"user": "alice", "currency": "BTC", "amount": "2"}))).await;
+        assert_eq!(reply["result"]["available"], json!("2"));
+        let (_, reply) = post(&address, Some("operator-token"), call(2, "dex.deposit", json!({"user": "bob", "currency": "USDT", "amount": "100000"}))).await;
+        assert_eq!(reply["id"], json!(2));
+
+        // A negative withdrawal would be a deposit; it is refused in every engine
+        for (method, params) in [("dex.withdraw", json!({"currency": "BTC", "amount": "-1000000"})),
+                                 ("nft.withdraw", json!({"currency": "BTC", "amount": "-1000000"})),
+                                 ("defi.withdraw", json!({"token": "ETH", "amount": "-1000000"}))] {
+            let (_, reply) = post(&address, Some("alice-token"), call(2, method, params)).await;
+            assert_eq!(reply["error"]["code"], json!(error_codes::INVALID_PARAMS), "{}", method);
+        }
+        let (_, reply) = post(&address, Some("alice-token"), call(2, "dex.get_balances", Value::Null)).await;
+        assert_eq!(reply["result"]["BTC"]["available"], json!("2"));
+        let (_, reply) = post(&address, Some("alice-token"), call(2, "nft.get_balance", json!({"currency": "BTC"}))).await;
+        assert_eq!(reply["result"], json!("0"));
+        assert_eq!(defi.lock().await.get_user_balance("alice", "ETH"), Decimal::ZERO);
+
+        let (_, reply) = post(&address, Some("alice-token"), call(3, "dex.place_order", json!({
+            "symbol": "BTC/USDT", "side": "Sell", "order_type": "Limit", "quantity": "1", "price": "30000"
+        }))).await;
+        let sell: Order = serde_json::from_value(reply["result"].clone()).unwrap();
+        assert_eq!(sell.trader, "alice");
+
+        // A batch answers each call in order; notifications get no answer
+        let (_, reply) = post(&address, Some("bob-token"), json!([
+            call(4, "dex.place_order", json!({
+                "symbol": "BTC/USDT", "side": "Buy", "order_type": "Limit", "quantity": "1", "price": "30000", "time_in_force": "IOC"
+            })),
+            {"jsonrpc": "2.0", "method": "dex.get_tickers"},
+            call(5, "dex.get_order", json!({"order_id": sell.id})),
+            call(6, "dex.get_recent_trades", json!({"symbol": "BTC/USDT"})),
+        ])).await;
+        let replies = reply.as_array().unwrap();
+        assert_eq!(replies.len(), 3);
+        assert_eq!(replies[0]["result"]["status"], json!("Filled"));
+        assert_eq!(replies[1]["error"]["message"], json!("Order not found")); // Alice's order is hers to look up
+        let trades: Vec<Trade> = serde_json::from_value(replies[2]["result"].clone()).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].seller, "alice");
+
+        // Orders on another symbol are found by their id alone, against the same balances
+        post(&address, Some("operator-token"), call(7, "dex.deposit", json!({"user": "alice", "currency": "USDT", "amount": "1000"}))).await;
+        let (_, reply) = post(&address, Some("alice-token"), call(7, "dex.place_order", json!({
+            "symbol": "ETH/USDT", "side": "Buy", "order_type": "Limit", "quantity": "1", "price": "500"
+        }))).await;
+        let bid: Order = serde_json::from_value(reply["result"].clone()).unwrap();
+        let (_, reply) = post(&address, Some("alice-token"), call(7, "dex.get_balances", Value::Null)).await;
+        assert_eq!(reply["result"]["USDT"]["locked"], json!("500"));
+        assert_eq!(reply["result"]["USDT"]["available"], json!("30500"));
+        let (_, reply) = post(&address, Some("alice-token"), call(7, "dex.cancel_order", json!({"order_id": bid.id}))).await;
+        assert_eq!(reply["result"]["status"], json!("Cancelled"));
+        let (_, reply) = post(&address, None, call(7, "dex.get_symbols", Value::Null)).await;
+        assert_eq!(reply["result"].as_array().unwrap().len(), 2);
+
+        let (_, reply) = post(&address, None, call(7, "dex.cancel_order", json!({"order_id": sell.id}))).await;
+        assert_eq!(reply["error"]["code"], json!(error_codes::UNAUTHORIZED));
+        let (status, _) = post(&address, Some("mallory-token"), call(8, "dex.get_tickers", Value::Null)).await;
+        assert_eq!(status, 401);
+        let (_, reply) = post(&address, None, call(9, "dex.launch_rockets", Value::Null)).await;
+        assert_eq!(reply["error"]["code"], json!(error_codes::METHOD_NOT_FOUND));
+        let (_, reply) = post(&address, None, call(10, "dex.get_depth", json!({"depth": 5}))).await;
+        assert_eq!(reply["error"]["code"], json!(error_codes::INVALID_PARAMS));
+        let (_, reply) = post(&address, None, json!("not a request")).await;
+        assert_eq!(reply["error"]["code"], json!(error_codes::INVALID_REQUEST));
+
+        post(&address, Some("operator-token"), call(11, "defi.deposit", json!({"user": "bob", "token": "ETH", "amount": "1"}))).await;
+        let (_, quote) = post(&address, None, call(12, "defi.get_amount_out", json!({"pool_id": "ETH_USDC", "amount_in": "1", "token_in": "ETH"}))).await;
+        let (_, swapped) = post(&address, Some("bob-token"), call(13, "defi.swap", json!({"pool_id": "ETH_USDC", "amount_in": "1", "token_in": "ETH"}))).await;
+        assert_eq!(swapped["result"], quote["result"]);
+        let amount_out: Decimal = serde_json::from_value(swapped["result"].clone()).unwrap();
+        assert_eq!(defi.lock().await.get_user_balance("bob", "USDC"), amount_out);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_streams_market_data_and_own_orders() {
+        let (address, _) = start_server().await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", address)).await.unwrap();
+
+        async fn send(socket: &mut tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>, message: Value) -> Value {
+            socket.send(tungstenite::Message::text(message.to_string())).await.unwrap();
+            receive(socket).await
+        }
+        async fn receive(socket: &mut tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>) -> Value {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next()).await.unwrap().unwrap().unwrap();
+            serde_json::from_str(message.to_text().unwrap()).unwrap()
+        }
+
+        let reply = send(&mut socket, call(1, "subscribe", json!({"channel": "orders"}))).await;
+        assert_eq!(reply["error"]["code"], json!(error_codes::UNAUTHORIZED));
+        let reply = send(&mut socket, call(2, "auth", json!({"token": "alice-token"}))).await;
+        assert_eq!(reply["result"]["trader"], json!("alice"));
+        send(&mut socket, call(3, "subscribe", json!({"channel": "orders"}))).await;
+        send(&mut socket, call(4, "subscribe", json!({"channel": "trades", "symbol": "BTC/USDT"}))).await;
+        send(&mut socket, call(5, "subscribe", json!({"channel": "ticker"}))).await;
+        let snapshot = send(&mut socket, call(6, "subscribe", json!({"channel": "depth", "symbol": "BTC/USDT"}))).await;
+        assert_eq!(snapshot["result"]["sequence"], json!(0));
+
+        // Bob's resting bid shows in depth but not in Alice's order stream
+        post(&address, Some("operator-token"), call(7, "dex.deposit", json!({"user": "bob", "currency": "USDT", "amount": "100000"}))).await;
+        post(&address, Some("bob-token"), call(8, "dex.place_order", json!({
+            "symbol": "BTC/USDT", "side": "Buy", "order_type": "Limit", "quantity": "2", "price": "30000"
+        }))).await;
+        let depth = receive(&mut socket).await;
+        assert_eq!(depth["method"], json!("depth"));
+        assert_eq!(depth["params"]["quantity"], json!("2"));
+        assert_eq!(depth["params"]["sequence"], json!(1));
+        let ticker = receive(&mut socket).await;
+        assert_eq!(ticker["params"]["best_bid"], json!("30000"));
+
+        // Alice trades over the socket itself
+        post(&address, Some("operator-token"), call(9, "dex.deposit", json!({"user": "alice", "currency": "BTC", "amount": "1"}))).await;
+        socket.send(tungstenite::Message::text(call(10, "dex.place_order", json!({
+            "symbol": "BTC/USDT", "side": "Sell", "order_type": "Market", "quantity": "1"
+        })).to_string())).await.unwrap();
+        let mut messages = Vec::new();
+        for _ in 0..5 {
+            messages.push(receive(&mut socket).await);
+        }
+        let by_method = |method: &str| messages.iter().filter(|m| m["method"] == json!(method)).cloned().collect::<Vec<Value>>();
+
+        assert_eq!(by_method("trades").len(), 1);
+        assert_eq!(by_method("trades")[0]["params"]["buyer"], json!("bob"));
+        assert_eq!(by_method("depth")[0]["params"]["quantity"], json!("1"));
+        assert_eq!(by_method("ticker")[0]["params"]["last"], json!("30000"));
+        let orders = by_method("orders");
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0]["params"]["trader"], json!("alice"));
+        assert_eq!(orders[0]["params"]["status"], json!("Filled"));
+        let reply = messages.iter().find(|m| m["id"] == json!(10)).unwrap();
+        assert_eq!(reply["result"]["status"], json!("Filled"));
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::BTreeMap;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+use crate::dex_engine::{DEXEngine, DepthSnapshot, Trade};
+use crate::journal::EngineCommand;
+
+/// One recorded command and the time it reached the venue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestEvent {
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub command: EngineCommand,
+}
+
+/// Reads one JSON event per line, in the journal's command format, e.g.
+/// `{"timestamp":"2024-03-01T10:00:00Z","command":"deposit","user":"alice","currency":"USDC","amount":"1000"}`.
+pub fn parse_json_lines(input: &str) -> Result<Vec<BacktestEvent>, String> {
+    input.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|e| format!("Line {}: {}", index + 1, e))
+        })
+        .collect()
+}
+
+/// Reads unquoted CSV whose header names the same fields as the JSON format. Empty cells are left
+/// out, so one file can mix commands, e.g. `timestamp,command,trader,symbol,side,order_type,quantity,price`.
+pub fn parse_csv(input: &str) -> Result<Vec<BacktestEvent>, String> {
+    let mut lines = input.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let columns: Vec<&str> = match lines.next() {
+        Some((_, header)) => header.split(',').map(str::trim).collect(),
+        None => return Ok(Vec::new()),
+    };
+
+    lines.map(|(index, line)| {
+        let cells: Vec<&str> = line.split(',').map(str::trim).collect();
+        if cells.len() != columns.len() {
+            return Err(format!("Line {}: expected {} fields, found {}", index + 1, columns.len(), cells.len()));
+        }
+        let fields: serde_json::Map<String, serde_json::Value> = columns.iter()
+            .zip(cells)
+            .filter(|(_, cell)| !cell.is_empty())
+            .map(|(column, cell)| (column.to_string(), serde_json::Value::String(cell.to_string())))
+            .collect();
+        serde_json::from_value(serde_json::Value::Object(fields)).map_err(|e| format!("Line {}: {}", index + 1, e))
+    }).collect()
+}
+
+/// Visible depth of every symbol as of a chosen time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookSnapshot {
+    pub timestamp: DateTime<Utc>,
+    pub books: Vec<DepthSnapshot>,
+}
+
+/// A trader's result on one symbol, in the quote currency. Realized P&L uses the average cost of
+/// the open position; the rest is marked at the symbol's last trade price.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraderPnl {
+    pub trader: String,
+    pub symbol: String,
+    pub position: Decimal, // Net base quantity, before fees
+    pub average_price: Decimal,
+    pub mark_price: Decimal,
+    pub realized_pnl: Decimal,
+    pub unrealized_pnl: Decimal,
+    pub fees: Decimal, // Base fees valued at the trade price
+    pub net_pnl: Decimal,
+}
+
+impl TraderPnl {
+    fn new(trader: &str, symbol: &str) -> Self {
+        Self {
+            trader: trader.to_string(),
+            symbol: symbol.to_string(),
+            position: Decimal::ZERO,
+            average_price: Decimal::ZERO,
+            mark_price: Decimal::ZERO,
+            realized_pnl: Decimal::ZERO,
+            unrealized_pnl: Decimal::ZERO,
+            fees: Decimal::ZERO,
+            net_pnl: Decimal::ZERO,
+        }
+    }
+
+    /// Books a fill of `quantity` (negative for sells) at `price`.
+    fn add_fill(&mut self, quantity: Decimal, price: Decimal, fee: Decimal) {
+        self.fees += fee;
+        if self.position.is_zero() || self.position.is_sign_positive() == quantity.is_sign_positive() {
+            let size = self.position.abs() + quantity.abs();
+            self.average_price = (self.average_price * self.position.abs() + price * quantity.abs()) / size;
+            self.position += quantity;
+            return;
+        }
+
+        let closed = quantity.abs().min(self.position.abs());
+        let gain = if self.position.is_sign_positive() { price - self.average_price } else { self.average_price - price };
+        self.realized_pnl += closed * gain;
+        self.position += quantity;
+        if self.position.is_zero() {
+            self.average_price = Decimal::ZERO;
+        } else if self.position.is_sign_positive() == quantity.is_sign_positive() {
+            // Flipped through zero; the rest of the fill opened the new position
+            self.average_price = price;
+        }
+    }
+
+    fn mark(&mut self, mark_price: Decimal) {
+        self.mark_price = mark_price;
+        self.unrealized_pnl = self.position * (mark_price - self.average_price);
+        self.net_pnl = self.realized_pnl + self.unrealized_pnl - self.fees;
+    }
+}
+
+/// A recorded command the engine turned down; replays reject it the same way.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rejection {
+    pub event: usize, // 1-based position in the input
+    pub timestamp: DateTime<Utc>,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestResult {
+    pub trades: Vec<Trade>,
+    pub snapshots: Vec<BookSnapshot>,
+    pub pnl: Vec<TraderPnl>, // Sorted by trader, then symbol
+    pub rejections: Vec<Rejection>,
+}
+
+/// Replays order flow through a `DEXEngine` on a simulated clock. The clock jumps to each event's
+/// timestamp, expiring due orders first, so the same input always produces the same output.
+pub struct Backtest {
+    engine: DEXEngine,
+    snapshot_times: Vec<DateTime<Utc>>,
+    snapshot_depth: usize,
+}
+
+impl Backtest {
+    pub fn new(mut snapshot_times: Vec<DateTime<Utc>>, snapshot_depth: usize) -> Self {
+        snapshot_times.sort();
+        Self { engine: DEXEngine::new(), snapshot_times, snapshot_depth }
+    }
+
+    pub fn engine(&self) -> &DEXEngine {
+        &self.engine
+    }
+
+    /// Runs the events on top of the engine's current state. Events must be in time order. A
+    /// snapshot taken at an event's timestamp includes that event.
+    pub fn run(&mut self, events: &[BacktestEvent]) -> Result<BacktestResult, String> {
+        let first_trade = self.engine.get_trades().len();
+        let mut snapshot_times = self.snapshot_times.clone().into_iter().peekable();
+        let mut snapshots = Vec::new();
+        let mut rejections = Vec::new();
+        let mut clock: Option<DateTime<Utc>> = None;
+
+        for (index, event) in events.iter().enumerate() {
+            if clock.is_some_and(|clock| event.timestamp < clock) {
+                return Err(format!("Event {} at {} is earlier than the event before it", index + 1, event.timestamp));
+            }
+            while let Some(timestamp) = snapshot_times.next_if(|timestamp| *timestamp < event.timestamp) {
+                self.advance_to(timestamp);
+                snapshots.push(self.book_snapshot(timestamp));
+            }
+            if clock != Some(event.timestamp) {
+                self.advance_to(event.timestamp);
+                clock = Some(event.timestamp);
+            }
+
+            if let Err(error) = event.command.apply(&mut self.engine) {
+                rejections.push(Rejection { event: index + 1, timestamp: event.timestamp, error });
+            }
+        }
+        for timestamp in snapshot_times {
+            self.advance_to(timestamp);
+            snapshots.push(self.book_snapshot(timestamp));
+        }
+
+        let trades = self.engine.get_trades()[first_trade..].to_vec();
+        let pnl = trader_pnl(&trades);
+        Ok(BacktestResult { trades, snapshots, pnl, rejections })
+    }
+
+    fn advance_to(&mut self, timestamp: DateTime<Utc>) {
+        self.engine.set_clock(Some(timestamp));
+        self.engine.process_pending_orders();
+    }
+
+    fn book_snapshot(&self, timestamp: DateTime<Utc>) -> BookSnapshot {
+        let books = self.engine.get_symbol_specs().iter()
+            .filter_map(|spec| self.engine.get_depth_snapshot(&spec.symbol, self.snapshot_depth))
+            .collect();
+        BookSnapshot { timestamp, books }
+    }
+}
+
+fn trader_pnl(trades: &[Trade]) -> Vec<TraderPnl> {
+    let mut pnl: BTreeMap<(String, String), TraderPnl> = BTreeMap::new();
+    let mut marks = BTreeMap::new();
+    for trade in trades {
+        let fills = [
+            (&trade.buyer, trade.quantity, trade.buyer_fee * trade.price),
+            (&trade.seller, -trade.quantity, trade.seller_fee),
+        ];
+        for (trader, quantity, fee) in fills {
+            pnl.entry((trader.clone(), trade.symbol.clone()))
+                .or_insert_with(|| TraderPnl::new(trader, &trade.symbol))
+                .add_fill(quantity, trade.price, fee);
+        }
+        marks.insert(trade.symbol.clone(), trade.price);
+    }
+
+    pnl.into_values()
+        .map(|mut trader_pnl| {
+            trader_pnl.mark(marks[&trader_pnl.symbol]);
+            trader_pnl
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDER_FLOW: &str = r#"
+{"timestamp":"2024-03-01T10:00:00Z","command":"add_symbol","symbol":"ETH/USDC"}
+{"timestamp":"2024-03-01T10:00:00Z","command":"deposit","user":"maker","currency":"ETH","amount":"10"}
+{"timestamp":"2024-03-01T10:00:00Z","command":"deposit","user":"taker","currency":"USDC","amount":"20000"}
+{"timestamp":"2024-03-01T10:00:01Z","command":"place_order","trader":"maker","symbol":"ETH/USDC","side":"Sell","order_type":"Limit","quantity":"2","price":"2000","stop_price":null,"time_in_force":"GTC","expire_at":null}
+{"timestamp":"2024-03-01T10:00:02Z","command":"place_order","trader":"maker","symbol":"ETH/USDC","side":"Sell","order_type":"Limit","quantity":"1","price":"2100","stop_price":null,"time_in_force":"GTD","expire_at":"2024-03-01T10:00:30Z"}
+{"timestamp":"2024-03-01T10:00:05Z","command":"place_order","trader":"taker","symbol":"ETH/USDC","side":"Buy","order_type":"Market","quantity":"2","price":null,"stop_price":null,"time_in_force":"GTC","expire_at":null}
+{"timestamp":"2024-03-01T10:00:10Z","command":"place_order","trader":"taker","symbol":"ETH/USDC","side":"Sell","order_type":"Limit","quantity":"1","price":"2050","stop_price":null,"time_in_force":"GTC","expire_at":null}
+{"timestamp":"2024-03-01T10:00:20Z","command":"place_order","trader":"maker","symbol":"ETH/USDC","side":"Buy","order_type":"Limit","quantity":"1","price":"2050","stop_price":null,"time_in_force":"GTC","expire_at":null}
+{"timestamp":"2024-03-01T10:00:40Z","command":"withdraw","user":"taker","currency":"USDC","amount":"100000"}
+"#;
+
+    fn run(events: &[BacktestEvent]) -> BacktestResult {
+        let snapshot_times = vec![
+            "2024-03-01T10:00:02Z".parse().unwrap(),
+            "2024-03-01T10:01:00Z".parse().unwrap(),
+        ];
+        Backtest::new(snapshot_times, 5).run(events).unwrap()
+    }
+
+    #[test]
+    fn test_backtest_is_reproducible() {
+        let events = parse_json_lines(ORDER_FLOW).unwrap();
+        let result = run(&events);
+        assert_eq!(serde_json::to_value(&result).unwrap(), serde_json::to_value(run(&events)).unwrap());
+
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].timestamp, "2024-03-01T10:00:05Z".parse::<DateTime<Utc>>().unwrap());
+        assert_eq!(result.trades[1].price, Decimal::new(2050, 0));
+
+        // Both asks rest at 10:00:02; the GTD ask has expired by the last snapshot
+        assert_eq!(result.snapshots[0].books[0].asks.len(), 2);
+        assert!(result.snapshots[1].books[0].asks.is_empty());
+
+        let taker = result.pnl.iter().find(|pnl| pnl.trader == "taker").unwrap();
+        assert_eq!((taker.position, taker.average_price), (Decimal::new(1, 0), Decimal::new(2000, 0)));
+        assert_eq!(taker.realized_pnl, Decimal::new(50, 0));
+        assert_eq!(taker.unrealized_pnl, Decimal::new(50, 0));
+        let maker = result.pnl.iter().find(|pnl| pnl.trader == "maker").unwrap();
+        assert_eq!((maker.position, maker.realized_pnl, maker.net_pnl),
+                   (Decimal::new(-1, 0), Decimal::new(-50, 0), Decimal::new(-100, 0)));
+
+        assert_eq!(result.rejections.len(), 1);
+        assert_eq!((result.rejections[0].event, result.rejections[0].error.as_str()), (9, "Insufficient balance"));
+    }
+
+    #[test]
+    fn test_backtest_reads_csv() {
+        let csv = "\
+timestamp,command,user,currency,amount,trader,symbol,side,order_type,quantity,price,time_in_force
+2024-03-01T10:00:00Z,add_symbol,,,,,ETH/USDC,,,,,
+2024-03-01T10:00:00Z,deposit,maker,ETH,10,,,,,,,
+2024-03-01T10:00:00Z,deposit,taker,USDC,20000,,,,,,,
+2024-03-01T10:00:01Z,place_order,,,,maker,ETH/USDC,Sell,Limit,2,2000,GTC
+2024-03-01T10:00:05Z,place_order,,,,taker,ETH/USDC,Buy,Limit,1,2000,IOC
+";
+        let events = parse_csv(csv).unwrap();
+        let result = Backtest::new(Vec::new(), 5).run(&events).unwrap();
+        assert!(result.rejections.is_empty());
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].buyer, "taker");
+
+        let out_of_order = parse_json_lines(&ORDER_FLOW.lines().rev().collect::<Vec<_>>().join("\n")).unwrap();
+        assert!(Backtest::new(Vec::new(), 5).run(&out_of_order).is_err());
+        assert!(parse_csv("timestamp,command\n2024-03-01T10:00:00Z").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::{HashMap, BTreeMap};
+use std::str::FromStr;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use crate::dex_engine::Trade;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CandleInterval {
+    OneSecond,
+    OneMinute,
+    FiveMinutes,
+    FifteenMinutes,
+    OneHour,
+    FourHours,
+    OneDay,
+}
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 7] = [
+        CandleInterval::OneSecond,
+        CandleInterval::OneMinute,
+        CandleInterval::FiveMinutes,
+        CandleInterval::FifteenMinutes,
+        CandleInterval::OneHour,
+        CandleInterval::FourHours,
+        CandleInterval::OneDay,
+    ];
+
+    pub fn seconds(&self) -> i64 {
+        match self {
+            CandleInterval::OneSecond => 1,
+            CandleInterval::OneMinute => 60,
+            CandleInterval::FiveMinutes => 5 * 60,
+            CandleInterval::FifteenMinutes => 15 * 60,
+            CandleInterval::OneHour => 60 * 60,
+            CandleInterval::FourHours => 4 * 60 * 60,
+            CandleInterval::OneDay => 24 * 60 * 60,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandleInterval::OneSecond => "1s",
+            CandleInterval::OneMinute => "1m",
+            CandleInterval::FiveMinutes => "5m",
+            CandleInterval::FifteenMinutes => "15m",
+            CandleInterval::OneHour => "1h",
+            CandleInterval::FourHours => "4h",
+            CandleInterval::OneDay => "1d",
+        }
+    }
+
+    /// Start of the bar containing `timestamp`. Bars are aligned to the Unix epoch, so daily bars
+    /// open at midnight UTC.
+    pub fn bar_start(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        let seconds = timestamp.timestamp();
+        let start = seconds - seconds.rem_euclid(self.seconds());
+        Utc.timestamp_opt(start, 0).unwrap()
+    }
+}
+
+impl FromStr for CandleInterval {
+    type Err = String;
+
+    /// Parses the interval names the klines API uses, e.g. "15m".
+    fn from_str(interval: &str) -> Result<Self, Self::Err> {
+        CandleInterval::ALL.iter()
+            .find(|candidate| candidate.as_str() == interval)
+            .copied()
+            .ok_or_else(|| format!("Unsupported candle interval: {}", interval))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Candle {
+    pub symbol: String,
+    pub interval: CandleInterval,
+    pub open_time: DateTime<Utc>,
+    pub close_time: DateTime<Utc>, // Start of the next bar
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,       // Base quantity traded
+    pub quote_volume: Decimal, // Sum of price * quantity
+    pub trade_count: u64,
+    pub vwap: Decimal,
+}
+
+impl Candle {
+    fn new(trade: &Trade, interval: CandleInterval) -> Self {
+        let open_time = interval.bar_start(trade.timestamp);
+        Self {
+            symbol: trade.symbol.clone(),
+            interval,
+            open_time,
+            close_time: open_time + Duration::seconds(interval.seconds()),
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: Decimal::ZERO,
+            quote_volume: Decimal::ZERO,
+            trade_count: 0,
+            vwap: trade.price,
+        }
+    }
+
+    fn add_trade(&mut self, trade: &Trade) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += trade.quantity;
+        self.quote_volume += trade.price * trade.quantity;
+        self.trade_count += 1;
+        if self.volume > Decimal::ZERO {
+            self.vwap = self.quote_volume / self.volume;
+        }
+    }
+}
+
+/// OHLCV bars for every symbol and interval, updated one trade at a time. Trades are expected in
+/// the order they happened; intervals without trades have no bar.
+#[derive(Debug, Clone, Default)]
+pub struct CandleAggregator {
+    candles: HashMap<(String, CandleInterval), BTreeMap<DateTime<Utc>, Candle>>,
+}
+
+impl CandleAggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_trades(trades: &[Trade]) -> Self {
+        let mut aggregator = Self::new();
+        aggregator.rebuild(trades);
+        aggregator
+    }
+
+    pub fn add_trade(&mut self, trade: &Trade) {
+        for interval in CandleInterval::ALL {
+            self.candles
+                .entry((trade.symbol.clone(), interval))
+                .or_default()
+                .entry(interval.bar_start(trade.timestamp))
+                .or_insert_with(|| Candle::new(trade, interval))
+                .add_trade(trade);
+        }
+    }
+
+    /// Throws away every bar and builds them again from the given trade history.
+    pub fn rebuild(&mut self, trades: &[Trade]) {
+        self.candles.clear();
+        for trade in trades {
+            self.add_trade(trade);
+        }
+    }
+
+    /// Bars opening in `[start, end)`, oldest first.
+    pub fn get_candles(&self, symbol: &str, interval: CandleInterval, start: DateTime<Utc>,
+                       end: DateTime<Utc>) -> Vec<Candle> {
+        if start >= end {
+            return Vec::new();
+        }
+
+        self.candles
+            .get(&(symbol.to_string(), interval))
+            .map(|candles| candles.range(start..end).map(|(_, candle)| candle.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get_latest_candle(&self, symbol: &str, interval: CandleInterval) -> Option<Candle> {
+        self.candles
+            .get(&(symbol.to_string(), interval))
+            .and_then(|candles| candles.values().next_back())
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex_engine::{DEXEngine, OrderRequest, OrderSide, OrderType};
+
+    fn trade(price: i64, quantity: i64, timestamp: &str) -> Trade {
+        Trade {
+            id: format!("trade_{}", timestamp),
+            symbol: "ETH/USDC".to_string(),
+            price: Decimal::new(price, 0),
+            quantity: Decimal::new(quantity, 0),
+            buy_order_id: "order_1".to_string(),
+            sell_order_id: "order_2".to_string(),
+            buyer: "trader1".to_string(),
+            seller: "trader2".to_string(),
+            timestamp: timestamp.parse().unwrap(),
+            trade_type: "limit".to_string(),
+            taker_side: OrderSide::Buy,
+            buyer_fee: Decimal::ZERO,
+            seller_fee: Decimal::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_candles_aggregate_trades_per_interval() {
+        let trades = vec![
+            trade(2000, 1, "2024-03-01T10:00:05Z"),
+            trade(2030, 2, "2024-03-01T10:00:40Z"),
+            trade(1990, 1, "2024-03-01T10:01:10Z"),
+            trade(2010, 1, "2024-03-01T10:04:59Z"),
+        ];
+        let mut aggregator = CandleAggregator::new();
+        for trade in &trades {
+            aggregator.add_trade(trade);
+        }
+
+        let start = "2024-03-01T10:00:00Z".parse().unwrap();
+        let end = "2024-03-01T10:05:00Z".parse().unwrap();
+        let minutes = aggregator.get_candles("ETH/USDC", CandleInterval::OneMinute, start, end);
+        assert_eq!(minutes.len(), 3);
+        assert_eq!((minutes[0].open, minutes[0].high, minutes[0].low, minutes[0].close),
+                   (Decimal::new(2000, 0), Decimal::new(2030, 0), Decimal::new(2000, 0), Decimal::new(2030, 0)));
+        assert_eq!(minutes[0].volume, Decimal::new(3, 0));
+        assert_eq!(minutes[0].trade_count, 2);
+        assert_eq!(minutes[0].vwap, Decimal::new(2020, 0));
+        assert_eq!(minutes[2].open_time, "2024-03-01T10:04:00Z".parse::<DateTime<Utc>>().unwrap());
+
+        let five_minutes = aggregator.get_candles("ETH/USDC", CandleInterval::FiveMinutes, start, end);
+        assert_eq!(five_minutes.len(), 1);
+        assert_eq!(five_minutes[0].quote_volume, Decimal::new(10060, 0));
+        assert_eq!(five_minutes[0].close, Decimal::new(2010, 0));
+        assert_eq!(five_minutes[0].close_time, end);
+
+        // The range end is exclusive, and rebuilding from history gives the same bars
+        let first_minute = aggregator.get_candles("ETH/USDC", CandleInterval::OneMinute, start,
+                                                  "2024-03-01T10:01:00Z".parse().unwrap());
+        assert_eq!(first_minute.len(), 1);
+        let rebuilt = CandleAggregator::from_trades(&trades);
+        assert_eq!(rebuilt.get_candles("ETH/USDC", CandleInterval::OneMinute, start, end), minutes);
+        assert_eq!("4h".parse::<CandleInterval>(), Ok(CandleInterval::FourHours));
+        assert!("3m".parse::<CandleInterval>().is_err());
+    }
+
+    #[test]
+    fn test_engine_trades_feed_candles() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        dex.deposit("trader1", "ETH", Decimal::new(10, 0));
+        dex.deposit("trader2", "USDC", Decimal::new(100000, 0));
+
+        for (quantity, price) in [(1, 2000), (2, 2100)] {
+            dex.place_order(OrderRequest::new(
+                "trader1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(quantity, 0),
+                Some(Decimal::new(price, 0)),
+                None,
+            )).unwrap();
+        }
+        dex.place_order(OrderRequest::new(
+            "trader2".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Market,
+            Decimal::new(3, 0),
+            None,
+            None,
+        )).unwrap();
+
+        let start = Utc::now() - Duration::days(1);
+        let end = Utc::now() + Duration::days(1);
+        let days = dex.get_candles("ETH/USDC", CandleInterval::OneDay, start, end);
+        let volume: Decimal = days.iter().map(|candle| candle.volume).sum();
+        let trade_count: u64 = days.iter().map(|candle| candle.trade_count).sum();
+        assert_eq!(volume, Decimal::new(3, 0));
+        assert_eq!(trade_count, 2);
+
+        let latest = dex.get_latest_candle("ETH/USDC", CandleInterval::OneSecond).unwrap();
+        dex.rebuild_candles();
+        assert_eq!(dex.get_latest_candle("ETH/USDC", CandleInterval::OneSecond), Some(latest));
+    }
+}
@@ -1,6 +1,4 @@
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 use rust_decimal::Decimal;
 use rust_decimal::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -38,7 +36,7 @@ pub enum OrderSide {
     Sell,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum OrderStatus {
     Pending,
     Partial,
@@ -81,7 +79,12 @@ pub struct DeFiProtocol {
     orders: HashMap<String, Order>,
     trades: Vec<Trade>,
     order_counter: u64,
-    trade_counter: u64,
+}
+
+impl Default for DeFiProtocol {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DeFiProtocol {
@@ -93,7 +96,6 @@ impl DeFiProtocol {
             orders: HashMap::new(),
             trades: Vec::new(),
             order_counter: 0,
-            trade_counter: 0,
         }
     }
 
@@ -123,11 +125,10 @@ impl DeFiProtocol {
     }
 
     pub fn add_liquidity(&mut self, pool_id: &str, user: &str, amount_a: Decimal, amount_b: Decimal) -> Result<Decimal, String> {
-        let pool = self.pools.get_mut(pool_id)
-            .ok_or_else(|| "Pool not found".to_string())?;
-
-        let user_balance_a = self.get_user_balance(user, &pool.token_a);
-        let user_balance_b = self.get_user_balance(user, &pool.token_b);
+        let (token_a, token_b) = self.pool_tokens(pool_id)?;
+        let user_balance_a = self.get_user_balance(user, &token_a);
+        let user_balance_b = self.get_user_balance(user, &token_b);
+        let pool = self.pools.get_mut(pool_id).unwrap();
 
         if user_balance_a < amount_a || user_balance_b < amount_b {
             return Err("Insufficient balance".to_string());
@@ -146,8 +147,8 @@ impl DeFiProtocol {
         pool.total_liquidity += liquidity_minted;
         pool.k_constant = pool.reserve_a * pool.reserve_b;
 
-        self.update_balance(user, &pool.token_a, user_balance_a - amount_a);
-        self.update_balance(user, &pool.token_b, user_balance_b - amount_b);
+        self.update_balance(user, &token_a, user_balance_a - amount_a);
+        self.update_balance(user, &token_b, user_balance_b - amount_b);
 
         let share = PoolShare {
             user: user.to_string(),
@@ -196,15 +197,22 @@ impl DeFiProtocol {
             shares.remove(user_share_index);
         }
 
-        let user_balance_a = self.get_user_balance(user, &pool.token_a);
-        let user_balance_b = self.get_user_balance(user, &pool.token_b);
+        let (token_a, token_b) = (pool.token_a.clone(), pool.token_b.clone());
+        let user_balance_a = self.get_user_balance(user, &token_a);
+        let user_balance_b = self.get_user_balance(user, &token_b);
 
-        self.update_balance(user, &pool.token_a, user_balance_a + token_a_amount);
-        self.update_balance(user, &pool.token_b, user_balance_b + token_b_amount);
+        self.update_balance(user, &token_a, user_balance_a + token_a_amount);
+        self.update_balance(user, &token_b, user_balance_b + token_b_amount);
 
         Ok((token_a_amount, token_b_amount))
     }
 
+    fn pool_tokens(&self, pool_id: &str) -> Result<(String, String), String> {
+        self.pools.get(pool_id)
+            .map(|pool| (pool.token_a.clone(), pool.token_b.clone()))
+            .ok_or_else(|| "Pool not found".to_string())
+    }
+
     pub fn get_amount_out(&self, pool_id: &str, amount_in: Decimal, token_in: &str) -> Result<Decimal, String> {
         let pool = self.pools.get(pool_id)
             .ok_or_else(|| "Pool not found".to_string())?;
@@ -231,18 +239,17 @@ impl DeFiProtocol {
     pub fn swap(&mut self, pool_id: &str, user: &str, amount_in: Decimal, token_in: &str) -> Result<Decimal, String> {
         let amount_out = self.get_amount_out(pool_id, amount_in, token_in)?;
 
-        let pool = self.pools.get_mut(pool_id)
-            .ok_or_else(|| "Pool not found".to_string())?;
-
         let user_balance_in = self.get_user_balance(user, token_in);
         if user_balance_in < amount_in {
             return Err("Insufficient balance".to_string());
         }
 
+        let pool = self.pools.get_mut(pool_id)
+            .ok_or_else(|| "Pool not found".to_string())?;
         let (token_out, reserve_in, reserve_out) = if token_in == pool.token_a {
-            (&pool.token_b, &mut pool.reserve_a, &mut pool.reserve_b)
+            (pool.token_b.clone(), &mut pool.reserve_a, &mut pool.reserve_b)
         } else {
-            (&pool.token_a, &mut pool.reserve_b, &mut pool.reserve_a)
+            (pool.token_a.clone(), &mut pool.reserve_b, &mut pool.reserve_a)
         };
 
         *reserve_in += amount_in;
@@ -250,8 +257,8 @@ impl DeFiProtocol {
         pool.k_constant = pool.reserve_a * pool.reserve_b;
 
         self.update_balance(user, token_in, user_balance_in - amount_in);
-        let user_balance_out = self.get_user_balance(user, token_out);
-        self.update_balance(user, token_out, user_balance_out + amount_out);
+        let user_balance_out = self.get_user_balance(user, &token_out);
+        self.update_balance(user, &token_out, user_balance_out + amount_out);
 
         Ok(amount_out)
     }
@@ -264,7 +271,7 @@ impl DeFiProtocol {
         let order = Order {
             id: order_id.clone(),
             trader: trader.to_string(),
-            order_type,
+            order_type: order_type.clone(),
             side,
             symbol: symbol.to_string(),
             amount,
@@ -339,7 +346,7 @@ impl DeFiProtocol {
     pub fn update_balance(&mut self, user: &str, token: &str, amount: Decimal) {
         self.user_balances
             .entry(user.to_string())
-            .or_insert_with(HashMap::new)
+            .or_default()
             .insert(token.to_string(), amount);
     }
 
@@ -362,7 +369,7 @@ impl DeFiProtocol {
         Ok(pool.reserve_a + pool.reserve_b)
     }
 
-    pub fn calculate_impermanent_loss(&self, pool_id: &str, initial_ratio: Decimal, current_ratio: Decimal) -> Result<Decimal, String> {
+    pub fn calculate_impermanent_loss(&self, pool_id: &str, initial_ratio: Decimal, _current_ratio: Decimal) -> Result<Decimal, String> {
         let pool = self.get_pool_info(pool_id)?;
 
         if pool.reserve_a == Decimal::ZERO || pool.reserve_b == Decimal::ZERO {
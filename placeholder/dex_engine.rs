@@ -1,8 +1,12 @@
-use std::collections::{HashMap, BTreeMap, VecDeque};
+use std::collections::{HashMap, BTreeMap, BTreeSet, VecDeque};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::cmp::Ordering;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+
+use crate::candles::{Candle, CandleAggregator, CandleInterval};
+use crate::ledger::Ledger;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum OrderSide {
@@ -10,17 +14,37 @@ pub enum OrderSide {
     Sell,
 }
 
+impl OrderSide {
+    pub fn opposite(&self) -> OrderSide {
+        match self {
+            OrderSide::Buy => OrderSide::Sell,
+            OrderSide::Sell => OrderSide::Buy,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum OrderType {
     Limit,
     Market,
     Stop,
     StopLimit,
+    TrailingStop,
+    TrailingStopLimit,
+}
+
+impl OrderType {
+    /// Whether the order trades as a limit order once it is live.
+    pub fn has_limit_price(&self) -> bool {
+        matches!(self, OrderType::Limit | OrderType::StopLimit | OrderType::TrailingStopLimit)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum OrderStatus {
+    Inactive, // Bracket exit waiting for its entry order to fill
     Pending,
+    Triggered,
     Partial,
     Filled,
     Cancelled,
@@ -35,6 +59,127 @@ pub enum TimeInForce {
     GTD, // Good Till Date
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PostOnly {
+    Reject, // Reject the order if it would take liquidity
+    Slide,  // Reprice it one tick behind the opposite best price instead
+}
+
+/// What happens when an order would trade against another order from the same trader.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SelfTradePrevention {
+    CancelNewest,       // Cancel the incoming order
+    CancelOldest,       // Cancel the resting order and keep matching
+    CancelBoth,
+    DecrementAndCancel, // Take the overlap off both orders, cancelling whichever is used up
+}
+
+/// Execution instructions on top of the order type and time in force.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ExecInstructions {
+    pub post_only: Option<PostOnly>,
+    pub reduce_only: bool,
+    pub hidden: bool,
+    pub display_quantity: Option<Decimal>, // Iceberg slice size; the rest of the order stays in reserve
+    pub self_trade_prevention: Option<SelfTradePrevention>, // Overrides the trader's own setting
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TrailingOffset {
+    Amount(Decimal),
+    Percent(Decimal), // e.g. 5 for a trail of 5% of the best price
+}
+
+/// Trailing state of a trailing stop order. Its current trigger level is the order's `stop_price`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrailingStop {
+    pub offset: TrailingOffset,
+    pub limit_offset: Option<Decimal>, // Distance of the limit price beyond the trigger level for trailing stop-limits
+    pub best_price: Decimal,           // Highest trade price since placement for sells, lowest for buys
+}
+
+impl TrailingStop {
+    /// Trigger level for an order on `side` trailing the best price.
+    pub fn stop_price(&self, side: &OrderSide) -> Decimal {
+        let distance = match self.offset {
+            TrailingOffset::Amount(amount) => amount,
+            TrailingOffset::Percent(percent) => self.best_price * percent / Decimal::ONE_HUNDRED,
+        };
+
+        match side {
+            OrderSide::Buy => self.best_price + distance,
+            OrderSide::Sell => self.best_price - distance,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderStatusChange {
+    pub status: OrderStatus,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderAmendment {
+    pub old_quantity: Decimal,
+    pub new_quantity: Decimal,
+    pub old_price: Option<Decimal>,
+    pub new_price: Option<Decimal>,
+    pub kept_priority: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fill {
+    pub trade_id: String,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub liquidity: Liquidity,
+    pub fee: Decimal, // Charged in the asset the order received
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A new order as the trader submits it. Good till cancelled with no instructions unless the
+/// fields say otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderRequest {
+    pub trader: String,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+    pub quantity: Decimal,
+    pub price: Option<Decimal>,
+    pub stop_price: Option<Decimal>,
+    pub time_in_force: TimeInForce,
+    pub expire_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub instructions: ExecInstructions,
+}
+
+impl OrderRequest {
+    pub fn new(trader: String, symbol: String, side: OrderSide, order_type: OrderType, quantity: Decimal,
+               price: Option<Decimal>, stop_price: Option<Decimal>) -> Self {
+        Self {
+            trader,
+            symbol,
+            side,
+            order_type,
+            quantity,
+            price,
+            stop_price,
+            time_in_force: TimeInForce::GTC,
+            expire_at: None,
+            instructions: ExecInstructions::default(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
     pub id: String,
@@ -52,61 +197,153 @@ pub struct Order {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub expire_at: Option<DateTime<Utc>>,
+    pub status_history: Vec<OrderStatusChange>,
+    pub fills: Vec<Fill>,
+    pub locked_amount: Decimal,
+    pub amend_history: Vec<OrderAmendment>,
+    pub instructions: ExecInstructions,
+    pub display_remaining: Decimal,
+    pub trailing: Option<TrailingStop>,
+    pub group_id: Option<String>,
+    pub self_trade_prevented: Decimal, // Quantity cancelled or decremented to stop a self-trade
 }
 
 impl Order {
-    pub fn new(
-        id: String,
-        trader: String,
-        symbol: String,
-        side: OrderSide,
-        order_type: OrderType,
-        quantity: Decimal,
-        price: Option<Decimal>,
-        stop_price: Option<Decimal>,
-        time_in_force: TimeInForce,
-        expire_at: Option<DateTime<Utc>>,
-    ) -> Self {
+    pub fn new(id: String, request: OrderRequest, now: DateTime<Utc>) -> Self {
         Self {
             id,
-            trader,
-            symbol,
-            side,
-            order_type,
-            quantity,
-            price,
-            stop_price,
+            trader: request.trader,
+            symbol: request.symbol,
+            side: request.side,
+            order_type: request.order_type,
+            quantity: request.quantity,
+            price: request.price,
+            stop_price: request.stop_price,
             filled_quantity: Decimal::ZERO,
-            remaining_quantity: quantity,
+            remaining_quantity: request.quantity,
             status: OrderStatus::Pending,
-            time_in_force,
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
-            expire_at,
+            time_in_force: request.time_in_force,
+            created_at: now,
+            updated_at: now,
+            expire_at: request.expire_at,
+            status_history: vec![OrderStatusChange { status: OrderStatus::Pending, timestamp: now }],
+            fills: Vec::new(),
+            locked_amount: Decimal::ZERO,
+            amend_history: Vec::new(),
+            instructions: request.instructions,
+            display_remaining: Decimal::ZERO,
+            trailing: None,
+            group_id: None,
+            self_trade_prevented: Decimal::ZERO,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        matches!(self.status, OrderStatus::Inactive | OrderStatus::Pending | OrderStatus::Triggered | OrderStatus::Partial)
+    }
+
+    pub fn is_stop(&self) -> bool {
+        matches!(self.order_type, OrderType::Stop | OrderType::StopLimit | OrderType::TrailingStop | OrderType::TrailingStopLimit)
+    }
+
+    /// Quantity shown in market depth while the order rests.
+    pub fn displayed_quantity(&self) -> Decimal {
+        if self.instructions.hidden {
+            Decimal::ZERO
+        } else if self.is_iceberg() {
+            self.display_remaining.min(self.remaining_quantity)
+        } else {
+            self.remaining_quantity
+        }
+    }
+
+    pub fn is_iceberg(&self) -> bool {
+        self.instructions.display_quantity.is_some()
+    }
+
+    /// Shows a fresh iceberg slice from the reserve.
+    pub fn refresh_display(&mut self) {
+        if let Some(display_quantity) = self.instructions.display_quantity {
+            self.display_remaining = display_quantity.min(self.remaining_quantity);
+        }
+    }
+
+    pub fn set_status(&mut self, status: OrderStatus, now: DateTime<Utc>) {
+        self.updated_at = now;
+        if self.status != status {
+            self.status = status.clone();
+            self.status_history.push(OrderStatusChange { status, timestamp: now });
         }
     }
 
-    pub fn is_expired(&self) -> bool {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
         if let Some(expire_at) = self.expire_at {
-            Utc::now() > expire_at
+            now > expire_at
         } else {
             false
         }
     }
 
-    pub fn update_filled(&mut self, filled_quantity: Decimal) {
+    pub fn update_filled(&mut self, filled_quantity: Decimal, now: DateTime<Utc>) {
         self.filled_quantity += filled_quantity;
         self.remaining_quantity -= filled_quantity;
-        self.updated_at = Utc::now();
+        self.display_remaining = (self.display_remaining - filled_quantity).max(Decimal::ZERO);
 
         if self.remaining_quantity == Decimal::ZERO {
-            self.status = OrderStatus::Filled;
+            self.set_status(OrderStatus::Filled, now);
         } else {
-            self.status = OrderStatus::Partial;
+            self.set_status(OrderStatus::Partial, now);
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderGroupType {
+    OneCancelsOther,
+    Bracket,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderGroupStatus {
+    Active,
+    Completed,
+    Cancelled,
+}
+
+/// Price and type of one order in an OCO or bracket group; side and size come from the group.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderLeg {
+    pub order_type: OrderType,
+    pub price: Option<Decimal>,
+    pub stop_price: Option<Decimal>,
+}
+
+/// Orders placed together whose lifecycles depend on each other. The exit orders (both legs
+/// of an OCO, take-profit and stop-loss of a bracket) share one size and one hold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderGroup {
+    pub id: String,
+    pub group_type: OrderGroupType,
+    pub trader: String,
+    pub symbol: String,
+    pub order_ids: Vec<String>,          // OCO legs, or entry, take-profit and stop-loss for brackets
+    pub entry_order_id: Option<String>,
+    pub quantity: Decimal,               // Size shared by the exit orders
+    pub locked_amount: Decimal,          // Hold shared by the exit orders
+    pub status: OrderGroupStatus,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl OrderGroup {
+    pub fn exit_order_ids(&self) -> Vec<String> {
+        self.order_ids.iter()
+            .filter(|order_id| self.entry_order_id.as_ref() != Some(*order_id))
+            .cloned()
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
     pub id: String,
@@ -119,600 +356,4735 @@ pub struct Trade {
     pub seller: String,
     pub timestamp: DateTime<Utc>,
     pub trade_type: String,
+    pub taker_side: OrderSide,
+    pub buyer_fee: Decimal,  // In the base currency the buyer received
+    pub seller_fee: Decimal, // In the quote currency the seller received
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OrderBookLevel {
-    pub price: Decimal,
-    pub quantity: Decimal,
-    pub order_count: usize,
+/// Trading phase of a symbol. A session runs Closed -> PreOpen -> Trading -> ClosingAuction -> Closed;
+/// the two call phases collect orders without matching them and end in an auction uncross.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TradingStatus {
+    PreOpen,
+    Trading,
+    ClosingAuction,
+    Halted, // Open orders stay on the book but no new orders are accepted
+    Closed,
 }
 
-#[derive(Debug, Clone)]
-pub struct OrderBook {
+impl TradingStatus {
+    pub fn accepts_orders(&self) -> bool {
+        matches!(self, TradingStatus::PreOpen | TradingStatus::Trading | TradingStatus::ClosingAuction)
+    }
+
+    pub fn is_call_auction(&self) -> bool {
+        matches!(self, TradingStatus::PreOpen | TradingStatus::ClosingAuction)
+    }
+}
+
+/// Where a call auction would uncross right now, published while a symbol is in a call phase.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IndicativeUncross {
     pub symbol: String,
-    pub bids: BTreeMap<Decimal, VecDeque<Order>>, // Price -> Orders (sorted descending)
-    pub asks: BTreeMap<Decimal, VecDeque<Order>>, // Price -> Orders (sorted ascending)
+    pub status: TradingStatus,
+    pub price: Option<Decimal>, // None while the book does not cross
+    pub matched_quantity: Decimal,
+    pub imbalance_quantity: Decimal, // Left over on the heavier side at the price
+    pub imbalance_side: Option<OrderSide>,
+    pub timestamp: DateTime<Utc>,
 }
 
-impl OrderBook {
-    pub fn new(symbol: String) -> Self {
+/// Price protection for one symbol, as percentages of the price each check is measured from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceBands {
+    pub static_percent: Option<Decimal>,     // Around the reference price: the last auction price, or one set by an operator
+    pub dynamic_percent: Option<Decimal>,    // Around the last trade price
+    pub volatility_percent: Option<Decimal>, // Largest move allowed within the window before trading is interrupted
+    pub volatility_window_seconds: i64,
+    pub halt_seconds: i64,    // How long a volatility interruption stays halted
+    pub auction_seconds: i64, // How long the reopening auction collects orders before it uncrosses
+}
+
+impl PriceBands {
+    pub fn new(static_percent: Option<Decimal>, dynamic_percent: Option<Decimal>) -> Self {
         Self {
-            symbol,
-            bids: BTreeMap::new(),
-            asks: BTreeMap::new(),
+            static_percent,
+            dynamic_percent,
+            volatility_percent: None,
+            volatility_window_seconds: 0,
+            halt_seconds: 0,
+            auction_seconds: 0,
         }
     }
+}
 
-    pub fn add_order(&mut self, order: Order) {
-        let price_map = match order.side {
-            OrderSide::Buy => &mut self.bids,
-            OrderSide::Sell => &mut self.asks,
-        };
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HaltSource {
+    Operator,
+    VolatilityInterruption,
+}
 
-        price_map.entry(order.price.unwrap_or(Decimal::ZERO))
-            .or_insert_with(VecDeque::new)
-            .push_back(order);
-    }
+/// One halt of a symbol, kept after it ends. A halt resumes through a reopening auction that
+/// starts at `resume_at` and uncrosses at `reopen_at`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradingHalt {
+    pub symbol: String,
+    pub source: HaltSource,
+    pub reason: String,
+    pub halted_at: DateTime<Utc>,
+    pub resume_at: Option<DateTime<Utc>>, // None until an operator resumes the symbol
+    pub reopen_at: Option<DateTime<Utc>>,
+    pub resumed_at: Option<DateTime<Utc>>, // Set once continuous trading is back
+    pub resume_reason: Option<String>,
+}
 
-    pub fn remove_order(&mut self, order_id: &str) -> Option<Order> {
-        for (price, orders) in self.bids.iter_mut().chain(self.asks.iter_mut()) {
-            if let Some(pos) = orders.iter().position(|o| o.id == order_id) {
-                return orders.remove(pos);
-            }
+/// Listing details of a symbol that every order on it is checked against.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymbolSpec {
+    #[serde(default)]
+    pub id: u32,               // Assigned on listing, from 1 in listing order
+    pub symbol: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub tick_size: Decimal,    // Prices must be a multiple of this
+    pub step_size: Decimal,    // Quantities must be a multiple of this
+    pub min_quantity: Decimal,
+    pub max_quantity: Decimal,
+    pub min_notional: Decimal, // Smallest price * quantity accepted
+    pub status: TradingStatus,
+}
+
+impl SymbolSpec {
+    /// A trading symbol with a one-cent tick, eight-decimal quantities and no size limits.
+    pub fn new(symbol: String, base_asset: String, quote_asset: String) -> Self {
+        Self {
+            id: 0,
+            symbol,
+            base_asset,
+            quote_asset,
+            tick_size: Decimal::new(1, 2),
+            step_size: Decimal::new(1, 8),
+            min_quantity: Decimal::new(1, 8),
+            max_quantity: Decimal::MAX,
+            min_notional: Decimal::ZERO,
+            status: TradingStatus::Trading,
         }
-        None
     }
 
-    pub fn get_best_bid(&self) -> Option<Decimal> {
-        self.bids.keys().next_back().copied()
+    pub fn validate_price(&self, name: &str, price: Decimal) -> Result<(), String> {
+        if price <= Decimal::ZERO {
+            return Err(format!("{} must be positive", name));
+        }
+        if price % self.tick_size != Decimal::ZERO {
+            return Err(format!("{} {} is not a multiple of the tick size {} for {}",
+                               name, price.normalize(), self.tick_size.normalize(), self.symbol));
+        }
+        Ok(())
     }
 
-    pub fn get_best_ask(&self) -> Option<Decimal> {
-        self.asks.keys().next().copied()
+    pub fn validate_quantity(&self, quantity: Decimal) -> Result<(), String> {
+        if quantity < self.min_quantity || quantity > self.max_quantity {
+            return Err(format!("Quantity {} is outside the range {} to {} for {}", quantity.normalize(),
+                               self.min_quantity.normalize(), self.max_quantity.normalize(), self.symbol));
+        }
+        if quantity % self.step_size != Decimal::ZERO {
+            return Err(format!("Quantity {} is not a multiple of the step size {} for {}",
+                               quantity.normalize(), self.step_size.normalize(), self.symbol));
+        }
+        Ok(())
     }
 
-    pub fn get_spread(&self) -> Option<Decimal> {
-        if let (Some(best_bid), Some(best_ask)) = (self.get_best_bid(), self.get_best_ask()) {
-            Some(best_ask - best_bid)
-        } else {
-            None
+    pub fn validate_notional(&self, price: Decimal, quantity: Decimal) -> Result<(), String> {
+        let notional = price * quantity;
+        if notional < self.min_notional {
+            return Err(format!("Order value {} is below the minimum of {} {} for {}", notional.normalize(),
+                               self.min_notional.normalize(), self.quote_asset, self.symbol));
         }
+        Ok(())
     }
 
-    pub fn get_bid_levels(&self, depth: usize) -> Vec<OrderBookLevel> {
-        self.bids.iter().rev().take(depth).map(|(price, orders)| {
-            let quantity = orders.iter().map(|o| o.remaining_quantity).sum();
-            OrderBookLevel {
-                price: *price,
-                quantity,
-                order_count: orders.len(),
-            }
-        }).collect()
+    /// Rounds a quantity down onto the step grid.
+    pub fn round_quantity(&self, quantity: Decimal) -> Decimal {
+        (quantity / self.step_size).floor() * self.step_size
     }
+}
 
-    pub fn get_ask_levels(&self, depth: usize) -> Vec<OrderBookLevel> {
-        self.asks.iter().take(depth).map(|(price, orders)| {
-            let quantity = orders.iter().map(|o| o.remaining_quantity).sum();
-            OrderBookLevel {
-                price: *price,
-                quantity,
-                order_count: orders.len(),
-            }
-        }).collect()
+/// Rates for traders with at least `min_volume` of quote currency traded on the symbol over the
+/// last 30 days. Rates are fractions of the amount received, e.g. 0.001 for 10 bps.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub min_volume: Decimal,
+    pub maker_rate: Decimal,
+    pub taker_rate: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    pub tiers: Vec<FeeTier>, // Ascending by min_volume, starting from zero
+}
+
+impl FeeSchedule {
+    pub fn new(tiers: Vec<FeeTier>) -> Self {
+        Self { tiers }
     }
 
-    pub fn get_market_depth(&self, depth: usize) -> (Vec<OrderBookLevel>, Vec<OrderBookLevel>) {
-        (self.get_bid_levels(depth), self.get_ask_levels(depth))
+    /// Highest tier the volume qualifies for.
+    pub fn tier_for(&self, volume: Decimal) -> Option<&FeeTier> {
+        self.tiers.iter().rev().find(|tier| volume >= tier.min_volume)
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct DEXEngine {
-    order_books: HashMap<String, OrderBook>,
-    orders: HashMap<String, Order>,
-    trades: Vec<Trade>,
-    user_balances: HashMap<String, HashMap<String, Decimal>>,
-    order_counter: u64,
-    trade_counter: u64,
+/// A trader's traded quote volume on one symbol, kept for the fee tier look-back.
+#[derive(Debug, Clone, Default)]
+struct VolumeWindow {
+    entries: VecDeque<(DateTime<Utc>, Decimal)>,
+    total: Decimal,
 }
 
-impl DEXEngine {
-    pub fn new() -> Self {
-        Self {
-            order_books: HashMap::new(),
-            orders: HashMap::new(),
-            trades: Vec::new(),
-            user_balances: HashMap::new(),
-            order_counter: 0,
-            trade_counter: 0,
+impl VolumeWindow {
+    fn record(&mut self, timestamp: DateTime<Utc>, volume: Decimal) {
+        self.entries.push_back((timestamp, volume));
+        self.total += volume;
+        while let Some((oldest, volume)) = self.entries.front().copied() {
+            if oldest > timestamp - Duration::days(FEE_VOLUME_DAYS) {
+                break;
+            }
+            self.entries.pop_front();
+            self.total -= volume;
         }
     }
 
-    pub fn add_symbol(&mut self, symbol: String) {
-        self.order_books.insert(symbol, OrderBook::new(symbol));
+    fn volume_since(&self, cutoff: DateTime<Utc>) -> Decimal {
+        let expired: Decimal = self.entries.iter()
+            .take_while(|(timestamp, _)| *timestamp <= cutoff)
+            .map(|(_, volume)| *volume)
+            .sum();
+        self.total - expired
     }
+}
 
-    pub fn place_order(&mut self, trader: String, symbol: String, side: OrderSide, order_type: OrderType,
-                      quantity: Decimal, price: Option<Decimal>, stop_price: Option<Decimal>,
-                      time_in_force: TimeInForce, expire_at: Option<DateTime<Utc>>) -> Result<String, String> {
-        if !self.order_books.contains_key(&symbol) {
-            return Err("Symbol not supported".to_string());
-        }
+const FEE_VOLUME_DAYS: i64 = 30;
 
-        // Validate order parameters
-        self.validate_order(&order_type, price, stop_price)?;
+/// A symbol's trades over the ticker window, with running totals and monotonic price queues so the
+/// range never needs a re-sort.
+#[derive(Debug, Clone, Default)]
+struct TickerWindow {
+    trades: VecDeque<(DateTime<Utc>, Decimal, Decimal)>, // Timestamp, price, quantity
+    highs: VecDeque<(DateTime<Utc>, Decimal)>,            // Strictly falling prices
+    lows: VecDeque<(DateTime<Utc>, Decimal)>,             // Strictly rising prices
+    volume: Decimal,
+    quote_volume: Decimal,
+}
 
-        // Check user balance for sell orders
-        if side == OrderSide::Sell {
-            let base_currency = self.get_base_currency(&symbol);
-            let balance = self.get_user_balance(&trader, &base_currency);
-            if balance < quantity {
-                return Err("Insufficient balance".to_string());
-            }
+impl TickerWindow {
+    fn record(&mut self, timestamp: DateTime<Utc>, price: Decimal, quantity: Decimal) {
+        self.trades.push_back((timestamp, price, quantity));
+        self.volume += quantity;
+        self.quote_volume += price * quantity;
+        while self.highs.back().is_some_and(|(_, high)| *high <= price) {
+            self.highs.pop_back();
         }
+        self.highs.push_back((timestamp, price));
+        while self.lows.back().is_some_and(|(_, low)| *low >= price) {
+            self.lows.pop_back();
+        }
+        self.lows.push_back((timestamp, price));
 
-        self.order_counter += 1;
-        let order_id = format!("order_{}", self.order_counter);
-
-        let mut order = Order::new(
-            order_id.clone(),
-            trader,
-            symbol.clone(),
-            side,
-            order_type,
-            quantity,
-            price,
-            stop_price,
-            time_in_force,
-            expire_at,
-        );
-
-        // Process market orders immediately
-        if order_type == OrderType::Market {
-            self.process_market_order(&mut order)?;
-        } else {
-            // Add limit orders to order book
-            self.order_books.get_mut(&symbol).unwrap().add_order(order.clone());
+        let cutoff = timestamp - Duration::hours(TICKER_WINDOW_HOURS);
+        while let Some((oldest, price, quantity)) = self.trades.front().copied() {
+            if oldest > cutoff {
+                break;
+            }
+            self.trades.pop_front();
+            self.volume -= quantity;
+            self.quote_volume -= price * quantity;
+        }
+        while self.highs.front().is_some_and(|(timestamp, _)| *timestamp <= cutoff) {
+            self.highs.pop_front();
         }
+        while self.lows.front().is_some_and(|(timestamp, _)| *timestamp <= cutoff) {
+            self.lows.pop_front();
+        }
+    }
 
-        self.orders.insert(order_id.clone(), order);
-        Ok(order_id)
+    /// Open, high, low, base volume, quote volume and trade count for trades after `cutoff`.
+    fn stats_since(&self, cutoff: DateTime<Utc>) -> Option<(Decimal, Decimal, Decimal, Decimal, Decimal, u64)> {
+        let expired = self.trades.iter().take_while(|(timestamp, _, _)| *timestamp <= cutoff).count();
+        let (_, open, _) = *self.trades.get(expired)?;
+        let (volume, quote_volume) = self.trades.iter()
+            .take(expired)
+            .fold((self.volume, self.quote_volume), |(volume, quote_volume), (_, price, quantity)| {
+                (volume - quantity, quote_volume - price * quantity)
+            });
+        let high = self.highs.iter().find(|(timestamp, _)| *timestamp > cutoff).map(|(_, price)| *price)?;
+        let low = self.lows.iter().find(|(timestamp, _)| *timestamp > cutoff).map(|(_, price)| *price)?;
+        Some((open, high, low, volume, quote_volume, (self.trades.len() - expired) as u64))
     }
+}
 
-    fn process_market_order(&mut self, order: &mut Order) -> Result<(), String> {
-        let order_book = self.order_books.get_mut(&order.symbol).unwrap();
+const TICKER_WINDOW_HOURS: i64 = 24;
 
-        match order.side {
-            OrderSide::Buy => {
-                // Match against asks (sell orders)
-                self.match_market_buy_order(order, order_book)?;
-            }
-            OrderSide::Sell => {
-                // Match against bids (buy orders)
-                self.match_market_sell_order(order, order_book)?;
-            }
+/// A symbol's recent trade prices as monotonic queues, so the volatility check finds the range
+/// without scanning the trade history. The window length comes from the symbol's bands at each
+/// check, and prices older than it are dropped then.
+#[derive(Debug, Clone, Default)]
+struct PriceRangeWindow {
+    highs: VecDeque<(DateTime<Utc>, Decimal)>, // Strictly falling prices
+    lows: VecDeque<(DateTime<Utc>, Decimal)>,  // Strictly rising prices
+}
+
+impl PriceRangeWindow {
+    fn record(&mut self, timestamp: DateTime<Utc>, price: Decimal) {
+        while self.highs.back().is_some_and(|(_, high)| *high <= price) {
+            self.highs.pop_back();
         }
+        self.highs.push_back((timestamp, price));
+        while self.lows.back().is_some_and(|(_, low)| *low >= price) {
+            self.lows.pop_back();
+        }
+        self.lows.push_back((timestamp, price));
+    }
 
-        Ok(())
+    /// Lowest and highest price traded at or after `cutoff`.
+    fn range_since(&mut self, cutoff: DateTime<Utc>) -> Option<(Decimal, Decimal)> {
+        while self.highs.front().is_some_and(|(timestamp, _)| *timestamp < cutoff) {
+            self.highs.pop_front();
+        }
+        while self.lows.front().is_some_and(|(timestamp, _)| *timestamp < cutoff) {
+            self.lows.pop_front();
+        }
+        Some((self.lows.front()?.1, self.highs.front()?.1))
     }
+}
 
-    fn match_market_buy_order(&mut self, order: &mut Order, order_book: &mut OrderBook) -> Result<(), String> {
-        let mut remaining_quantity = order.quantity;
+/// Rolling 24h statistics for one symbol. Prices fall back to the last trade when nothing traded
+/// inside the window, and are zero for a symbol that never traded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ticker {
+    pub symbol: String,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub last: Decimal,
+    pub change: Decimal,         // last - open
+    pub change_percent: Decimal, // Rounded to 2 dp
+    pub vwap: Decimal,
+    pub volume: Decimal,       // Base quantity traded
+    pub quote_volume: Decimal, // Sum of price * quantity
+    pub trade_count: u64,
+    pub best_bid: Option<Decimal>,
+    pub best_bid_quantity: Decimal,
+    pub best_ask: Option<Decimal>,
+    pub best_ask_quantity: Decimal,
+    pub timestamp: DateTime<Utc>,
+}
 
-        while remaining_quantity > Decimal::ZERO {
-            if let Some((price, orders)) = order_book.asks.iter_mut().next() {
-                if orders.is_empty() {
-                    continue;
-                }
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    pub available: Decimal,
+    pub locked: Decimal,
+}
 
-                let sell_order = orders.front_mut().unwrap();
-                let match_quantity = remaining_quantity.min(sell_order.remaining_quantity);
+impl Balance {
+    pub fn total(&self) -> Decimal {
+        self.available + self.locked
+    }
+}
 
-                self.execute_trade(order, sell_order, *price, match_quantity);
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OrderBookLevel {
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub order_count: usize,
+}
 
-                remaining_quantity -= match_quantity;
+/// New state of one displayed price level. A quantity of zero means the level is gone.
+/// Sequence numbers go up by one per update on a symbol, so a skipped number means a missed update.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DepthUpdate {
+    pub symbol: String,
+    pub sequence: u64,
+    pub side: OrderSide,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub order_count: usize,
+}
 
-                if sell_order.remaining_quantity == Decimal::ZERO {
-                    orders.pop_front();
-                }
+/// Displayed depth as of `sequence`: updates with a higher sequence apply on top of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthSnapshot {
+    pub symbol: String,
+    pub sequence: u64,
+    pub bids: Vec<OrderBookLevel>,
+    pub asks: Vec<OrderBookLevel>,
+}
 
-                if orders.is_empty() {
-                    order_book.asks.remove(price);
-                }
-            } else {
-                // No more sell orders available
-                break;
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderEventKind {
+    Add,     // Joined the back of its price level
+    Modify,  // Changed size or price
+    Cancel,
+    Expire,
+    Execute, // Traded; one per order per trade
+    Trade,   // The trade itself, reported from the taker's side
+}
+
+/// One step in an order's life, numbered per symbol. Applying a symbol's events in sequence with
+/// `OrderBook::apply_event` rebuilds its book exactly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderEvent {
+    pub symbol: String,
+    pub sequence: u64,
+    pub kind: OrderEventKind,
+    pub order_id: String,
+    pub side: OrderSide,
+    pub price: Option<Decimal>,      // Order price, or the trade price for executions and trades
+    pub quantity: Decimal,           // Quantity added, modified to, cancelled, expired or traded
+    pub displayed_quantity: Decimal, // Showing on the book after the event
+    pub hidden_quantity: Decimal,    // Resting out of sight after the event
+    pub kept_priority: bool,         // For modifies: if not, the order left the book and any rest re-enters with an add
+    pub trade_id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl OrderEvent {
+    fn for_order(kind: OrderEventKind, order: &Order, quantity: Decimal) -> Self {
+        let displayed_quantity = if order.is_open() { order.displayed_quantity() } else { Decimal::ZERO };
+        let hidden_quantity = if order.is_open() { order.remaining_quantity - displayed_quantity } else { Decimal::ZERO };
+        Self {
+            symbol: order.symbol.clone(),
+            sequence: 0,
+            kind,
+            order_id: order.id.clone(),
+            side: order.side.clone(),
+            price: order.price,
+            quantity,
+            displayed_quantity,
+            hidden_quantity,
+            kept_priority: true,
+            trade_id: None,
+            timestamp: order.updated_at, // Stamped when published
+        }
+    }
+}
+
+/// Book entry for a resting order. The full order lives in `DEXEngine::orders`; the book keeps only
+/// what it needs to aggregate depth.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RestingOrder {
+    pub order_id: String,
+    pub quantity: Decimal,
+    pub hidden_quantity: Decimal,
+}
+
+/// Orders resting at one price, keyed by queue position so that the lowest key has time priority.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PriceLevel {
+    pub quantity: Decimal,
+    pub hidden_quantity: Decimal,
+    pub orders: BTreeMap<u64, RestingOrder>,
+}
+
+impl PriceLevel {
+    pub fn total_quantity(&self) -> Decimal {
+        self.quantity + self.hidden_quantity
+    }
+
+    pub fn displayed_order_count(&self) -> usize {
+        self.orders.values().filter(|resting| resting.quantity > Decimal::ZERO).count()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderLocation {
+    pub side: OrderSide,
+    pub price: Decimal,
+    pub sequence: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderBook {
+    pub symbol: String,
+    pub bids: BTreeMap<Decimal, PriceLevel>, // Price -> Orders (sorted descending)
+    pub asks: BTreeMap<Decimal, PriceLevel>, // Price -> Orders (sorted ascending)
+    index: HashMap<String, OrderLocation>,
+    next_sequence: u64,
+    changed_bids: BTreeSet<Decimal>, // Levels touched since depth was last published
+    changed_asks: BTreeSet<Decimal>,
+    published_bids: BTreeMap<Decimal, OrderBookLevel>, // Depth as last published
+    published_asks: BTreeMap<Decimal, OrderBookLevel>,
+    depth_sequence: u64,
+}
+
+impl OrderBook {
+    pub fn new(symbol: String) -> Self {
+        Self {
+            symbol,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            index: HashMap::new(),
+            next_sequence: 0,
+            changed_bids: BTreeSet::new(),
+            changed_asks: BTreeSet::new(),
+            published_bids: BTreeMap::new(),
+            published_asks: BTreeMap::new(),
+            depth_sequence: 0,
+        }
+    }
+
+    /// Queues the order at the back of its price level.
+    pub fn add_order(&mut self, order: &Order) {
+        let displayed = order.displayed_quantity();
+        let hidden = order.remaining_quantity - displayed;
+        self.add_resting(&order.id, &order.side, order.price.unwrap_or(Decimal::ZERO), displayed, hidden);
+    }
+
+    fn add_resting(&mut self, order_id: &str, side: &OrderSide, price: Decimal, displayed: Decimal, hidden: Decimal) {
+        self.next_sequence += 1;
+        let location = OrderLocation {
+            side: side.clone(),
+            price,
+            sequence: self.next_sequence,
+        };
+
+        self.mark_changed(&location.side, location.price);
+        let level = self.side_mut(&location.side).entry(location.price).or_default();
+        level.quantity += displayed;
+        level.hidden_quantity += hidden;
+        level.orders.insert(location.sequence, RestingOrder {
+            order_id: order_id.to_string(),
+            quantity: displayed,
+            hidden_quantity: hidden,
+        });
+
+        self.index.insert(order_id.to_string(), location);
+    }
+
+    pub fn remove_order(&mut self, order_id: &str) -> Option<RestingOrder> {
+        let location = self.index.remove(order_id)?;
+        self.mark_changed(&location.side, location.price);
+        let price_map = self.side_mut(&location.side);
+
+        let level = price_map.get_mut(&location.price)?;
+        let resting = level.orders.remove(&location.sequence)?;
+        level.quantity -= resting.quantity;
+        level.hidden_quantity -= resting.hidden_quantity;
+        if level.orders.is_empty() {
+            price_map.remove(&location.price);
+        }
+        Some(resting)
+    }
+
+    /// Brings the resting quantities of an order in line with the order, keeping its queue
+    /// position. An order with nothing left is removed.
+    pub fn update_order(&mut self, order: &Order) {
+        if !order.is_open() || order.remaining_quantity <= Decimal::ZERO {
+            self.remove_order(&order.id);
+            return;
+        }
+
+        let displayed = order.displayed_quantity();
+        self.set_resting(&order.id, displayed, order.remaining_quantity - displayed);
+    }
+
+    fn set_resting(&mut self, order_id: &str, displayed: Decimal, hidden: Decimal) {
+        let location = match self.index.get(order_id) {
+            Some(location) => location.clone(),
+            None => return,
+        };
+
+        self.mark_changed(&location.side, location.price);
+        if let Some(level) = self.side_mut(&location.side).get_mut(&location.price) {
+            if let Some(resting) = level.orders.get_mut(&location.sequence) {
+                level.quantity += displayed - resting.quantity;
+                level.hidden_quantity += hidden - resting.hidden_quantity;
+                resting.quantity = displayed;
+                resting.hidden_quantity = hidden;
             }
         }
+    }
 
-        if remaining_quantity > Decimal::ZERO {
-            order.status = OrderStatus::Partial;
-            order.remaining_quantity = remaining_quantity;
+    /// Applies an event from `DEXEngine::subscribe_order_events` to a book being rebuilt from the
+    /// stream. Events for orders that never rested here are ignored.
+    pub fn apply_event(&mut self, event: &OrderEvent) {
+        match event.kind {
+            OrderEventKind::Add => {
+                self.add_resting(&event.order_id, &event.side, event.price.unwrap_or(Decimal::ZERO),
+                                 event.displayed_quantity, event.hidden_quantity);
+            }
+            OrderEventKind::Modify | OrderEventKind::Execute
+                if event.kept_priority && event.displayed_quantity + event.hidden_quantity > Decimal::ZERO => {
+                self.set_resting(&event.order_id, event.displayed_quantity, event.hidden_quantity);
+            }
+            OrderEventKind::Modify | OrderEventKind::Execute | OrderEventKind::Cancel | OrderEventKind::Expire => {
+                self.remove_order(&event.order_id);
+            }
+            OrderEventKind::Trade => {}
         }
+    }
 
-        Ok(())
+    pub fn contains(&self, order_id: &str) -> bool {
+        self.index.contains_key(order_id)
+    }
+
+    pub fn get_order_location(&self, order_id: &str) -> Option<&OrderLocation> {
+        self.index.get(order_id)
+    }
+
+    /// Price and id of the order with priority on the given side.
+    pub fn get_best_order(&self, side: &OrderSide) -> Option<(Decimal, String)> {
+        let best = match side {
+            OrderSide::Buy => self.bids.iter().next_back(),
+            OrderSide::Sell => self.asks.iter().next(),
+        };
+
+        best.and_then(|(price, level)| {
+            level.orders.values().next().map(|resting| (*price, resting.order_id.clone()))
+        })
+    }
+
+    pub fn order_count(&self) -> usize {
+        self.index.len()
+    }
+
+    fn mark_changed(&mut self, side: &OrderSide, price: Decimal) {
+        match side {
+            OrderSide::Buy => self.changed_bids.insert(price),
+            OrderSide::Sell => self.changed_asks.insert(price),
+        };
+    }
+
+    /// Sequence number of the last depth update published.
+    pub fn depth_sequence(&self) -> u64 {
+        self.depth_sequence
     }
 
-    fn match_market_sell_order(&mut self, order: &mut Order, order_book: &mut OrderBook) -> Result<(), String> {
-        let mut remaining_quantity = order.quantity;
+    /// Depth updates for every displayed level that changed since the last call. Levels touched
+    /// without a visible change, such as by hidden orders, produce no update.
+    pub fn take_depth_updates(&mut self) -> Vec<DepthUpdate> {
+        let mut updates = Vec::new();
+        for side in [OrderSide::Buy, OrderSide::Sell] {
+            let (levels, changed, published) = match side {
+                OrderSide::Buy => (&self.bids, &mut self.changed_bids, &mut self.published_bids),
+                OrderSide::Sell => (&self.asks, &mut self.changed_asks, &mut self.published_asks),
+            };
 
-        while remaining_quantity > Decimal::ZERO {
-            if let Some((price, orders)) = order_book.bids.iter_mut().rev().next() {
-                if orders.is_empty() {
+            for price in std::mem::take(changed) {
+                let level = levels.get(&price)
+                    .filter(|level| level.quantity > Decimal::ZERO)
+                    .map(|level| OrderBookLevel {
+                        price,
+                        quantity: level.quantity,
+                        order_count: level.displayed_order_count(),
+                    });
+                if level.as_ref() == published.get(&price) {
                     continue;
                 }
 
-                let buy_order = orders.front_mut().unwrap();
-                let match_quantity = remaining_quantity.min(buy_order.remaining_quantity);
+                self.depth_sequence += 1;
+                updates.push(DepthUpdate {
+                    symbol: self.symbol.clone(),
+                    sequence: self.depth_sequence,
+                    side: side.clone(),
+                    price,
+                    quantity: level.as_ref().map_or(Decimal::ZERO, |level| level.quantity),
+                    order_count: level.as_ref().map_or(0, |level| level.order_count),
+                });
+                match level {
+                    Some(level) => published.insert(price, level),
+                    None => published.remove(&price),
+                };
+            }
+        }
+        updates
+    }
+
+    fn side_mut(&mut self, side: &OrderSide) -> &mut BTreeMap<Decimal, PriceLevel> {
+        match side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        }
+    }
+
+    /// Quantity an incoming order on `side` could take from the opposite side without trading
+    /// through `limit_price` (no limit for market orders).
+    pub fn fillable_quantity(&self, side: &OrderSide, limit_price: Option<Decimal>) -> Decimal {
+        match side {
+            OrderSide::Buy => self.asks.iter()
+                .take_while(|(price, _)| limit_price.is_none_or(|limit| **price <= limit))
+                .map(|(_, level)| level.total_quantity())
+                .sum(),
+            OrderSide::Sell => self.bids.iter().rev()
+                .take_while(|(price, _)| limit_price.is_none_or(|limit| **price >= limit))
+                .map(|(_, level)| level.total_quantity())
+                .sum(),
+        }
+    }
 
-                self.execute_trade(buy_order, order, *price, match_quantity);
+    /// Where a call auction would uncross the book: the price that trades the most, then leaves
+    /// the smallest imbalance, then lies nearest `reference_price` (or the middle of the tied
+    /// prices without one). Returns the price with the buy and sell quantity willing to trade
+    /// there, or None if the book does not cross. Hidden quantity takes part.
+    pub fn equilibrium(&self, reference_price: Option<Decimal>) -> Option<(Decimal, Decimal, Decimal)> {
+        let (best_bid, best_ask) = (self.get_best_bid()?, self.get_best_ask()?);
+        if best_bid < best_ask {
+            return None;
+        }
 
-                remaining_quantity -= match_quantity;
+        let prices: BTreeSet<Decimal> = self.bids.range(best_ask..=best_bid)
+            .chain(self.asks.range(best_ask..=best_bid))
+            .map(|(price, _)| *price)
+            .collect();
+        let volumes: Vec<(Decimal, Decimal, Decimal)> = prices.into_iter().map(|price| {
+            let buy_quantity = self.bids.range(price..).map(|(_, level)| level.total_quantity()).sum();
+            let sell_quantity = self.asks.range(..=price).map(|(_, level)| level.total_quantity()).sum();
+            (price, buy_quantity, sell_quantity)
+        }).collect();
 
-                if buy_order.remaining_quantity == Decimal::ZERO {
-                    orders.pop_front();
-                }
+        let most = volumes.iter().map(|(_, buy, sell)| *buy.min(sell)).max()?;
+        let tied: Vec<_> = volumes.into_iter().filter(|(_, buy, sell)| *buy.min(sell) == most).collect();
+        let least = tied.iter().map(|(_, buy, sell)| (*buy - *sell).abs()).min()?;
+        let tied: Vec<_> = tied.into_iter().filter(|(_, buy, sell)| (*buy - *sell).abs() == least).collect();
+        let reference_price = reference_price.unwrap_or((tied[0].0 + tied[tied.len() - 1].0) / Decimal::TWO);
+        tied.into_iter().min_by_key(|(price, _, _)| ((*price - reference_price).abs(), *price))
+    }
 
-                if orders.is_empty() {
-                    order_book.bids.remove(price);
-                }
-            } else {
-                // No more buy orders available
-                break;
+    pub fn get_best_bid(&self) -> Option<Decimal> {
+        self.bids.keys().next_back().copied()
+    }
+
+    pub fn get_best_ask(&self) -> Option<Decimal> {
+        self.asks.keys().next().copied()
+    }
+
+    pub fn get_spread(&self) -> Option<Decimal> {
+        if let (Some(best_bid), Some(best_ask)) = (self.get_best_bid(), self.get_best_ask()) {
+            Some(best_ask - best_bid)
+        } else {
+            None
+        }
+    }
+
+    // Depth only shows displayed quantity; levels holding nothing but hidden orders are skipped
+    pub fn get_bid_levels(&self, depth: usize) -> Vec<OrderBookLevel> {
+        self.bids.iter().rev().filter(|(_, level)| level.quantity > Decimal::ZERO).take(depth).map(|(price, level)| {
+            OrderBookLevel {
+                price: *price,
+                quantity: level.quantity,
+                order_count: level.displayed_order_count(),
+            }
+        }).collect()
+    }
+
+    pub fn get_ask_levels(&self, depth: usize) -> Vec<OrderBookLevel> {
+        self.asks.iter().filter(|(_, level)| level.quantity > Decimal::ZERO).take(depth).map(|(price, level)| {
+            OrderBookLevel {
+                price: *price,
+                quantity: level.quantity,
+                order_count: level.displayed_order_count(),
             }
+        }).collect()
+    }
+
+    pub fn get_market_depth(&self, depth: usize) -> (Vec<OrderBookLevel>, Vec<OrderBookLevel>) {
+        (self.get_bid_levels(depth), self.get_ask_levels(depth))
+    }
+}
+
+/// Untriggered stop orders for one symbol, keyed by stop price. Buy stops fire once the
+/// last trade price rises to their stop price, sell stops once it falls to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StopBook {
+    pub symbol: String,
+    pub buy_stops: BTreeMap<Decimal, VecDeque<String>>,
+    pub sell_stops: BTreeMap<Decimal, VecDeque<String>>,
+    pub trailing_orders: BTreeSet<String>,
+}
+
+impl StopBook {
+    pub fn new(symbol: String) -> Self {
+        Self {
+            symbol,
+            buy_stops: BTreeMap::new(),
+            sell_stops: BTreeMap::new(),
+            trailing_orders: BTreeSet::new(),
+        }
+    }
+
+    pub fn add_order(&mut self, order: &Order) {
+        let stop_map = match order.side {
+            OrderSide::Buy => &mut self.buy_stops,
+            OrderSide::Sell => &mut self.sell_stops,
+        };
+
+        stop_map.entry(order.stop_price.unwrap_or(Decimal::ZERO))
+            .or_insert_with(VecDeque::new)
+            .push_back(order.id.clone());
+
+        if order.trailing.is_some() {
+            self.trailing_orders.insert(order.id.clone());
+        }
+    }
+
+    pub fn remove_order(&mut self, order: &Order) -> bool {
+        let stop_map = match order.side {
+            OrderSide::Buy => &mut self.buy_stops,
+            OrderSide::Sell => &mut self.sell_stops,
+        };
+        let stop_price = order.stop_price.unwrap_or(Decimal::ZERO);
+
+        let removed = match stop_map.get_mut(&stop_price) {
+            Some(ids) => match ids.iter().position(|id| *id == order.id) {
+                Some(pos) => ids.remove(pos).is_some(),
+                None => false,
+            },
+            None => false,
+        };
+
+        if stop_map.get(&stop_price).is_some_and(|ids| ids.is_empty()) {
+            stop_map.remove(&stop_price);
+        }
+        self.trailing_orders.remove(&order.id);
+        removed
+    }
+
+    /// Removes and returns the next stop order crossed by `last_price`, oldest first within a level.
+    pub fn pop_triggered(&mut self, last_price: Decimal) -> Option<String> {
+        let buy_level = self.buy_stops.keys().next().copied().filter(|stop| *stop <= last_price);
+        let sell_level = self.sell_stops.keys().next_back().copied().filter(|stop| *stop >= last_price);
+
+        let order_id = if let Some(stop_price) = buy_level {
+            Self::pop_level(&mut self.buy_stops, stop_price)
+        } else if let Some(stop_price) = sell_level {
+            Self::pop_level(&mut self.sell_stops, stop_price)
+        } else {
+            None
+        };
+
+        if let Some(order_id) = &order_id {
+            self.trailing_orders.remove(order_id);
+        }
+        order_id
+    }
+
+    fn pop_level(stop_map: &mut BTreeMap<Decimal, VecDeque<String>>, stop_price: Decimal) -> Option<String> {
+        let ids = stop_map.get_mut(&stop_price)?;
+        let id = ids.pop_front();
+        if ids.is_empty() {
+            stop_map.remove(&stop_price);
+        }
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.buy_stops.values().chain(self.sell_stops.values()).map(|ids| ids.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Serializes everything except subscribers and the statistics kept from the trade history; call
+/// `rebuild_trade_statistics` after deserializing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DEXEngine {
+    symbol_specs: HashMap<String, SymbolSpec>,
+    price_bands: HashMap<String, PriceBands>,
+    reference_prices: HashMap<String, Decimal>,
+    trading_halts: HashMap<String, Vec<TradingHalt>>, // Oldest first; only the last can be active
+    order_books: HashMap<String, OrderBook>,
+    stop_books: HashMap<String, StopBook>,
+    last_trade_prices: HashMap<String, Decimal>,
+    orders: HashMap<String, Order>,
+    order_groups: HashMap<String, OrderGroup>,
+    group_events: VecDeque<String>, // Grouped orders that filled or closed since their group last caught up
+    trades: Vec<Trade>,
+    #[serde(skip)]
+    candles: CandleAggregator,
+    #[serde(skip)]
+    ticker_windows: HashMap<String, TickerWindow>,
+    #[serde(skip)]
+    price_ranges: HashMap<String, PriceRangeWindow>, // Recent trade prices for volatility interruptions
+    fee_schedules: HashMap<String, FeeSchedule>,
+    #[serde(skip)]
+    trade_volumes: HashMap<(String, String), VolumeWindow>, // Keyed by trader and symbol
+    fee_account: String,
+    self_trade_prevention: HashMap<String, SelfTradePrevention>, // Per-trader default
+    user_balances: Ledger,
+    positions: HashMap<String, HashMap<String, Decimal>>,
+    order_counter: u64,
+    group_counter: u64,
+    trade_counter: u64,
+    #[serde(default)]
+    id_prefix: String, // Put before new order, group and trade ids
+    #[serde(skip)]
+    depth_subscribers: HashMap<String, Vec<Sender<DepthUpdate>>>,
+    order_event_sequences: HashMap<String, u64>,
+    #[serde(skip)]
+    order_event_subscribers: HashMap<String, Vec<Sender<OrderEvent>>>,
+    #[serde(skip)]
+    published_uncrosses: HashMap<String, IndicativeUncross>,
+    #[serde(skip)]
+    uncross_subscribers: HashMap<String, Vec<Sender<IndicativeUncross>>>,
+    #[serde(skip)]
+    clock: Option<DateTime<Utc>>, // Pinned time for replays; None follows the wall clock
+}
+
+impl Default for DEXEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DEXEngine {
+    pub fn new() -> Self {
+        Self {
+            symbol_specs: HashMap::new(),
+            price_bands: HashMap::new(),
+            reference_prices: HashMap::new(),
+            trading_halts: HashMap::new(),
+            order_books: HashMap::new(),
+            stop_books: HashMap::new(),
+            last_trade_prices: HashMap::new(),
+            orders: HashMap::new(),
+            order_groups: HashMap::new(),
+            group_events: VecDeque::new(),
+            trades: Vec::new(),
+            candles: CandleAggregator::new(),
+            ticker_windows: HashMap::new(),
+            price_ranges: HashMap::new(),
+            fee_schedules: HashMap::new(),
+            trade_volumes: HashMap::new(),
+            fee_account: "fees".to_string(),
+            self_trade_prevention: HashMap::new(),
+            user_balances: Ledger::new(),
+            positions: HashMap::new(),
+            order_counter: 0,
+            group_counter: 0,
+            trade_counter: 0,
+            id_prefix: String::new(),
+            depth_subscribers: HashMap::new(),
+            order_event_sequences: HashMap::new(),
+            order_event_subscribers: HashMap::new(),
+            published_uncrosses: HashMap::new(),
+            uncross_subscribers: HashMap::new(),
+            clock: None,
+        }
+    }
+
+    /// An engine that keeps its balances in `ledger`, which other engines may share.
+    pub fn with_ledger(ledger: Ledger) -> Self {
+        Self {
+            user_balances: ledger,
+            ..Self::new()
+        }
+    }
+
+    /// A handle to the engine's balances.
+    pub fn ledger(&self) -> Ledger {
+        self.user_balances.share()
+    }
+
+    /// Puts `prefix` before the ids of orders, groups and trades created from now on, so engines
+    /// sharing a ledger never hand out the same id.
+    pub fn set_id_prefix(&mut self, prefix: &str) {
+        self.id_prefix = prefix.to_string();
+    }
+
+    /// Time the engine stamps on orders, trades and events.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.unwrap_or_else(Utc::now)
+    }
+
+    /// Pins the engine's time, or hands it back to the wall clock with None.
+    pub fn set_clock(&mut self, now: Option<DateTime<Utc>>) {
+        self.clock = now;
+    }
+
+    /// Lists a "BASE/QUOTE" symbol with the default spec.
+    pub fn add_symbol(&mut self, symbol: String) {
+        let base_asset = symbol.split('/').next().unwrap_or("BASE").to_string();
+        let quote_asset = symbol.split('/').nth(1).unwrap_or("QUOTE").to_string();
+        self.list_symbol(SymbolSpec::new(symbol, base_asset, quote_asset));
+    }
+
+    pub fn add_symbol_spec(&mut self, spec: SymbolSpec) -> Result<(), String> {
+        if self.symbol_specs.contains_key(&spec.symbol) {
+            return Err(format!("Symbol {} is already listed", spec.symbol));
+        }
+        if spec.base_asset.is_empty() || spec.quote_asset.is_empty() || spec.base_asset == spec.quote_asset {
+            return Err("Base and quote assets must be two different assets".to_string());
+        }
+        if spec.tick_size <= Decimal::ZERO || spec.step_size <= Decimal::ZERO {
+            return Err("Tick size and step size must be positive".to_string());
+        }
+        if spec.min_quantity <= Decimal::ZERO || spec.min_quantity > spec.max_quantity {
+            return Err("Minimum quantity must be positive and no more than the maximum".to_string());
+        }
+        if spec.min_notional < Decimal::ZERO {
+            return Err("Minimum notional cannot be negative".to_string());
         }
 
-        if remaining_quantity > Decimal::ZERO {
-            order.status = OrderStatus::Partial;
-            order.remaining_quantity = remaining_quantity;
+        self.list_symbol(spec);
+        Ok(())
+    }
+
+    fn list_symbol(&mut self, mut spec: SymbolSpec) {
+        spec.id = self.symbol_specs.len() as u32 + 1;
+        let symbol = spec.symbol.clone();
+        self.order_books.insert(symbol.clone(), OrderBook::new(symbol.clone()));
+        self.stop_books.insert(symbol.clone(), StopBook::new(symbol.clone()));
+        self.symbol_specs.insert(symbol, spec);
+    }
+
+    pub fn get_symbol_spec(&self, symbol: &str) -> Option<SymbolSpec> {
+        self.symbol_specs.get(symbol).cloned()
+    }
+
+    pub fn get_symbol_specs(&self) -> Vec<SymbolSpec> {
+        let mut specs: Vec<SymbolSpec> = self.symbol_specs.values().cloned().collect();
+        specs.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        specs
+    }
+
+    /// Moves a symbol to another trading phase. Opening for continuous trading uncrosses whatever
+    /// the book has collected, and so does closing out of the closing auction, all at one price.
+    /// Any phase can be halted without uncrossing, and a halted symbol can go to any phase.
+    pub fn set_trading_status(&mut self, symbol: &str, status: TradingStatus) -> Result<(), String> {
+        self.change_trading_status(symbol, status)?;
+        self.finish_update(symbol);
+        Ok(())
+    }
+
+    /// `set_trading_status` without the follow-up, for changes made in the middle of a match.
+    fn change_trading_status(&mut self, symbol: &str, status: TradingStatus) -> Result<(), String> {
+        let current = self.symbol_specs.get(symbol)
+            .ok_or_else(|| "Symbol not supported".to_string())?
+            .status.clone();
+        if current == status {
+            return Ok(());
         }
 
+        let allowed = matches!((&current, &status),
+            (TradingStatus::Closed, TradingStatus::PreOpen)
+            | (TradingStatus::PreOpen, TradingStatus::Trading | TradingStatus::Closed)
+            | (TradingStatus::Trading, TradingStatus::ClosingAuction | TradingStatus::Closed)
+            | (TradingStatus::ClosingAuction, TradingStatus::Closed)
+            | (TradingStatus::Halted, _)
+            | (_, TradingStatus::Halted));
+        if !allowed {
+            return Err(format!("Symbol {} cannot move from {:?} to {:?}", symbol, current, status));
+        }
+
+        if status == TradingStatus::Trading
+            || (current == TradingStatus::ClosingAuction && status == TradingStatus::Closed) {
+            self.uncross(symbol)?;
+        }
+        self.symbol_specs.get_mut(symbol).unwrap().status = status;
         Ok(())
     }
 
-    pub fn process_limit_order_matching(&mut self, symbol: &str) -> Result<(), String> {
-        let order_book = self.order_books.get_mut(symbol)
-            .ok_or_else(|| "Symbol not found".to_string())?;
+    /// Executes every crossing order at the equilibrium price, with the last trade price as the
+    /// reference for ties.
+    fn uncross(&mut self, symbol: &str) -> Result<(), String> {
+        let reference_price = self.last_trade_prices.get(symbol).copied();
+        if let Some((price, _, _)) = self.order_books[symbol].equilibrium(reference_price) {
+            self.match_crossed_orders(symbol, Some(price))?;
+            self.reference_prices.insert(symbol.to_string(), price);
+        }
+        Ok(())
+    }
+
+    /// Where the symbol's call auction would uncross now, or None outside a call phase.
+    pub fn get_indicative_uncross(&self, symbol: &str) -> Option<IndicativeUncross> {
+        let status = self.symbol_specs.get(symbol)?.status.clone();
+        if !status.is_call_auction() {
+            return None;
+        }
+
+        let reference_price = self.last_trade_prices.get(symbol).copied();
+        let (price, buy_quantity, sell_quantity) = match self.order_books[symbol].equilibrium(reference_price) {
+            Some((price, buy_quantity, sell_quantity)) => (Some(price), buy_quantity, sell_quantity),
+            None => (None, Decimal::ZERO, Decimal::ZERO),
+        };
+        let imbalance_side = match buy_quantity.cmp(&sell_quantity) {
+            Ordering::Greater => Some(OrderSide::Buy),
+            Ordering::Less => Some(OrderSide::Sell),
+            Ordering::Equal => None,
+        };
+
+        Some(IndicativeUncross {
+            symbol: symbol.to_string(),
+            status,
+            price,
+            matched_quantity: buy_quantity.min(sell_quantity),
+            imbalance_quantity: (buy_quantity - sell_quantity).abs(),
+            imbalance_side,
+            timestamp: self.now(),
+        })
+    }
+
+    /// Streams the indicative uncross each time it changes during the symbol's call phases.
+    pub fn subscribe_indicative_uncross(&mut self, symbol: &str) -> Result<Receiver<IndicativeUncross>, String> {
+        if !self.order_books.contains_key(symbol) {
+            return Err("Symbol not supported".to_string());
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.uncross_subscribers.entry(symbol.to_string()).or_default().push(sender);
+        Ok(receiver)
+    }
+
+    fn publish_indicative_uncross(&mut self, symbol: &str) {
+        let indicative = match self.get_indicative_uncross(symbol) {
+            Some(indicative) => indicative,
+            None => return,
+        };
+        let unchanged = self.published_uncrosses.get(symbol).is_some_and(|published| {
+            *published == IndicativeUncross { timestamp: published.timestamp, ..indicative.clone() }
+        });
+        if unchanged {
+            return;
+        }
+
+        if let Some(subscribers) = self.uncross_subscribers.get_mut(symbol) {
+            subscribers.retain(|subscriber| subscriber.send(indicative.clone()).is_ok());
+        }
+        self.published_uncrosses.insert(symbol.to_string(), indicative);
+    }
+
+    /// Sets the maker and taker rates charged on the symbol's trades. Symbols without a schedule trade free.
+    pub fn set_fee_schedule(&mut self, symbol: &str, schedule: FeeSchedule) -> Result<(), String> {
+        if !self.order_books.contains_key(symbol) {
+            return Err("Symbol not supported".to_string());
+        }
+        if schedule.tiers.first().is_none_or(|tier| tier.min_volume != Decimal::ZERO) {
+            return Err("Fee schedule must start with a tier from zero volume".to_string());
+        }
+        if schedule.tiers.windows(2).any(|tiers| tiers[1].min_volume <= tiers[0].min_volume) {
+            return Err("Fee tiers must be in ascending order of volume".to_string());
+        }
+        let valid_rate = |rate: Decimal| rate >= Decimal::ZERO && rate < Decimal::ONE;
+        if !schedule.tiers.iter().all(|tier| valid_rate(tier.maker_rate) && valid_rate(tier.taker_rate)) {
+            return Err("Fee rates must be at least 0 and less than 1".to_string());
+        }
+
+        self.fee_schedules.insert(symbol.to_string(), schedule);
+        Ok(())
+    }
+
+    /// Account credited with every fee charged.
+    pub fn set_fee_account(&mut self, account: &str) {
+        self.fee_account = account.to_string();
+    }
+
+    pub fn get_fee_account(&self) -> &str {
+        &self.fee_account
+    }
+
+    pub fn set_price_bands(&mut self, symbol: &str, bands: PriceBands) -> Result<(), String> {
+        if !self.order_books.contains_key(symbol) {
+            return Err("Symbol not supported".to_string());
+        }
+        let valid_percent = |percent: Option<Decimal>| {
+            percent.is_none_or(|percent| percent > Decimal::ZERO && percent < Decimal::ONE_HUNDRED)
+        };
+        if !valid_percent(bands.static_percent) || !valid_percent(bands.dynamic_percent)
+            || !valid_percent(bands.volatility_percent) {
+            return Err("Band percentages must be between 0 and 100".to_string());
+        }
+        if bands.volatility_window_seconds < 0 || bands.halt_seconds < 0 || bands.auction_seconds < 0 {
+            return Err("Band durations cannot be negative".to_string());
+        }
+
+        self.price_bands.insert(symbol.to_string(), bands);
+        Ok(())
+    }
+
+    pub fn get_price_bands(&self, symbol: &str) -> Option<PriceBands> {
+        self.price_bands.get(symbol).cloned()
+    }
+
+    /// Sets the price the static band is measured from until the next auction sets a new one.
+    pub fn set_reference_price(&mut self, symbol: &str, price: Decimal) -> Result<(), String> {
+        if !self.order_books.contains_key(symbol) {
+            return Err("Symbol not supported".to_string());
+        }
+        if price <= Decimal::ZERO {
+            return Err("Reference price must be positive".to_string());
+        }
+        self.reference_prices.insert(symbol.to_string(), price);
+        Ok(())
+    }
+
+    pub fn get_reference_price(&self, symbol: &str) -> Option<Decimal> {
+        self.reference_prices.get(symbol).copied()
+    }
+
+    /// Halts the symbol until `resume_symbol`. Open orders stay on the book.
+    pub fn halt_symbol(&mut self, symbol: &str, reason: &str) -> Result<(), String> {
+        if reason.trim().is_empty() {
+            return Err("A halt needs a reason".to_string());
+        }
+        if self.get_active_halt(symbol).is_some() {
+            return Err(format!("Symbol {} is already halted", symbol));
+        }
+        self.halt(symbol, HaltSource::Operator, reason, None)?;
+        self.finish_update(symbol);
+        Ok(())
+    }
+
+    /// Ends a halt, whatever started it, with a reopening auction for the symbol's configured
+    /// auction time. Without one the book uncrosses straight away.
+    pub fn resume_symbol(&mut self, symbol: &str, reason: &str) -> Result<(), String> {
+        if reason.trim().is_empty() {
+            return Err("A resumption needs a reason".to_string());
+        }
+        let now = self.now();
+        let halt = self.trading_halts.get_mut(symbol)
+            .and_then(|halts| halts.last_mut())
+            .filter(|halt| halt.resumed_at.is_none() && halt.reopen_at.is_none())
+            .ok_or_else(|| format!("Symbol {} is not halted", symbol))?;
+        halt.resume_at = Some(now);
+        halt.resume_reason = Some(reason.to_string());
+        self.process_trading_halts(symbol)
+    }
+
+    /// The symbol's halt that has not resumed yet, including one in its reopening auction.
+    pub fn get_active_halt(&self, symbol: &str) -> Option<TradingHalt> {
+        self.trading_halts.get(symbol)
+            .and_then(|halts| halts.last())
+            .filter(|halt| halt.resumed_at.is_none())
+            .cloned()
+    }
+
+    pub fn get_trading_halts(&self, symbol: &str) -> Vec<TradingHalt> {
+        self.trading_halts.get(symbol).cloned().unwrap_or_default()
+    }
+
+    /// Halts the symbol and records why. Operator halts and volatility interruptions both come
+    /// through here; the caller runs `finish_update` once it is done with the book.
+    fn halt(&mut self, symbol: &str, source: HaltSource, reason: &str,
+            resume_at: Option<DateTime<Utc>>) -> Result<(), String> {
+        self.change_trading_status(symbol, TradingStatus::Halted)?;
+        let halt = TradingHalt {
+            symbol: symbol.to_string(),
+            source,
+            reason: reason.to_string(),
+            halted_at: self.now(),
+            resume_at,
+            reopen_at: None,
+            resumed_at: None,
+            resume_reason: None,
+        };
+        self.trading_halts.entry(symbol.to_string()).or_default().push(halt);
+        Ok(())
+    }
+
+    /// Moves an active halt along once its times are due: into the reopening auction at
+    /// `resume_at`, and back to continuous trading through the uncross at `reopen_at`.
+    fn process_trading_halts(&mut self, symbol: &str) -> Result<(), String> {
+        let now = self.now();
+        let halt = match self.get_active_halt(symbol) {
+            Some(halt) => halt,
+            None => return Ok(()),
+        };
+
+        let mut reopen_at = halt.reopen_at;
+        if reopen_at.is_none() && halt.resume_at.is_some_and(|resume_at| resume_at <= now) {
+            let auction_seconds = self.price_bands.get(symbol).map_or(0, |bands| bands.auction_seconds);
+            reopen_at = Some(halt.resume_at.unwrap() + Duration::seconds(auction_seconds));
+            self.active_halt_mut(symbol).reopen_at = reopen_at;
+            self.set_trading_status(symbol, TradingStatus::PreOpen)?;
+        }
+        if reopen_at.is_some_and(|reopen_at| reopen_at <= now) {
+            self.set_trading_status(symbol, TradingStatus::Trading)?;
+            self.active_halt_mut(symbol).resumed_at = Some(now);
+        }
+        Ok(())
+    }
+
+    fn active_halt_mut(&mut self, symbol: &str) -> &mut TradingHalt {
+        self.trading_halts.get_mut(symbol).and_then(|halts| halts.last_mut()).unwrap()
+    }
+
+    /// Bands a limit price has to fall within, as (name, low, high).
+    fn price_band_limits(&self, symbol: &str) -> Vec<(&'static str, Decimal, Decimal)> {
+        let bands = match self.price_bands.get(symbol) {
+            Some(bands) => bands,
+            None => return Vec::new(),
+        };
+        let band = |percent: Decimal, price: Decimal| {
+            let width = price * percent / Decimal::ONE_HUNDRED;
+            (price - width, price + width)
+        };
+
+        let mut limits = Vec::new();
+        if let (Some(percent), Some(price)) = (bands.static_percent, self.reference_prices.get(symbol)) {
+            let (low, high) = band(percent, *price);
+            limits.push(("static", low, high));
+        }
+        if let (Some(percent), Some(price)) = (bands.dynamic_percent, self.last_trade_prices.get(symbol)) {
+            let (low, high) = band(percent, *price);
+            limits.push(("dynamic", low, high));
+        }
+        limits
+    }
+
+    fn validate_price_bands(&self, symbol: &str, price: Decimal) -> Result<(), String> {
+        for (name, low, high) in self.price_band_limits(symbol) {
+            if price < low || price > high {
+                return Err(format!("Price {} is outside the {} band {} to {} for {}",
+                                   price.normalize(), name, low.normalize(), high.normalize(), symbol));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a trade may print at `price`. Prices outside the bands stop the match; a move
+    /// larger than the volatility limit within its window halts the symbol instead.
+    fn allow_trade_price(&mut self, symbol: &str, price: Decimal) -> bool {
+        if self.validate_price_bands(symbol, price).is_err() {
+            return false;
+        }
+        let bands = match self.price_bands.get(symbol) {
+            Some(bands) => bands.clone(),
+            None => return true,
+        };
+        let percent = match bands.volatility_percent {
+            Some(percent) => percent,
+            None => return true,
+        };
+
+        let now = self.now();
+        let cutoff = now - Duration::seconds(bands.volatility_window_seconds);
+        let (low, high) = match self.price_ranges.get_mut(symbol).and_then(|window| window.range_since(cutoff)) {
+            Some(range) => range,
+            None => return true,
+        };
+        let limit = percent / Decimal::ONE_HUNDRED;
+        if price <= low * (Decimal::ONE + limit) && price >= high * (Decimal::ONE - limit) {
+            return true;
+        }
+
+        let reason = format!("Volatility interruption: {} moved more than {}% from the {} to {} range of the last {}s",
+                             price.normalize(), percent.normalize(), low.normalize(), high.normalize(),
+                             bands.volatility_window_seconds);
+        let _ = self.halt(symbol, HaltSource::VolatilityInterruption, &reason,
+                          Some(now + Duration::seconds(bands.halt_seconds)));
+        false
+    }
+
+    /// Quote currency the trader has traded on the symbol over the last 30 days.
+    pub fn get_trading_volume(&self, trader: &str, symbol: &str) -> Decimal {
+        let cutoff = self.now() - Duration::days(FEE_VOLUME_DAYS);
+        self.trade_volumes
+            .get(&(trader.to_string(), symbol.to_string()))
+            .map(|window| window.volume_since(cutoff))
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Maker and taker rates the trader currently pays on the symbol.
+    pub fn get_fee_rates(&self, trader: &str, symbol: &str) -> (Decimal, Decimal) {
+        self.fee_schedules
+            .get(symbol)
+            .and_then(|schedule| schedule.tier_for(self.get_trading_volume(trader, symbol)))
+            .map(|tier| (tier.maker_rate, tier.taker_rate))
+            .unwrap_or((Decimal::ZERO, Decimal::ZERO))
+    }
+
+    fn fee_rate(&self, trader: &str, symbol: &str, liquidity: &Liquidity) -> Decimal {
+        let (maker_rate, taker_rate) = self.get_fee_rates(trader, symbol);
+        match liquidity {
+            Liquidity::Maker => maker_rate,
+            Liquidity::Taker => taker_rate,
+        }
+    }
+
+    /// Sets how the trader's orders avoid trading with each other when an order does not say
+    /// otherwise. `None` lets them trade.
+    pub fn set_self_trade_prevention(&mut self, trader: &str, mode: Option<SelfTradePrevention>) {
+        match mode {
+            Some(mode) => self.self_trade_prevention.insert(trader.to_string(), mode),
+            None => self.self_trade_prevention.remove(trader),
+        };
+    }
+
+    pub fn place_order(&mut self, request: OrderRequest) -> Result<Order, String> {
+        self.submit_order(request, None)
+    }
+
+    /// Places a stop whose trigger level follows the market by `offset`: below the highest trade
+    /// price seen since placement for sells, above the lowest for buys. It fires as a market order,
+    /// or as a limit order `limit_offset` beyond the trigger level when one is given.
+    /// The request must be a trailing stop, or a trailing stop-limit when `limit_offset` is given,
+    /// and carries no prices of its own.
+    pub fn place_trailing_stop_order(&mut self, mut request: OrderRequest, offset: TrailingOffset,
+                                     limit_offset: Option<Decimal>) -> Result<Order, String> {
+        let order_type = if limit_offset.is_some() { OrderType::TrailingStopLimit } else { OrderType::TrailingStop };
+        if request.order_type != order_type {
+            return Err("Trailing stop-limit orders need a limit offset and trailing stops must not have one".to_string());
+        }
+        if request.price.is_some() || request.stop_price.is_some() {
+            return Err("Trailing stop prices follow the market and cannot be set".to_string());
+        }
+        let best_price = self.last_trade_prices.get(&request.symbol)
+            .copied()
+            .ok_or_else(|| "No trade price to trail yet".to_string())?;
+
+        match offset {
+            TrailingOffset::Amount(amount) if amount <= Decimal::ZERO => {
+                return Err("Trailing amount must be positive".to_string());
+            }
+            TrailingOffset::Percent(percent) if percent <= Decimal::ZERO || percent >= Decimal::ONE_HUNDRED => {
+                return Err("Trailing percent must be between 0 and 100".to_string());
+            }
+            _ => {}
+        }
+        if limit_offset.is_some_and(|limit_offset| limit_offset < Decimal::ZERO) {
+            return Err("Limit offset cannot be negative".to_string());
+        }
+        if let (Some(limit_offset), Some(spec)) = (limit_offset, self.symbol_specs.get(&request.symbol)) {
+            if limit_offset % spec.tick_size != Decimal::ZERO {
+                return Err(format!("Limit offset {} is not a multiple of the tick size {} for {}",
+                                   limit_offset.normalize(), spec.tick_size.normalize(), request.symbol));
+            }
+        }
+
+        let trailing = TrailingStop { offset, limit_offset, best_price };
+        request.stop_price = Some(self.round_stop_price(&request.symbol, &request.side,
+                                                        trailing.stop_price(&request.side)));
+        self.submit_order(request, Some(trailing))
+    }
+
+    /// Places two orders on the same side for the same size where one filling cancels the other.
+    /// A partial fill on either leg shrinks the other to what is left, and the legs share one hold.
+    /// The request is the first leg; the second takes everything but its type and prices from it.
+    pub fn place_oco_order(&mut self, request: OrderRequest, second: OrderLeg) -> Result<OrderGroup, String> {
+        if request.time_in_force == TimeInForce::IOC || request.time_in_force == TimeInForce::FOK {
+            return Err("One-cancels-other legs must be able to rest".to_string());
+        }
+        if request.instructions != ExecInstructions::default() {
+            return Err("Grouped orders cannot carry execution instructions".to_string());
+        }
+
+        let trader = request.trader.clone();
+        let symbol = request.symbol.clone();
+        let second = OrderRequest {
+            order_type: second.order_type,
+            price: second.price,
+            stop_price: second.stop_price,
+            ..request.clone()
+        };
+        let mut legs = Vec::new();
+        for leg in [request, second] {
+            if !matches!(leg.order_type, OrderType::Limit | OrderType::Stop | OrderType::StopLimit) {
+                return Err("One-cancels-other legs must be limit, stop or stop-limit orders".to_string());
+            }
+            legs.push(self.prepare_order(leg, None)?);
+        }
+
+        // The legs never fill more than the group's size between them, so the larger hold covers both
+        let hold_currency = self.hold_currency(&legs[0]);
+        let hold_amount = legs.iter().map(|leg| self.initial_hold(leg).1).max().unwrap_or_default();
+        self.lock_balance(&trader, &hold_currency, hold_amount)?;
+
+        let group_id = self.register_order_group(OrderGroupType::OneCancelsOther, legs, false, hold_amount);
+        let order_ids = self.order_groups[&group_id].order_ids.clone();
+        self.activate_grouped_orders(&order_ids);
+        self.finish_update(&symbol);
+        Ok(self.order_groups[&group_id].clone())
+    }
+
+    /// Places an entry order together with a take-profit limit and a stop-loss stop on the other
+    /// side. The exits stay inactive until the entry is done, then go live as a one-cancels-other
+    /// pair for whatever the entry filled.
+    pub fn place_bracket_order(&mut self, entry: OrderRequest, take_profit_price: Decimal,
+                               stop_loss_price: Decimal) -> Result<OrderGroup, String> {
+        if matches!(entry.order_type, OrderType::TrailingStop | OrderType::TrailingStopLimit) {
+            return Err("Bracket entries must be market, limit, stop or stop-limit orders".to_string());
+        }
+        if entry.instructions != ExecInstructions::default() {
+            return Err("Grouped orders cannot carry execution instructions".to_string());
+        }
+
+        // The take-profit sits on the profitable side of the entry and the stop-loss on the other
+        let (low_price, high_price) = match entry.side {
+            OrderSide::Buy => (stop_loss_price, take_profit_price),
+            OrderSide::Sell => (take_profit_price, stop_loss_price),
+        };
+        let entry_price = entry.price.or(entry.stop_price);
+        if low_price <= Decimal::ZERO || low_price >= high_price
+            || entry_price.is_some_and(|price| price <= low_price || price >= high_price) {
+            return Err("Take-profit and stop-loss prices must be on either side of the entry".to_string());
+        }
+
+        let symbol = entry.symbol.clone();
+        let take_profit = OrderRequest::new(entry.trader.clone(), symbol.clone(), entry.side.opposite(), OrderType::Limit,
+                                            entry.quantity, Some(take_profit_price), None);
+        let stop_loss = OrderRequest::new(entry.trader.clone(), symbol.clone(), entry.side.opposite(), OrderType::Stop,
+                                          entry.quantity, None, Some(stop_loss_price));
+        let mut entry_order = self.prepare_order(entry, None)?;
+        let mut take_profit = self.prepare_order(take_profit, None)?;
+        let mut stop_loss = self.prepare_order(stop_loss, None)?;
+        let now = self.now();
+        take_profit.set_status(OrderStatus::Inactive, now);
+        stop_loss.set_status(OrderStatus::Inactive, now);
+        self.lock_initial_hold(&mut entry_order)?;
+
+        let group_id = self.register_order_group(OrderGroupType::Bracket, vec![entry_order, take_profit, stop_loss],
+                                                 true, Decimal::ZERO);
+        let entry_order_id = self.order_groups[&group_id].entry_order_id.clone().unwrap();
+        self.activate_grouped_orders(&[entry_order_id]);
+        self.finish_update(&symbol);
+        Ok(self.order_groups[&group_id].clone())
+    }
+
+    /// Cancels every open order in a group, including bracket exits that never went live.
+    pub fn cancel_order_group(&mut self, group_id: &str, trader: &str) -> Result<OrderGroup, String> {
+        let now = self.now();
+        let group = self.order_groups.get_mut(group_id)
+            .ok_or_else(|| "Order group not found".to_string())?;
+
+        if group.trader != trader {
+            return Err("Unauthorized".to_string());
+        }
+
+        if group.status != OrderGroupStatus::Active {
+            return Err("Order group cannot be cancelled".to_string());
+        }
+
+        group.status = OrderGroupStatus::Cancelled;
+        group.updated_at = now;
+        let order_ids = group.order_ids.clone();
+        let symbol = group.symbol.clone();
+
+        for order_id in &order_ids {
+            self.cancel_open_order(order_id);
+        }
+        self.finish_update(&symbol);
+        Ok(self.order_groups[group_id].clone())
+    }
+
+    pub fn get_order_group(&self, group_id: &str) -> Option<OrderGroup> {
+        self.order_groups.get(group_id).cloned()
+    }
+
+    pub fn get_group_orders(&self, group_id: &str) -> Vec<Order> {
+        self.order_groups.get(group_id)
+            .map(|group| group.order_ids.iter().filter_map(|order_id| self.orders.get(order_id)).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Gives grouped orders their ids and adds them to `orders` under a new group, without
+    /// sending any of them to the market yet.
+    fn register_order_group(&mut self, group_type: OrderGroupType, mut orders: Vec<Order>, has_entry: bool,
+                            locked_amount: Decimal) -> String {
+        self.group_counter += 1;
+        let group_id = format!("{}group_{}", self.id_prefix, self.group_counter);
+        for order in &mut orders {
+            order.id = self.next_order_id();
+            order.group_id = Some(group_id.clone());
+        }
+
+        let now = self.now();
+        let group = OrderGroup {
+            id: group_id.clone(),
+            group_type,
+            trader: orders[0].trader.clone(),
+            symbol: orders[0].symbol.clone(),
+            order_ids: orders.iter().map(|order| order.id.clone()).collect(),
+            entry_order_id: if has_entry { Some(orders[0].id.clone()) } else { None },
+            quantity: orders[0].quantity,
+            locked_amount,
+            status: OrderGroupStatus::Active,
+            created_at: now,
+            updated_at: now,
+        };
+        self.order_groups.insert(group_id.clone(), group);
+
+        for order in orders {
+            self.orders.insert(order.id.clone(), order);
+        }
+        group_id
+    }
+
+    /// Routes grouped orders one at a time, letting the group react to each before the next goes live.
+    fn activate_grouped_orders(&mut self, order_ids: &[String]) {
+        for order_id in order_ids {
+            self.process_group_events();
+            let mut order = match self.orders.remove(order_id) {
+                Some(order) => order,
+                None => continue,
+            };
+            if order.status == OrderStatus::Pending {
+                let _ = self.route_order(&mut order);
+            }
+            self.orders.insert(order_id.clone(), order);
+        }
+    }
+
+    fn submit_order(&mut self, request: OrderRequest, trailing: Option<TrailingStop>) -> Result<Order, String> {
+        let mut order = self.prepare_order(request, trailing)?;
+
+        self.lock_initial_hold(&mut order)?;
+
+        order.id = self.next_order_id();
+        let order_id = order.id.clone();
+        let symbol = order.symbol.clone();
+        self.route_order(&mut order)?;
+
+        self.orders.insert(order_id.clone(), order);
+        self.finish_update(&symbol);
+        Ok(self.orders[&order_id].clone())
+    }
+
+    /// Validates a new order and builds it, without an id and before anything is locked.
+    fn prepare_order(&self, mut request: OrderRequest, trailing: Option<TrailingStop>) -> Result<Order, String> {
+        let OrderRequest { ref trader, ref symbol, ref side, ref order_type, ref time_in_force, .. } = request;
+        if !self.order_books.contains_key(symbol) {
+            return Err("Symbol not supported".to_string());
+        }
+
+        // Validate order parameters
+        self.validate_order(order_type, request.price, request.stop_price)?;
+        self.validate_symbol_spec(symbol, side, request.quantity, request.price, request.stop_price)?;
+        let status = &self.symbol_specs[symbol].status;
+        if status.is_call_auction() && (*order_type == OrderType::Market
+            || *time_in_force == TimeInForce::IOC || *time_in_force == TimeInForce::FOK) {
+            return Err(format!("Only orders that can rest are accepted while {} is in {:?}", symbol, status));
+        }
+        if trailing.is_none() && (*order_type == OrderType::TrailingStop || *order_type == OrderType::TrailingStopLimit) {
+            return Err("Trailing stop orders must be placed with place_trailing_stop_order".to_string());
+        }
+        self.validate_time_in_force(time_in_force, request.expire_at)?;
+        self.validate_instructions(&request.instructions, order_type, time_in_force, request.quantity)?;
+
+        // Post-only orders must not take liquidity: reject them or slide them behind the opposite side
+        let mut price = request.price;
+        if let (Some(post_only), OrderType::Limit) = (&request.instructions.post_only, order_type) {
+            price = Some(self.post_only_price(symbol, side, price.unwrap(), post_only)?);
+        }
+
+        // Reduce-only orders must trade against the trader's position and are capped at its size
+        let mut quantity = request.quantity;
+        if request.instructions.reduce_only {
+            let reducible = self.reducible_quantity(trader, symbol, side);
+            if reducible <= Decimal::ZERO {
+                return Err("Reduce-only order would increase the position".to_string());
+            }
+            quantity = quantity.min(reducible);
+        }
+
+        request.price = price;
+        request.quantity = quantity;
+        let mut order = Order::new(String::new(), request, self.now());
+        order.trailing = trailing;
+
+        // Fill-or-kill orders are checked against the book before anything executes
+        if order.time_in_force == TimeInForce::FOK && matches!(order.order_type, OrderType::Market | OrderType::Limit) {
+            let limit_price = if order.order_type == OrderType::Limit { order.price } else { None };
+            if self.fillable_quantity(&order, limit_price) < order.quantity {
+                return Err("Fill-or-kill order cannot be filled in full".to_string());
+            }
+        }
+        Ok(order)
+    }
+
+    /// Hold a new order takes: base currency for sells, quote currency at the limit price for buys.
+    /// Market buys hold what they could spend on the book, stop buys take theirs on trigger.
+    fn initial_hold(&self, order: &Order) -> (String, Decimal) {
+        let hold_currency = self.hold_currency(order);
+        let hold_amount = match (&order.side, &order.order_type) {
+            (OrderSide::Sell, _) => order.quantity,
+            (OrderSide::Buy, OrderType::Limit | OrderType::StopLimit) => order.price.unwrap() * order.quantity,
+            (OrderSide::Buy, OrderType::Market) => self.market_buy_hold(order),
+            (OrderSide::Buy, OrderType::Stop | OrderType::TrailingStop | OrderType::TrailingStopLimit) => Decimal::ZERO,
+        };
+        (hold_currency, hold_amount)
+    }
+
+    /// Most a buy without a limit price can spend: its remaining quantity at the worst ask it
+    /// could reach, walking the book no further than the upper price band, and no more than
+    /// the trader has available.
+    fn market_buy_hold(&self, order: &Order) -> Decimal {
+        let available = self.get_user_balance(&order.trader, &self.hold_currency(order));
+        let band_high = self.price_band_limits(&order.symbol).into_iter().map(|(_, _, high)| high).min();
+        let mut unfilled = order.remaining_quantity;
+        let mut worst_price = None;
+        for (price, level) in &self.order_books[&order.symbol].asks {
+            if unfilled <= Decimal::ZERO || band_high.is_some_and(|high| *price > high) {
+                break;
+            }
+            worst_price = Some(*price);
+            unfilled -= level.total_quantity();
+        }
+        worst_price.map_or(Decimal::ZERO, |price| (price * order.remaining_quantity).min(available))
+    }
+
+    fn lock_initial_hold(&mut self, order: &mut Order) -> Result<(), String> {
+        let (hold_currency, hold_amount) = self.initial_hold(order);
+        let balance = self.get_user_balance(&order.trader, &hold_currency);
+        if balance < hold_amount || (order.order_type == OrderType::Market && balance == Decimal::ZERO) {
+            return Err("Insufficient balance".to_string());
+        }
+        self.lock_balance(&order.trader, &hold_currency, hold_amount)?;
+        order.locked_amount = hold_amount;
+        Ok(())
+    }
+
+    fn next_order_id(&mut self) -> String {
+        self.order_counter += 1;
+        format!("{}order_{}", self.id_prefix, self.order_counter)
+    }
+
+    /// Sends a live order on its way: market orders execute, limit orders match and rest, and
+    /// stop orders wait off-book until the last trade price crosses their stop price.
+    fn route_order(&mut self, order: &mut Order) -> Result<(), String> {
+        match order.order_type {
+            OrderType::Market => self.process_market_order(order),
+            OrderType::Stop | OrderType::StopLimit | OrderType::TrailingStop | OrderType::TrailingStopLimit => {
+                self.stop_books.get_mut(&order.symbol).unwrap().add_order(order);
+                Ok(())
+            }
+            OrderType::Limit => self.process_limit_order(order),
+        }
+    }
+
+    fn process_market_order(&mut self, order: &mut Order) -> Result<(), String> {
+        if !self.order_books.contains_key(&order.symbol) {
+            return Err("Symbol not found".to_string());
+        }
+
+        if self.is_matching(&order.symbol) {
+            self.match_incoming_order(order, None)?;
+        }
+
+        // Market orders never rest, so whatever could not be matched is cancelled
+        self.cancel_unfilled_remainder(order);
+        Ok(())
+    }
+
+    /// Matches an incoming limit order against the opposite side at the resting orders' prices,
+    /// then rests whatever is left (or cancels it for IOC/FOK).
+    fn process_limit_order(&mut self, order: &mut Order) -> Result<(), String> {
+        // Outside continuous trading orders only join the book
+        if self.is_matching(&order.symbol) {
+            self.match_incoming_order(order, order.price)?;
+        }
+        if !order.is_open() {
+            // Cancelled to prevent a self-trade
+            return Ok(());
+        }
+
+        match order.time_in_force {
+            TimeInForce::IOC | TimeInForce::FOK => self.cancel_unfilled_remainder(order),
+            // A reduce-only order that has already closed out its position has nothing left to do
+            _ if order.instructions.reduce_only && self.max_fill_quantity(order) <= Decimal::ZERO => {
+                self.cancel_unfilled_remainder(order);
+            }
+            // Add the unfilled remainder to the order book
+            TimeInForce::GTC | TimeInForce::GTD => {
+                if order.remaining_quantity > Decimal::ZERO {
+                    order.refresh_display();
+                    self.order_books.get_mut(&order.symbol).unwrap().add_order(order);
+                    self.publish_order_event(OrderEvent::for_order(OrderEventKind::Add, order, order.remaining_quantity));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_matching(&self, symbol: &str) -> bool {
+        self.symbol_specs.get(symbol).is_some_and(|spec| spec.status == TradingStatus::Trading)
+    }
+
+    fn cancel_unfilled_remainder(&mut self, order: &mut Order) {
+        if order.is_open() && order.remaining_quantity > Decimal::ZERO {
+            self.close_order(order, OrderStatus::Cancelled);
+        }
+    }
+
+    /// Moves an order to a final status and gives back whatever it still has locked.
+    fn close_order(&mut self, order: &mut Order, status: OrderStatus) {
+        let kind = if status == OrderStatus::Expired { OrderEventKind::Expire } else { OrderEventKind::Cancel };
+        order.set_status(status, self.now());
+        self.publish_order_event(OrderEvent::for_order(kind, order, order.remaining_quantity));
+        self.release_excess_hold(order);
+        if order.group_id.is_some() {
+            self.group_events.push_back(order.id.clone());
+        }
+    }
+
+    /// Matches an incoming order against the opposite side of the book, best price first and
+    /// oldest first within a price, never trading through `limit_price`.
+    fn match_incoming_order(&mut self, order: &mut Order, limit_price: Option<Decimal>) -> Result<(), String> {
+        let resting_side = order.side.opposite();
+
+        while order.is_open() && order.remaining_quantity > Decimal::ZERO {
+            let order_book = self.order_books.get(&order.symbol).unwrap();
+            let (price, resting_order_id) = match order_book.get_best_order(&resting_side) {
+                Some((price, order_id)) if limit_price.is_none_or(|limit| match order.side {
+                    OrderSide::Buy => price <= limit,
+                    OrderSide::Sell => price >= limit,
+                }) => (price, order_id),
+                // No more orders available within the limit
+                _ => break,
+            };
+
+            let mut match_quantity = self.max_fill_quantity(order);
+            if order.side == OrderSide::Buy {
+                // Never buy more than the order's hold can pay for
+                let affordable = self.symbol_specs[&order.symbol].round_quantity(self.available_hold(order) / price);
+                match_quantity = match_quantity.min(affordable);
+            }
+            if match_quantity <= Decimal::ZERO {
+                break;
+            }
+
+            let mut resting_order = self.orders.remove(&resting_order_id).unwrap();
+            if resting_order.trader == order.trader {
+                if let Some(mode) = self.self_trade_prevention_mode(order) {
+                    self.prevent_self_trade(order, &mut resting_order, &mode);
+                    self.sync_resting_order(resting_order);
+                    continue;
+                }
+            }
+
+            let mut resting_quantity = self.max_fill_quantity(&resting_order);
+            if resting_order.is_iceberg() {
+                // Only the displayed slice trades before the iceberg refreshes
+                resting_quantity = resting_quantity.min(resting_order.display_remaining);
+            }
+            if resting_quantity <= Decimal::ZERO {
+                // A resting reduce-only order whose position is already closed, or a group leg
+                // whose siblings have used up the group's size
+                self.close_order(&mut resting_order, OrderStatus::Cancelled);
+                self.sync_resting_order(resting_order);
+                continue;
+            }
+            let match_quantity = match_quantity.min(resting_quantity);
+
+            // Only a trade that would actually print is held to the bands
+            if !self.allow_trade_price(&order.symbol, price) {
+                self.sync_resting_order(resting_order);
+                break;
+            }
+            match order.side {
+                OrderSide::Buy => self.execute_trade(order, &mut resting_order, price, match_quantity, OrderSide::Buy),
+                OrderSide::Sell => self.execute_trade(&mut resting_order, order, price, match_quantity, OrderSide::Sell),
+            }
+            self.sync_resting_order(resting_order);
+        }
+
+        Ok(())
+    }
+
+    /// How much of an order may still trade: its remaining quantity, capped by the trader's
+    /// position for reduce-only orders and by what is left of the group's size for group exits.
+    fn max_fill_quantity(&self, order: &Order) -> Decimal {
+        let mut quantity = order.remaining_quantity;
+        if order.instructions.reduce_only {
+            quantity = quantity.min(self.reducible_quantity(&order.trader, &order.symbol, &order.side));
+        }
+        if let Some(group) = self.shared_hold_group(order) {
+            quantity = quantity.min(group.quantity - self.group_filled_quantity(group, order));
+        }
+        quantity
+    }
+
+    /// Size of the trader's position that an order on `side` would reduce.
+    fn reducible_quantity(&self, trader: &str, symbol: &str, side: &OrderSide) -> Decimal {
+        let position = self.get_position(trader, symbol);
+        match side {
+            OrderSide::Buy => (-position).max(Decimal::ZERO),
+            OrderSide::Sell => position.max(Decimal::ZERO),
+        }
+    }
+
+    /// Writes a resting order that was just matched back to `orders` and brings its book entry
+    /// in line, dropping it from the book once it is filled. An iceberg whose slice is used up
+    /// shows a new slice from its reserve and goes to the back of the queue.
+    fn sync_resting_order(&mut self, mut order: Order) {
+        if let Some(order_book) = self.order_books.get_mut(&order.symbol) {
+            if order.is_iceberg() && order.is_open() && order.display_remaining <= Decimal::ZERO
+                && order.remaining_quantity > Decimal::ZERO {
+                order.refresh_display();
+                order_book.remove_order(&order.id);
+                order_book.add_order(&order);
+
+                let mut requeued = OrderEvent::for_order(OrderEventKind::Modify, &order, order.remaining_quantity);
+                requeued.kept_priority = false;
+                self.publish_order_event(requeued);
+                self.publish_order_event(OrderEvent::for_order(OrderEventKind::Add, &order, order.remaining_quantity));
+            } else {
+                order_book.update_order(&order);
+            }
+        }
+        self.orders.insert(order.id.clone(), order);
+    }
+
+    fn self_trade_prevention_mode(&self, order: &Order) -> Option<SelfTradePrevention> {
+        order.instructions.self_trade_prevention.clone()
+            .or_else(|| self.self_trade_prevention.get(&order.trader).cloned())
+    }
+
+    /// Quantity `order` could take from the book without trading through `limit_price`. Unlike
+    /// `OrderBook::fillable_quantity` this follows the order's self-trade prevention: the
+    /// trader's own resting orders are skipped when they would be cancelled, and end the fill
+    /// when the incoming order would be cancelled or decremented instead.
+    fn fillable_quantity(&self, order: &Order, limit_price: Option<Decimal>) -> Decimal {
+        let order_book = &self.order_books[&order.symbol];
+        let levels: Vec<(&Decimal, &PriceLevel)> = match order.side {
+            OrderSide::Buy => order_book.asks.iter().collect(),
+            OrderSide::Sell => order_book.bids.iter().rev().collect(),
+        };
+        let mode = self.self_trade_prevention_mode(order);
+
+        let mut fillable = Decimal::ZERO;
+        for (price, level) in levels {
+            let through_limit = limit_price.is_some_and(|limit| match order.side {
+                OrderSide::Buy => *price > limit,
+                OrderSide::Sell => *price < limit,
+            });
+            if through_limit {
+                break;
+            }
+            for resting in level.orders.values() {
+                let own = self.orders.get(&resting.order_id).is_some_and(|resting| resting.trader == order.trader);
+                match (own, &mode) {
+                    (true, Some(SelfTradePrevention::CancelOldest)) => {}
+                    (true, Some(_)) => return fillable,
+                    _ => fillable += resting.quantity + resting.hidden_quantity,
+                }
+            }
+        }
+        fillable
+    }
+
+    /// Stops `newest` from trading against `oldest`, an order from the same trader.
+    fn prevent_self_trade(&mut self, newest: &mut Order, oldest: &mut Order, mode: &SelfTradePrevention) {
+        match mode {
+            SelfTradePrevention::CancelNewest => self.cancel_for_self_trade(newest),
+            SelfTradePrevention::CancelOldest => self.cancel_for_self_trade(oldest),
+            SelfTradePrevention::CancelBoth => {
+                self.cancel_for_self_trade(newest);
+                self.cancel_for_self_trade(oldest);
+            }
+            SelfTradePrevention::DecrementAndCancel => {
+                let quantity = newest.remaining_quantity.min(oldest.remaining_quantity);
+                for order in [newest, oldest] {
+                    order.quantity -= quantity;
+                    order.remaining_quantity -= quantity;
+                    order.display_remaining = order.display_remaining.min(order.remaining_quantity);
+                    order.self_trade_prevented += quantity;
+                    if order.remaining_quantity <= Decimal::ZERO {
+                        self.close_order(order, OrderStatus::Cancelled);
+                    } else {
+                        order.updated_at = self.now();
+                        self.release_excess_hold(order);
+                        self.publish_order_event(OrderEvent::for_order(OrderEventKind::Modify, order, order.remaining_quantity));
+                    }
+                }
+            }
+        }
+    }
+
+    fn cancel_for_self_trade(&mut self, order: &mut Order) {
+        order.self_trade_prevented += order.remaining_quantity;
+        self.close_order(order, OrderStatus::Cancelled);
+    }
+
+    pub fn process_limit_order_matching(&mut self, symbol: &str) -> Result<(), String> {
+        if !self.is_matching(symbol) {
+            return Err(format!("Symbol {} is not in continuous trading", symbol));
+        }
+        self.match_crossed_orders(symbol, None)?;
+        self.finish_update(symbol);
+        Ok(())
+    }
+
+    /// Trades crossed orders against each other in price-time priority. Each match takes the
+    /// price of whichever order joined the book first, or the single `auction_price` in an
+    /// uncross, which only matches orders willing to trade at it.
+    fn match_crossed_orders(&mut self, symbol: &str, auction_price: Option<Decimal>) -> Result<(), String> {
+        // Match buy and sell orders
+        loop {
+            let order_book = self.order_books.get_mut(symbol)
+                .ok_or_else(|| "Symbol not found".to_string())?;
+
+            let (bid_price, ask_price) = match (order_book.get_best_bid(), order_book.get_best_ask()) {
+                (Some(bid_price), Some(ask_price)) if bid_price >= ask_price
+                    && auction_price.is_none_or(|price| bid_price >= price && ask_price <= price) => (bid_price, ask_price),
+                _ => break, // No more matches possible
+            };
+
+            let (_, buy_order_id) = order_book.get_best_order(&OrderSide::Buy).unwrap();
+            let (_, sell_order_id) = order_book.get_best_order(&OrderSide::Sell).unwrap();
+
+            // The order that joined the book first sets the price
+            let buy_sequence = order_book.get_order_location(&buy_order_id).unwrap().sequence;
+            let sell_sequence = order_book.get_order_location(&sell_order_id).unwrap().sequence;
+            let (match_price, taker_side) = if buy_sequence < sell_sequence {
+                (auction_price.unwrap_or(bid_price), OrderSide::Sell)
+            } else {
+                (auction_price.unwrap_or(ask_price), OrderSide::Buy)
+            };
+            let mut buy_order = self.orders.remove(&buy_order_id).unwrap();
+            let mut sell_order = self.orders.remove(&sell_order_id).unwrap();
+
+            // The order that joined the book last decides how a self-trade is prevented
+            if buy_order.trader == sell_order.trader {
+                let (newest, oldest) = match taker_side {
+                    OrderSide::Buy => (&mut buy_order, &mut sell_order),
+                    OrderSide::Sell => (&mut sell_order, &mut buy_order),
+                };
+                if let Some(mode) = self.self_trade_prevention_mode(newest) {
+                    self.prevent_self_trade(newest, oldest, &mode);
+                    self.sync_resting_order(buy_order);
+                    self.sync_resting_order(sell_order);
+                    continue;
+                }
+            }
+
+            let match_quantity = self.max_fill_quantity(&buy_order).min(self.max_fill_quantity(&sell_order));
+
+            // Drop orders that may not fill any more (closed reduce-only positions, used-up groups) and look again
+            if match_quantity <= Decimal::ZERO {
+                for mut order in [buy_order, sell_order] {
+                    if self.max_fill_quantity(&order) <= Decimal::ZERO {
+                        self.close_order(&mut order, OrderStatus::Cancelled);
+                    }
+                    self.sync_resting_order(order);
+                }
+                continue;
+            }
+
+            if auction_price.is_none() && !self.allow_trade_price(symbol, match_price) {
+                self.sync_resting_order(buy_order);
+                self.sync_resting_order(sell_order);
+                break;
+            }
+            self.execute_trade(&mut buy_order, &mut sell_order, match_price, match_quantity, taker_side);
+
+            self.sync_resting_order(buy_order);
+            self.sync_resting_order(sell_order);
+        }
+
+        Ok(())
+    }
+
+    /// Runs what follows any change to a symbol's book: group reactions and stop triggers, then
+    /// publishing the depth changes and, in a call phase, the indicative uncross.
+    fn finish_update(&mut self, symbol: &str) {
+        self.process_stop_triggers(symbol);
+        self.publish_depth_updates(symbol);
+        self.publish_indicative_uncross(symbol);
+    }
+
+    fn publish_depth_updates(&mut self, symbol: &str) {
+        let updates = match self.order_books.get_mut(symbol) {
+            Some(order_book) => order_book.take_depth_updates(),
+            None => return,
+        };
+        if let Some(subscribers) = self.depth_subscribers.get_mut(symbol) {
+            // Subscribers that hung up are dropped
+            subscribers.retain(|subscriber| updates.iter().all(|update| subscriber.send(update.clone()).is_ok()));
+        }
+    }
+
+    /// Streams depth updates for the symbol from its current sequence on. Take a snapshot after
+    /// subscribing and drop updates at or below its sequence.
+    pub fn subscribe_depth(&mut self, symbol: &str) -> Result<Receiver<DepthUpdate>, String> {
+        if !self.order_books.contains_key(symbol) {
+            return Err("Symbol not supported".to_string());
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.depth_subscribers.entry(symbol.to_string()).or_default().push(sender);
+        Ok(receiver)
+    }
+
+    /// Streams every order event on the symbol from now on.
+    pub fn subscribe_order_events(&mut self, symbol: &str) -> Result<Receiver<OrderEvent>, String> {
+        if !self.order_books.contains_key(symbol) {
+            return Err("Symbol not supported".to_string());
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.order_event_subscribers.entry(symbol.to_string()).or_default().push(sender);
+        Ok(receiver)
+    }
+
+    fn publish_order_event(&mut self, mut event: OrderEvent) {
+        let sequence = self.order_event_sequences.entry(event.symbol.clone()).or_default();
+        *sequence += 1;
+        event.sequence = *sequence;
+        event.timestamp = self.now();
+        if let Some(subscribers) = self.order_event_subscribers.get_mut(&event.symbol) {
+            subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+    }
+
+    pub fn get_depth_snapshot(&self, symbol: &str, depth: usize) -> Option<DepthSnapshot> {
+        let order_book = self.order_books.get(symbol)?;
+        Some(DepthSnapshot {
+            symbol: symbol.to_string(),
+            sequence: order_book.depth_sequence(),
+            bids: order_book.get_bid_levels(depth),
+            asks: order_book.get_ask_levels(depth),
+        })
+    }
+
+    /// Fires every stop order crossed by the symbol's last trade price. Triggered orders can
+    /// trade and move the price further, so this keeps going until nothing else is crossed.
+    /// Group reactions run before each trigger so a stop never fires after its sibling has filled.
+    fn process_stop_triggers(&mut self, symbol: &str) {
+        loop {
+            self.process_group_events();
+            let last_price = match self.last_trade_prices.get(symbol).copied() {
+                Some(last_price) => last_price,
+                None => break,
+            };
+            let order_id = match self.stop_books.get_mut(symbol).and_then(|book| book.pop_triggered(last_price)) {
+                Some(order_id) => order_id,
+                None => break,
+            };
+            self.trigger_stop_order(&order_id);
+        }
+    }
+
+    fn trigger_stop_order(&mut self, order_id: &str) {
+        let mut order = match self.orders.remove(order_id) {
+            Some(order) => order,
+            None => return,
+        };
+        order.set_status(OrderStatus::Triggered, self.now());
+
+        // A trailing stop-limit gets its limit price from where the trigger level ended up
+        if let (Some(trailing), Some(stop_price)) = (&order.trailing, order.stop_price) {
+            if let Some(limit_offset) = trailing.limit_offset {
+                order.price = Some(match order.side {
+                    OrderSide::Buy => stop_price + limit_offset,
+                    OrderSide::Sell => (stop_price - limit_offset).max(self.get_tick_size(&order.symbol)),
+                });
+            }
+        }
+
+        // Stop buys without a limit price to reserve against take their hold now: what they could
+        // spend on the book when they fire, or at the new limit price for trailing stop-limits
+        if order.side == OrderSide::Buy && order.order_type != OrderType::StopLimit {
+            let quote_currency = self.get_quote_currency(&order.symbol);
+            let hold = match order.price {
+                Some(_) => self.get_user_balance(&order.trader, &quote_currency),
+                None => self.market_buy_hold(&order),
+            };
+            if self.lock_balance(&order.trader, &quote_currency, hold).is_ok() {
+                self.add_hold(&mut order, hold);
+            }
+            // Trailing stop-limits only need their new limit price covered
+            self.release_excess_hold(&mut order);
+        }
+
+        // A post-only stop-limit that would now take liquidity is slid or cancelled
+        if let (Some(post_only), Some(price)) = (order.instructions.post_only.clone(), order.price) {
+            match self.post_only_price(&order.symbol, &order.side, price, &post_only) {
+                Ok(price) => {
+                    order.price = Some(price);
+                    self.release_excess_hold(&mut order);
+                }
+                Err(_) => {
+                    self.close_order(&mut order, OrderStatus::Cancelled);
+                    self.orders.insert(order.id.clone(), order);
+                    return;
+                }
+            }
+        }
+
+        // Stop orders become market orders, stop-limit orders limit orders at their limit price
+        let limit_price = if order.order_type.has_limit_price() { order.price } else { None };
+        if order.time_in_force == TimeInForce::FOK && self.fillable_quantity(&order, limit_price) < order.remaining_quantity {
+            self.close_order(&mut order, OrderStatus::Cancelled);
+            self.orders.insert(order.id.clone(), order);
+            return;
+        }
+
+        if order.order_type.has_limit_price() {
+            let _ = self.process_limit_order(&mut order);
+        } else {
+            let _ = self.process_market_order(&mut order);
+        }
+        self.orders.insert(order.id.clone(), order);
+    }
+
+    /// Follows a trade print with every trailing stop on the symbol, moving trigger levels only in
+    /// the trader's favour: up for sells, down for buys.
+    fn update_trailing_stops(&mut self, symbol: &str, price: Decimal) {
+        let order_ids: Vec<String> = match self.stop_books.get(symbol) {
+            Some(stop_book) => stop_book.trailing_orders.iter().cloned().collect(),
+            None => return,
+        };
+
+        for order_id in order_ids {
+            let order = match self.orders.get(&order_id) {
+                Some(order) => order,
+                None => continue,
+            };
+            let mut trailing = match &order.trailing {
+                Some(trailing) => trailing.clone(),
+                None => continue,
+            };
+
+            let improved = match order.side {
+                OrderSide::Sell => price > trailing.best_price,
+                OrderSide::Buy => price < trailing.best_price,
+            };
+            if !improved {
+                continue;
+            }
+            trailing.best_price = price;
+
+            let stop_price = self.round_stop_price(symbol, &order.side, trailing.stop_price(&order.side));
+            let moved = match (order.side.clone(), order.stop_price) {
+                (OrderSide::Sell, Some(current)) => stop_price > current,
+                (OrderSide::Buy, Some(current)) => stop_price < current,
+                (_, None) => true,
+            };
+
+            let stop_book = self.stop_books.get_mut(symbol).unwrap();
+            let order = self.orders.get_mut(&order_id).unwrap();
+            if moved {
+                stop_book.remove_order(order);
+                order.stop_price = Some(stop_price);
+                order.trailing = Some(trailing);
+                stop_book.add_order(order);
+            } else {
+                order.trailing = Some(trailing);
+            }
+        }
+    }
+
+    fn execute_trade(&mut self, buy_order: &mut Order, sell_order: &mut Order, price: Decimal, quantity: Decimal,
+                     taker_side: OrderSide) {
+        self.trade_counter += 1;
+        let trade_id = format!("{}trade_{}", self.id_prefix, self.trade_counter);
+        let timestamp = self.now();
+
+        let (buy_liquidity, sell_liquidity) = match taker_side {
+            OrderSide::Buy => (Liquidity::Taker, Liquidity::Maker),
+            OrderSide::Sell => (Liquidity::Maker, Liquidity::Taker),
+        };
+        let trade_value = price * quantity;
+
+        // Fees come out of what each side receives, at the rates for their volume before this trade
+        let symbol = buy_order.symbol.clone();
+        let buyer_fee = (quantity * self.fee_rate(&buy_order.trader, &symbol, &buy_liquidity)).round_dp(8);
+        let seller_fee = (trade_value * self.fee_rate(&sell_order.trader, &symbol, &sell_liquidity)).round_dp(8);
+
+        buy_order.fills.push(Fill { trade_id: trade_id.clone(), price, quantity, liquidity: buy_liquidity,
+                                    fee: buyer_fee, timestamp });
+        sell_order.fills.push(Fill { trade_id: trade_id.clone(), price, quantity, liquidity: sell_liquidity,
+                                     fee: seller_fee, timestamp });
+
+        let trade = Trade {
+            id: trade_id.clone(),
+            symbol: buy_order.symbol.clone(),
+            price,
+            quantity,
+            buy_order_id: buy_order.id.clone(),
+            sell_order_id: sell_order.id.clone(),
+            buyer: buy_order.trader.clone(),
+            seller: sell_order.trader.clone(),
+            timestamp,
+            trade_type: "limit".to_string(),
+            taker_side: taker_side.clone(),
+            buyer_fee,
+            seller_fee,
+        };
+
+        self.record_trade_statistics(&trade);
+        self.trades.push(trade);
+        self.last_trade_prices.insert(buy_order.symbol.clone(), price);
+        // The buyer's position grows by the base they receive, so a reduce-only sell can close all of it
+        self.update_position(&buy_order.trader, &buy_order.symbol, quantity - buyer_fee);
+        self.update_position(&sell_order.trader, &sell_order.symbol, -quantity);
+        self.update_trailing_stops(&buy_order.symbol, price);
+
+        // Update order quantities
+        buy_order.update_filled(quantity, timestamp);
+        sell_order.update_filled(quantity, timestamp);
+        for order in [&*buy_order, &*sell_order] {
+            if order.group_id.is_some() {
+                self.group_events.push_back(order.id.clone());
+            }
+
+            let mut execution = OrderEvent::for_order(OrderEventKind::Execute, order, quantity);
+            execution.price = Some(price);
+            execution.trade_id = Some(trade_id.clone());
+            self.publish_order_event(execution);
+        }
+        let taker = if taker_side == OrderSide::Buy { &*buy_order } else { &*sell_order };
+        let mut trade_event = OrderEvent::for_order(OrderEventKind::Trade, taker, quantity);
+        trade_event.price = Some(price);
+        trade_event.displayed_quantity = Decimal::ZERO;
+        trade_event.hidden_quantity = Decimal::ZERO;
+        trade_event.trade_id = Some(trade_id);
+        self.publish_order_event(trade_event);
+
+        // Update balances
+        let base_currency = self.get_base_currency(&buy_order.symbol);
+        let quote_currency = self.get_quote_currency(&buy_order.symbol);
+
+        // Buyer: -quote_currency out of the order's hold, +base_currency less fees
+        self.consume_hold(buy_order, &quote_currency, trade_value);
+        self.credit_balance(&buy_order.trader, &base_currency, quantity - buyer_fee);
+
+        // Seller: -base_currency out of the order's hold, +quote_currency less fees
+        self.consume_hold(sell_order, &base_currency, quantity);
+        self.credit_balance(&sell_order.trader, &quote_currency, trade_value - seller_fee);
+
+        let fee_account = self.fee_account.clone();
+        self.credit_balance(&fee_account, &base_currency, buyer_fee);
+        self.credit_balance(&fee_account, &quote_currency, seller_fee);
+
+        // A buy filled below its limit price, or an order that is now done, has more locked than it needs
+        self.release_excess_hold(buy_order);
+        self.release_excess_hold(sell_order);
+    }
+
+    /// Feeds a trade into the fee volume windows, candles and ticker.
+    fn record_trade_statistics(&mut self, trade: &Trade) {
+        for trader in [&trade.buyer, &trade.seller] {
+            self.trade_volumes
+                .entry((trader.clone(), trade.symbol.clone()))
+                .or_default()
+                .record(trade.timestamp, trade.price * trade.quantity);
+        }
+        self.candles.add_trade(trade);
+        self.ticker_windows.entry(trade.symbol.clone()).or_default().record(trade.timestamp, trade.price, trade.quantity);
+        self.price_ranges.entry(trade.symbol.clone()).or_default().record(trade.timestamp, trade.price);
+    }
+
+    /// Rebuilds fee volumes, candles and tickers from the trade history.
+    pub fn rebuild_trade_statistics(&mut self) {
+        self.trade_volumes.clear();
+        self.candles = CandleAggregator::new();
+        self.ticker_windows.clear();
+        self.price_ranges.clear();
+        let trades = std::mem::take(&mut self.trades);
+        for trade in &trades {
+            self.record_trade_statistics(trade);
+        }
+        self.trades = trades;
+    }
+
+    fn consume_hold(&mut self, order: &mut Order, currency: &str, amount: Decimal) {
+        match self.shared_hold_group(order).map(|group| group.id.clone()) {
+            Some(group_id) => self.order_groups.get_mut(&group_id).unwrap().locked_amount -= amount,
+            None => order.locked_amount -= amount,
+        }
+        self.user_balances.update(&order.trader, currency, |balance| balance.locked -= amount);
+    }
+
+    /// Funds an open order still needs locked: its remaining quantity for sells and the remaining
+    /// quantity at the limit price for priced buys. Market-priced buys keep their hold until they finish.
+    fn required_hold(order: &Order) -> Decimal {
+        if !order.is_open() {
+            return Decimal::ZERO;
+        }
+
+        match (&order.side, &order.order_type, order.price) {
+            (OrderSide::Sell, _, _) => order.remaining_quantity,
+            (OrderSide::Buy, order_type, Some(price)) if order_type.has_limit_price() => price * order.remaining_quantity,
+            (OrderSide::Buy, _, _) => order.locked_amount,
+        }
+    }
+
+    /// Buys hold quote currency, sells hold base currency.
+    fn hold_currency(&self, order: &Order) -> String {
+        match order.side {
+            OrderSide::Buy => self.get_quote_currency(&order.symbol),
+            OrderSide::Sell => self.get_base_currency(&order.symbol),
+        }
+    }
+
+    /// Group whose shared hold funds the order: OCO legs and bracket exits. Bracket entries hold their own.
+    fn shared_hold_group(&self, order: &Order) -> Option<&OrderGroup> {
+        let group = self.order_groups.get(order.group_id.as_ref()?)?;
+        (group.entry_order_id.as_ref() != Some(&order.id)).then_some(group)
+    }
+
+    /// Funds an order can trade against: its own hold, or its group's.
+    fn available_hold(&self, order: &Order) -> Decimal {
+        self.shared_hold_group(order).map_or(order.locked_amount, |group| group.locked_amount)
+    }
+
+    /// Books newly locked funds against the order, or its group's shared hold.
+    fn add_hold(&mut self, order: &mut Order, amount: Decimal) {
+        match self.shared_hold_group(order).map(|group| group.id.clone()) {
+            Some(group_id) => self.order_groups.get_mut(&group_id).unwrap().locked_amount += amount,
+            None => order.locked_amount += amount,
+        }
+    }
+
+    /// Quantity the group's exits have filled between them, taking `order` over the stored copy.
+    fn group_filled_quantity(&self, group: &OrderGroup, order: &Order) -> Decimal {
+        group.exit_order_ids().iter()
+            .filter_map(|order_id| if *order_id == order.id { Some(order) } else { self.orders.get(order_id) })
+            .map(|exit| exit.filled_quantity)
+            .sum()
+    }
+
+    fn release_excess_hold(&mut self, order: &mut Order) {
+        if let Some(group_id) = self.shared_hold_group(order).map(|group| group.id.clone()) {
+            self.release_excess_group_hold(&group_id, order);
+            return;
+        }
+
+        let excess = order.locked_amount - Self::required_hold(order);
+        if excess > Decimal::ZERO {
+            let currency = self.hold_currency(order);
+            self.unlock_balance(&order.trader, &currency, excess);
+            order.locked_amount -= excess;
+        }
+    }
+
+    /// Exits share the larger of what each still needs; a market-priced buy that has gone live
+    /// keeps the whole hold until it finishes.
+    fn release_excess_group_hold(&mut self, group_id: &str, order: &Order) {
+        let group = &self.order_groups[group_id];
+        let required = group.exit_order_ids().iter()
+            .filter_map(|order_id| if *order_id == order.id { Some(order) } else { self.orders.get(order_id) })
+            .map(|exit| match (&exit.side, exit.order_type.has_limit_price(), &exit.status) {
+                (_, _, OrderStatus::Inactive) => Decimal::ZERO,
+                (OrderSide::Buy, false, OrderStatus::Triggered | OrderStatus::Partial) => group.locked_amount,
+                _ => Self::required_hold(exit),
+            })
+            .max()
+            .unwrap_or_default();
+
+        let excess = group.locked_amount - required;
+        if excess > Decimal::ZERO {
+            let currency = self.hold_currency(order);
+            self.unlock_balance(&order.trader, &currency, excess);
+            self.order_groups.get_mut(group_id).unwrap().locked_amount -= excess;
+        }
+    }
+
+    pub fn cancel_order(&mut self, order_id: &str, trader: &str) -> Result<(), String> {
+        let order = self.orders.get(order_id)
+            .ok_or_else(|| "Order not found".to_string())?;
+
+        if order.trader != trader {
+            return Err("Unauthorized".to_string());
+        }
+
+        if !order.is_open() {
+            return Err("Order cannot be cancelled".to_string());
+        }
+
+        let symbol = order.symbol.clone();
+        self.cancel_open_order(order_id);
+        self.finish_update(&symbol);
+
+        Ok(())
+    }
+
+    fn cancel_open_order(&mut self, order_id: &str) {
+        let mut order = match self.orders.remove(order_id) {
+            Some(order) => order,
+            None => return,
+        };
+
+        if order.is_open() {
+            // Remove from the stop book if it has not triggered yet, otherwise from the order book
+            if order.is_stop() && order.status == OrderStatus::Pending {
+                if let Some(stop_book) = self.stop_books.get_mut(&order.symbol) {
+                    stop_book.remove_order(&order);
+                }
+            } else if let Some(order_book) = self.order_books.get_mut(&order.symbol) {
+                order_book.remove_order(order_id);
+            }
+
+            // Update order status and release its hold
+            self.close_order(&mut order, OrderStatus::Cancelled);
+        }
+        self.orders.insert(order.id.clone(), order);
+    }
+
+    /// Lets the groups of orders that filled or closed react, until nothing else changes.
+    fn process_group_events(&mut self) {
+        while let Some(order_id) = self.group_events.pop_front() {
+            self.update_order_group(&order_id);
+        }
+    }
+
+    fn update_order_group(&mut self, order_id: &str) {
+        let order = match self.orders.get(order_id) {
+            Some(order) => order.clone(),
+            None => return,
+        };
+        let group = match order.group_id.as_ref().and_then(|group_id| self.order_groups.get(group_id)) {
+            Some(group) if group.status == OrderGroupStatus::Active => group.clone(),
+            _ => return,
+        };
+        let other_exit_ids: Vec<String> = group.exit_order_ids().into_iter()
+            .filter(|exit_id| exit_id != order_id)
+            .collect();
+
+        if group.entry_order_id.as_deref() == Some(order_id) {
+            // Exits go live once the entry is done, for whatever it filled. An entry that
+            // filled nothing takes its exits with it.
+            if !order.is_open() {
+                // A buy entry only received its fills less fees, so that is all its exits can sell
+                let quantity = match order.side {
+                    OrderSide::Buy => order.filled_quantity - order.fills.iter().map(|fill| fill.fee).sum::<Decimal>(),
+                    OrderSide::Sell => order.filled_quantity,
+                };
+                let quantity = self.symbol_specs[&order.symbol].round_quantity(quantity);
+                if quantity > Decimal::ZERO {
+                    self.arm_bracket_exits(&group.id, quantity);
+                } else {
+                    for exit_id in &other_exit_ids {
+                        self.cancel_open_order(exit_id);
+                    }
+                }
+            }
+        } else if !order.is_open() {
+            // One exit is done, so the others go
+            for exit_id in &other_exit_ids {
+                self.cancel_open_order(exit_id);
+            }
+        } else {
+            // A partial fill comes off the size the other exits have left
+            let remaining = group.quantity - self.group_filled_quantity(&group, &order);
+            for exit_id in &other_exit_ids {
+                self.shrink_order(exit_id, remaining);
+            }
+        }
+
+        self.refresh_group_status(&group.id);
+    }
+
+    /// Sizes a bracket's exits to what the entry filled, holds for them and sends them live.
+    fn arm_bracket_exits(&mut self, group_id: &str, quantity: Decimal) {
+        let exit_ids = self.order_groups[group_id].exit_order_ids();
+        let mut exits: Vec<Order> = exit_ids.iter()
+            .filter_map(|exit_id| self.orders.get(exit_id))
+            .filter(|exit| exit.status == OrderStatus::Inactive)
+            .cloned()
+            .collect();
+        if exits.is_empty() {
+            return;
+        }
+        for exit in &mut exits {
+            exit.quantity = quantity;
+            exit.remaining_quantity = quantity;
+        }
+
+        let hold_currency = self.hold_currency(&exits[0]);
+        let hold_amount = exits.iter().map(|exit| self.initial_hold(exit).1).max().unwrap_or_default();
+        if self.lock_balance(&exits[0].trader, &hold_currency, hold_amount).is_err() {
+            for exit in &exits {
+                self.cancel_open_order(&exit.id);
+            }
+            return;
+        }
+        let group = self.order_groups.get_mut(group_id).unwrap();
+        group.quantity = quantity;
+        group.locked_amount = hold_amount;
+
+        let now = self.now();
+        for mut exit in exits {
+            exit.set_status(OrderStatus::Pending, now);
+            self.orders.insert(exit.id.clone(), exit);
+        }
+        self.activate_grouped_orders(&exit_ids);
+    }
+
+    /// Cuts an open order down to `remaining` left to fill without losing its place in the queue.
+    fn shrink_order(&mut self, order_id: &str, remaining: Decimal) {
+        if !self.orders.get(order_id).is_some_and(|order| order.is_open() && order.remaining_quantity > remaining) {
+            return;
+        }
+        if remaining <= Decimal::ZERO {
+            self.cancel_open_order(order_id);
+            return;
+        }
+
+        let mut order = self.orders.remove(order_id).unwrap();
+        order.quantity = order.filled_quantity + remaining;
+        order.remaining_quantity = remaining;
+        order.display_remaining = order.display_remaining.min(remaining);
+        order.updated_at = self.now();
+        if let Some(order_book) = self.order_books.get_mut(&order.symbol) {
+            order_book.update_order(&order);
+        }
+        self.publish_order_event(OrderEvent::for_order(OrderEventKind::Modify, &order, remaining));
+        self.release_excess_hold(&mut order);
+        self.orders.insert(order.id.clone(), order);
+    }
+
+    /// Closes a group once none of its orders are open: completed if an exit traded, cancelled otherwise.
+    fn refresh_group_status(&mut self, group_id: &str) {
+        let group = &self.order_groups[group_id];
+        if group.status != OrderGroupStatus::Active
+            || group.order_ids.iter().any(|order_id| self.orders.get(order_id).is_some_and(|order| order.is_open())) {
+            return;
+        }
+
+        let exit_filled = group.exit_order_ids().iter()
+            .any(|order_id| self.orders.get(order_id).is_some_and(|order| order.filled_quantity > Decimal::ZERO));
+        let now = self.now();
+        let group = self.order_groups.get_mut(group_id).unwrap();
+        group.status = if exit_filled { OrderGroupStatus::Completed } else { OrderGroupStatus::Cancelled };
+        group.updated_at = now;
+    }
+
+    /// Changes the total quantity and/or limit price of a resting order in place. Reducing the
+    /// quantity keeps the order's time priority; a new price or a larger size sends it to the back
+    /// of the queue, and a new price that crosses the spread trades immediately.
+    pub fn amend_order(&mut self, order_id: &str, trader: &str, new_quantity: Option<Decimal>,
+                       new_price: Option<Decimal>) -> Result<Order, String> {
+        let mut order = self.orders.get(order_id)
+            .cloned()
+            .ok_or_else(|| "Order not found".to_string())?;
+
+        if order.trader != trader {
+            return Err("Unauthorized".to_string());
+        }
+
+        if order.group_id.is_some() {
+            return Err("Orders in a group cannot be amended".to_string());
+        }
+
+        if !order.is_open() || !self.order_books[&order.symbol].contains(order_id) {
+            return Err("Only orders resting on the book can be amended".to_string());
+        }
+
+        let quantity = new_quantity.unwrap_or(order.quantity);
+        let price = new_price.or(order.price);
+        if quantity <= order.filled_quantity {
+            return Err("New quantity must be greater than the filled quantity".to_string());
+        }
+        if price.is_none_or(|price| price <= Decimal::ZERO) {
+            return Err("Amended price must be positive".to_string());
+        }
+        if quantity == order.quantity && price == order.price {
+            return Err("Amendment does not change the order".to_string());
+        }
+        self.validate_symbol_spec(&order.symbol, &order.side, quantity, price, order.stop_price)?;
+        let price = match (&order.instructions.post_only, price) {
+            (Some(post_only), Some(new_price)) if price != order.price => {
+                Some(self.post_only_price(&order.symbol, &order.side, new_price, post_only)?)
+            }
+            _ => price,
+        };
+
+        let amendment = OrderAmendment {
+            old_quantity: order.quantity,
+            new_quantity: quantity,
+            old_price: order.price,
+            new_price: price,
+            kept_priority: price == order.price && quantity < order.quantity,
+            timestamp: self.now(),
+        };
+
+        order.quantity = quantity;
+        order.remaining_quantity = quantity - order.filled_quantity;
+        order.price = price;
+        order.display_remaining = order.display_remaining.min(order.remaining_quantity);
+
+        // Top up the hold if the order now needs more, give back the rest otherwise
+        let required_hold = Self::required_hold(&order);
+        if required_hold > order.locked_amount {
+            let currency = self.hold_currency(&order);
+            self.lock_balance(trader, &currency, required_hold - order.locked_amount)?;
+            order.locked_amount = required_hold;
+        }
+        self.release_excess_hold(&mut order);
+
+        order.updated_at = amendment.timestamp;
+        let kept_priority = amendment.kept_priority;
+        order.amend_history.push(amendment);
+
+        let symbol = order.symbol.clone();
+        let order_book = self.order_books.get_mut(&symbol).unwrap();
+        let mut modified = OrderEvent::for_order(OrderEventKind::Modify, &order, order.remaining_quantity);
+        if kept_priority {
+            order_book.update_order(&order);
+            self.publish_order_event(modified);
+        } else {
+            // Re-enter the order as if it had just arrived
+            order_book.remove_order(order_id);
+            modified.kept_priority = false;
+            modified.displayed_quantity = Decimal::ZERO;
+            modified.hidden_quantity = Decimal::ZERO;
+            self.publish_order_event(modified);
+            self.orders.remove(order_id);
+            self.process_limit_order(&mut order)?;
+        }
+
+        self.orders.insert(order.id.clone(), order);
+        self.finish_update(&symbol);
+        Ok(self.orders[order_id].clone())
+    }
+
+    pub fn get_order(&self, order_id: &str) -> Option<Order> {
+        self.orders.get(order_id).cloned()
+    }
+
+    pub fn get_user_orders(&self, trader: &str) -> Vec<Order> {
+        self.orders.values()
+            .filter(|order| order.trader == trader)
+            .cloned()
+            .collect()
+    }
+
+    pub fn get_order_book(&self, symbol: &str) -> Option<OrderBook> {
+        self.order_books.get(symbol).cloned()
+    }
+
+    /// Every trade, oldest first.
+    pub fn get_trades(&self) -> &[Trade] {
+        &self.trades
+    }
+
+    pub fn get_recent_trades(&self, symbol: &str, limit: usize) -> Vec<Trade> {
+        self.trades.iter()
+            .filter(|trade| trade.symbol == symbol)
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// OHLCV bars opening in `[start, end)`, oldest first.
+    pub fn get_candles(&self, symbol: &str, interval: CandleInterval, start: DateTime<Utc>,
+                       end: DateTime<Utc>) -> Vec<Candle> {
+        self.candles.get_candles(symbol, interval, start, end)
+    }
+
+    pub fn get_latest_candle(&self, symbol: &str, interval: CandleInterval) -> Option<Candle> {
+        self.candles.get_latest_candle(symbol, interval)
+    }
+
+    /// Rebuilds every bar from the trade history.
+    pub fn rebuild_candles(&mut self) {
+        self.candles.rebuild(&self.trades);
+    }
+
+    /// Rolling 24h ticker, or None if the symbol isn't listed.
+    pub fn get_ticker(&self, symbol: &str) -> Option<Ticker> {
+        let order_book = self.order_books.get(symbol)?;
+        let now = self.now();
+        let last = self.last_trade_prices.get(symbol).copied().unwrap_or(Decimal::ZERO);
+        let (open, high, low, volume, quote_volume, trade_count) = self.ticker_windows.get(symbol)
+            .and_then(|window| window.stats_since(now - Duration::hours(TICKER_WINDOW_HOURS)))
+            .unwrap_or((last, last, last, Decimal::ZERO, Decimal::ZERO, 0));
+        let change = last - open;
+        let change_percent = if open > Decimal::ZERO {
+            (change / open * Decimal::ONE_HUNDRED).round_dp(2)
+        } else {
+            Decimal::ZERO
+        };
+        let vwap = if volume > Decimal::ZERO { quote_volume / volume } else { last };
+        let best_bid = order_book.get_bid_levels(1).pop();
+        let best_ask = order_book.get_ask_levels(1).pop();
+
+        Some(Ticker {
+            symbol: symbol.to_string(),
+            open,
+            high,
+            low,
+            last,
+            change,
+            change_percent,
+            vwap,
+            volume,
+            quote_volume,
+            trade_count,
+            best_bid: best_bid.as_ref().map(|level| level.price),
+            best_bid_quantity: best_bid.map_or(Decimal::ZERO, |level| level.quantity),
+            best_ask: best_ask.as_ref().map(|level| level.price),
+            best_ask_quantity: best_ask.map_or(Decimal::ZERO, |level| level.quantity),
+            timestamp: now,
+        })
+    }
+
+    /// Tickers for every listed symbol, sorted by symbol.
+    pub fn get_tickers(&self) -> Vec<Ticker> {
+        let mut tickers: Vec<Ticker> = self.order_books.keys()
+            .filter_map(|symbol| self.get_ticker(symbol))
+            .collect();
+        tickers.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        tickers
+    }
+
+    /// Net base quantity the trader has bought (positive) or sold (negative) on this symbol, after
+    /// the fees taken from what they bought.
+    pub fn get_position(&self, trader: &str, symbol: &str) -> Decimal {
+        self.positions
+            .get(trader)
+            .and_then(|positions| positions.get(symbol))
+            .copied()
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    fn update_position(&mut self, trader: &str, symbol: &str, quantity: Decimal) {
+        *self.positions
+            .entry(trader.to_string())
+            .or_default()
+            .entry(symbol.to_string())
+            .or_default() += quantity;
+    }
+
+    /// Balance the user can spend on new orders or withdraw.
+    pub fn get_user_balance(&self, user: &str, currency: &str) -> Decimal {
+        self.get_balance(user, currency).available
+    }
+
+    /// Balance held for the user's open orders.
+    pub fn get_locked_balance(&self, user: &str, currency: &str) -> Decimal {
+        self.get_balance(user, currency).locked
+    }
+
+    pub fn get_balance(&self, user: &str, currency: &str) -> Balance {
+        self.user_balances.get_balance(user, currency)
+    }
+
+    pub fn get_user_balances(&self, user: &str) -> HashMap<String, Balance> {
+        self.user_balances.get_user_balances(user)
+    }
+
+    pub fn update_balance(&mut self, user: &str, currency: &str, amount: Decimal) {
+        self.user_balances.update(user, currency, |balance| balance.available = amount);
+    }
+
+    fn credit_balance(&mut self, user: &str, currency: &str, amount: Decimal) {
+        self.user_balances.deposit(user, currency, amount);
+    }
+
+    fn lock_balance(&mut self, user: &str, currency: &str, amount: Decimal) -> Result<(), String> {
+        self.user_balances.lock(user, currency, amount)
+    }
+
+    fn unlock_balance(&mut self, user: &str, currency: &str, amount: Decimal) {
+        self.user_balances.unlock(user, currency, amount);
+    }
+
+    pub fn deposit(&mut self, user: &str, currency: &str, amount: Decimal) {
+        self.user_balances.deposit(user, currency, amount);
+    }
+
+    pub fn withdraw(&mut self, user: &str, currency: &str, amount: Decimal) -> Result<(), String> {
+        self.user_balances.withdraw(user, currency, amount)
+    }
+
+    fn validate_order(&self, order_type: &OrderType, price: Option<Decimal>, stop_price: Option<Decimal>) -> Result<(), String> {
+        match order_type {
+            OrderType::Limit => {
+                if price.is_none() || price.unwrap() <= Decimal::ZERO {
+                    return Err("Limit orders must have a valid price".to_string());
+                }
+            }
+            OrderType::Stop => {
+                if stop_price.is_none() || stop_price.unwrap() <= Decimal::ZERO {
+                    return Err("Stop orders must have a valid stop price".to_string());
+                }
+            }
+            OrderType::StopLimit => {
+                if price.is_none() || stop_price.is_none() ||
+                   price.unwrap() <= Decimal::ZERO || stop_price.unwrap() <= Decimal::ZERO {
+                    return Err("Stop-limit orders must have valid price and stop price".to_string());
+                }
+            }
+            OrderType::TrailingStop | OrderType::TrailingStopLimit => {
+                // The trigger level comes from the trail and the limit price is set when it fires
+                if price.is_some() {
+                    return Err("Trailing stop orders take a limit offset, not a price".to_string());
+                }
+                if stop_price.is_none_or(|stop_price| stop_price <= Decimal::ZERO) {
+                    return Err("Trailing stop is too wide for the current price".to_string());
+                }
+            }
+            OrderType::Market => {
+                // Market orders don't need price validation
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks an order against its symbol's listing: the symbol must be trading, prices on the
+    /// tick grid, the quantity on the step grid and within limits, and the value above the minimum.
+    fn validate_symbol_spec(&self, symbol: &str, side: &OrderSide, quantity: Decimal, price: Option<Decimal>,
+                            stop_price: Option<Decimal>) -> Result<(), String> {
+        let spec = self.symbol_specs.get(symbol)
+            .ok_or_else(|| "Symbol not supported".to_string())?;
+        if !spec.status.accepts_orders() {
+            return Err(format!("Symbol {} is not trading ({:?})", symbol, spec.status));
+        }
+
+        spec.validate_quantity(quantity)?;
+        if let Some(price) = price {
+            spec.validate_price("Price", price)?;
+            self.validate_price_bands(symbol, price)?;
+        }
+        if let Some(stop_price) = stop_price {
+            spec.validate_price("Stop price", stop_price)?;
+        }
+
+        // Market orders are valued at the best price they could trade at
+        let order_book = &self.order_books[symbol];
+        let reference_price = price.or(stop_price).or(match side {
+            OrderSide::Buy => order_book.get_best_ask(),
+            OrderSide::Sell => order_book.get_best_bid(),
+        });
+        if let Some(reference_price) = reference_price {
+            spec.validate_notional(reference_price, quantity)?;
+        }
+        Ok(())
+    }
+
+    fn validate_time_in_force(&self, time_in_force: &TimeInForce, expire_at: Option<DateTime<Utc>>) -> Result<(), String> {
+        if *time_in_force == TimeInForce::GTD {
+            match expire_at {
+                None => return Err("Good-till-date orders must have an expiry time".to_string()),
+                Some(expire_at) if expire_at <= self.now() => {
+                    return Err("Expiry time must be in the future".to_string());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_instructions(&self, instructions: &ExecInstructions, order_type: &OrderType,
+                             time_in_force: &TimeInForce, quantity: Decimal) -> Result<(), String> {
+        if instructions.post_only.is_some() {
+            if !order_type.has_limit_price() {
+                return Err("Post-only orders must have a limit price".to_string());
+            }
+            if *time_in_force == TimeInForce::IOC || *time_in_force == TimeInForce::FOK {
+                return Err("Post-only orders cannot be immediate-or-cancel or fill-or-kill".to_string());
+            }
+        }
+        if instructions.hidden && !order_type.has_limit_price() {
+            return Err("Hidden orders must have a limit price".to_string());
+        }
+        if let Some(display_quantity) = instructions.display_quantity {
+            if !order_type.has_limit_price() {
+                return Err("Iceberg orders must have a limit price".to_string());
+            }
+            if instructions.hidden {
+                return Err("Iceberg orders cannot also be hidden".to_string());
+            }
+            if display_quantity <= Decimal::ZERO || display_quantity >= quantity {
+                return Err("Display quantity must be positive and less than the order quantity".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Price a post-only order can rest at without taking liquidity.
+    fn post_only_price(&self, symbol: &str, side: &OrderSide, price: Decimal, post_only: &PostOnly) -> Result<Decimal, String> {
+        let order_book = &self.order_books[symbol];
+        let crossing_price = match side {
+            OrderSide::Buy => order_book.get_best_ask().filter(|ask| price >= *ask),
+            OrderSide::Sell => order_book.get_best_bid().filter(|bid| price <= *bid),
+        };
+
+        match (crossing_price, post_only) {
+            (None, _) => Ok(price),
+            (Some(_), PostOnly::Reject) => Err("Post-only order would take liquidity".to_string()),
+            (Some(opposite_price), PostOnly::Slide) => {
+                let tick_size = self.get_tick_size(symbol);
+                let slid_price = match side {
+                    OrderSide::Buy => opposite_price - tick_size,
+                    OrderSide::Sell => opposite_price + tick_size,
+                };
+                if slid_price <= Decimal::ZERO {
+                    return Err("Post-only order cannot be slid to a valid price".to_string());
+                }
+                Ok(slid_price)
+            }
+        }
+    }
+
+    /// Rounds a computed stop price onto the tick grid, away from the market.
+    fn round_stop_price(&self, symbol: &str, side: &OrderSide, stop_price: Decimal) -> Decimal {
+        let tick_size = self.get_tick_size(symbol);
+        let ticks = stop_price / tick_size;
+        let ticks = match side {
+            OrderSide::Buy => ticks.ceil(),
+            OrderSide::Sell => ticks.floor(),
+        };
+        ticks * tick_size
+    }
+
+    fn get_tick_size(&self, symbol: &str) -> Decimal {
+        self.symbol_specs[symbol].tick_size
+    }
+
+    fn get_base_currency(&self, symbol: &str) -> String {
+        self.symbol_specs[symbol].base_asset.clone()
+    }
+
+    fn get_quote_currency(&self, symbol: &str) -> String {
+        self.symbol_specs[symbol].quote_asset.clone()
+    }
+
+    pub fn process_pending_orders(&mut self) {
+        let now = self.now();
+        let mut expired_orders: Vec<String> = self.orders.iter()
+            .filter(|(_, order)| order.is_open() && order.is_expired(now))
+            .map(|(id, _)| id.clone())
+            .collect();
+        // Map order differs between runs; expire in a fixed order so events and group reactions replay the same
+        expired_orders.sort();
+
+        let mut symbols = BTreeSet::new();
+        for order_id in expired_orders {
+            if let Some(mut order) = self.orders.remove(&order_id) {
+                symbols.insert(order.symbol.clone());
+                // Remove from the stop book or the order book
+                if order.is_stop() {
+                    if let Some(stop_book) = self.stop_books.get_mut(&order.symbol) {
+                        stop_book.remove_order(&order);
+                    }
+                }
+                if let Some(order_book) = self.order_books.get_mut(&order.symbol) {
+                    order_book.remove_order(&order_id);
+                }
+                self.close_order(&mut order, OrderStatus::Expired);
+                self.orders.insert(order_id, order);
+            }
+        }
+
+        // Let groups react to the expired orders and publish the depth they took off the book
+        for symbol in symbols {
+            self.finish_update(&symbol);
+        }
+
+        let mut halted_symbols: Vec<String> = self.trading_halts.keys().cloned().collect();
+        halted_symbols.sort();
+        for symbol in halted_symbols {
+            // A halt that cannot move on yet, e.g. one an operator closed for the day, stays as it is
+            let _ = self.process_trading_halts(&symbol);
+        }
+    }
+
+    pub fn get_market_stats(&self) -> HashMap<String, String> {
+        let mut stats = HashMap::new();
+
+        let total_symbols = self.order_books.len();
+        stats.insert("total_symbols".to_string(), total_symbols.to_string());
+
+        let total_orders = self.orders.len();
+        stats.insert("total_orders".to_string(), total_orders.to_string());
+
+        let total_trades = self.trades.len();
+        stats.insert("total_trades".to_string(), total_trades.to_string());
+
+        let total_users = self.user_balances.user_count();
+        stats.insert("total_users".to_string(), total_users.to_string());
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_place_limit_order() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        dex.deposit("trader1", "USDC", Decimal::new(10000, 0));
+
+        let result = dex.place_order(OrderRequest::new(
+            "trader1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Limit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        ));
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_market_order_matching() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        // Add sell order
+        dex.deposit("seller1", "ETH", Decimal::new(1, 0));
+        dex.place_order(OrderRequest::new(
+            "seller1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Sell,
+            OrderType::Limit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        )).unwrap();
+
+        // Add buy order
+        dex.deposit("buyer1", "USDC", Decimal::new(2000, 0));
+        dex.place_order(OrderRequest::new(
+            "buyer1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Market,
+            Decimal::new(1, 0),
+            None,
+            None,
+        )).unwrap();
+
+        // Check that trade occurred
+        assert_eq!(dex.trades.len(), 1);
+        assert_eq!(dex.get_user_balance("buyer1", "ETH"), Decimal::new(1, 0));
+        assert_eq!(dex.get_user_balance("seller1", "USDC"), Decimal::new(2000, 0));
+    }
+
+    #[test]
+    fn test_cancel_order() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        dex.deposit("trader1", "USDC", Decimal::new(10000, 0));
+
+        let order_id = dex.place_order(OrderRequest::new(
+            "trader1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Limit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        )).unwrap().id;
+
+        let result = dex.cancel_order(&order_id, "trader1");
+        assert!(result.is_ok());
+
+        let order = dex.get_order(&order_id).unwrap();
+        assert_eq!(order.status, OrderStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_stop_order_triggers_on_trade() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        dex.deposit("seller1", "ETH", Decimal::new(2, 0));
+        for price in [2000, 2100] {
+            dex.place_order(OrderRequest::new(
+                "seller1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(price, 0)),
+                None,
+            )).unwrap();
+        }
+
+        // Buy stop resting above the market
+        dex.deposit("trader1", "USDC", Decimal::new(5000, 0));
+        let stop_id = dex.place_order(OrderRequest::new(
+            "trader1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Stop,
+            Decimal::new(1, 0),
+            None,
+            Some(Decimal::new(2000, 0)),
+        )).unwrap().id;
+        assert_eq!(dex.trades.len(), 0);
+        assert_eq!(dex.get_order(&stop_id).unwrap().status, OrderStatus::Pending);
+
+        // A trade at 2000 crosses the stop, which then lifts the 2100 offer
+        dex.deposit("buyer1", "USDC", Decimal::new(2000, 0));
+        dex.place_order(OrderRequest::new(
+            "buyer1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Market,
+            Decimal::new(1, 0),
+            None,
+            None,
+        )).unwrap();
+
+        assert_eq!(dex.trades.len(), 2);
+        assert_eq!(dex.trades[1].price, Decimal::new(2100, 0));
+
+        let stop_order = dex.get_order(&stop_id).unwrap();
+        assert_eq!(stop_order.status, OrderStatus::Filled);
+        let history: Vec<OrderStatus> = stop_order.status_history.iter().map(|c| c.status.clone()).collect();
+        assert_eq!(history, vec![OrderStatus::Pending, OrderStatus::Triggered, OrderStatus::Filled]);
+    }
+
+    #[test]
+    fn test_cancel_untriggered_stop_limit() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        dex.deposit("trader1", "ETH", Decimal::new(1, 0));
+        let order_id = dex.place_order(OrderRequest::new(
+            "trader1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Sell,
+            OrderType::StopLimit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(1890, 0)),
+            Some(Decimal::new(1900, 0)),
+        )).unwrap().id;
+
+        assert!(dex.get_order_book("ETH/USDC").unwrap().get_best_ask().is_none());
+        assert_eq!(dex.stop_books["ETH/USDC"].len(), 1);
+
+        dex.cancel_order(&order_id, "trader1").unwrap();
+        assert!(dex.stop_books["ETH/USDC"].is_empty());
+        assert_eq!(dex.get_order(&order_id).unwrap().status, OrderStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_time_in_force_ioc_and_fok() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        dex.deposit("seller1", "ETH", Decimal::new(2, 0));
+        dex.place_order(OrderRequest::new(
+            "seller1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Sell,
+            OrderType::Limit,
+            Decimal::new(2, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        )).unwrap();
+
+        dex.deposit("buyer1", "USDC", Decimal::new(10000, 0));
+
+        // FOK for more than the book holds is rejected without trading
+        let result = dex.place_order(OrderRequest {
+            time_in_force: TimeInForce::FOK,
+            ..OrderRequest::new(
+                "buyer1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                Decimal::new(3, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )
+        });
+        assert!(result.is_err());
+        assert_eq!(dex.trades.len(), 0);
+
+        // IOC takes what it can and cancels the rest instead of resting
+        let ioc_id = dex.place_order(OrderRequest {
+            time_in_force: TimeInForce::IOC,
+            ..OrderRequest::new(
+                "buyer1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                Decimal::new(3, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )
+        }).unwrap().id;
+
+        let ioc_order = dex.get_order(&ioc_id).unwrap();
+        assert_eq!(ioc_order.filled_quantity, Decimal::new(2, 0));
+        assert_eq!(ioc_order.status, OrderStatus::Cancelled);
+        assert!(dex.get_order_book("ETH/USDC").unwrap().get_best_bid().is_none());
+    }
+
+    #[test]
+    fn test_gtd_requires_expiry() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+        dex.deposit("trader1", "USDC", Decimal::new(2000, 0));
+
+        let result = dex.place_order(OrderRequest {
+            time_in_force: TimeInForce::GTD,
+            ..OrderRequest::new(
+                "trader1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )
+        });
+        assert!(result.is_err());
+
+        let result = dex.place_order(OrderRequest {
+            time_in_force: TimeInForce::GTD,
+            expire_at: Some(Utc::now() + chrono::Duration::hours(1)),
+            ..OrderRequest::new(
+                "trader1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )
+        });
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_crossing_limit_order_matches_on_entry() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        // Two offers at the same price, then a better one
+        dex.deposit("seller1", "ETH", Decimal::new(1, 0));
+        dex.deposit("seller2", "ETH", Decimal::new(1, 0));
+        dex.deposit("seller3", "ETH", Decimal::new(1, 0));
+        for (seller, price) in [("seller1", 2010), ("seller2", 2010), ("seller3", 2000)] {
+            dex.place_order(OrderRequest::new(
+                seller.to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(price, 0)),
+                None,
+            )).unwrap();
+        }
+
+        dex.deposit("buyer1", "USDC", Decimal::new(10000, 0));
+        let order = dex.place_order(OrderRequest::new(
+            "buyer1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Limit,
+            Decimal::new(2, 0),
+            Some(Decimal::new(2010, 0)),
+            None,
+        )).unwrap();
+
+        // Best price first, then the earlier order at 2010, each at the resting price
+        assert_eq!(order.fills.len(), 2);
+        assert_eq!(order.fills[0].price, Decimal::new(2000, 0));
+        assert_eq!(order.fills[1].price, Decimal::new(2010, 0));
+        assert!(order.fills.iter().all(|fill| fill.liquidity == Liquidity::Taker));
+        assert_eq!(dex.trades[1].seller, "seller1");
+        assert_eq!(order.status, OrderStatus::Filled);
+
+        let order = dex.place_order(OrderRequest::new(
+            "buyer1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Limit,
+            Decimal::new(2, 0),
+            Some(Decimal::new(2010, 0)),
+            None,
+        )).unwrap();
+        assert_eq!(order.status, OrderStatus::Partial);
+        assert_eq!(dex.trades[2].seller, "seller2");
+
+        // Only the unfilled remainder rests, and the book is no longer crossed
+        let order_book = dex.get_order_book("ETH/USDC").unwrap();
+        assert_eq!(order_book.get_bid_levels(1)[0].quantity, Decimal::new(1, 0));
+        assert!(order_book.get_best_ask().is_none());
+        assert_eq!(dex.get_order(&dex.trades[0].sell_order_id).unwrap().status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn test_open_orders_hold_funds() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+        dex.deposit("buyer1", "USDC", Decimal::new(5000, 0));
+
+        let first_id = dex.place_order(OrderRequest::new(
+            "buyer1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Limit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        )).unwrap().id;
+        dex.place_order(OrderRequest::new(
+            "buyer1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Limit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        )).unwrap();
+        assert_eq!(dex.get_user_balance("buyer1", "USDC"), Decimal::new(1000, 0));
+        assert_eq!(dex.get_locked_balance("buyer1", "USDC"), Decimal::new(4000, 0));
+
+        // The same funds cannot back a third order
+        let result = dex.place_order(OrderRequest::new(
+            "buyer1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Limit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        ));
+        assert!(result.is_err());
+
+        // Cancelling releases the hold
+        dex.cancel_order(&first_id, "buyer1").unwrap();
+        assert_eq!(dex.get_user_balance("buyer1", "USDC"), Decimal::new(3000, 0));
+        assert_eq!(dex.get_locked_balance("buyer1", "USDC"), Decimal::new(2000, 0));
+
+        // A fill consumes it
+        dex.deposit("seller1", "ETH", Decimal::new(1, 0));
+        dex.place_order(OrderRequest::new(
+            "seller1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Sell,
+            OrderType::Market,
+            Decimal::new(1, 0),
+            None,
+            None,
+        )).unwrap();
+        assert_eq!(dex.get_balance("buyer1", "USDC"), Balance { available: Decimal::new(3000, 0), locked: Decimal::ZERO });
+        assert_eq!(dex.get_balance("buyer1", "ETH").available, Decimal::new(1, 0));
+        assert_eq!(dex.get_balance("seller1", "ETH").total(), Decimal::ZERO);
+        assert_eq!(dex.get_user_balance("seller1", "USDC"), Decimal::new(2000, 0));
+    }
+
+    #[test]
+    fn test_market_buy_hold_stops_at_book_depth_and_band() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+        dex.deposit("buyer1", "USDC", Decimal::new(100000, 0));
+        dex.deposit("seller1", "ETH", Decimal::new(3, 0));
+        for price in [2000, 2100, 2500] {
+            dex.place_order(OrderRequest::new(
+                "seller1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(price, 0)),
+                None,
+            )).unwrap();
+        }
+
+        let market_buy = |dex: &DEXEngine, quantity: i64| {
+            let order = dex.prepare_order(OrderRequest::new(
+                "buyer1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Market,
+                Decimal::new(quantity, 0),
+                None,
+                None,
+            ), None).unwrap();
+            dex.initial_hold(&order).1
+        };
+
+        // The hold covers the whole size at the worst ask it reaches, not the whole balance
+        assert_eq!(market_buy(&dex, 1), Decimal::new(2000, 0));
+        assert_eq!(market_buy(&dex, 2), Decimal::new(4200, 0));
+        assert_eq!(market_buy(&dex, 5), Decimal::new(12500, 0));
+
+        // Asks beyond the band cannot trade, so they do not raise it
+        dex.set_price_bands("ETH/USDC", PriceBands::new(Some(Decimal::new(10, 0)), None)).unwrap();
+        dex.set_reference_price("ETH/USDC", Decimal::new(2000, 0)).unwrap();
+        assert_eq!(market_buy(&dex, 5), Decimal::new(10500, 0));
+
+        // It never takes more than the trader has
+        dex.withdraw("buyer1", "USDC", Decimal::new(95000, 0)).unwrap();
+        assert_eq!(market_buy(&dex, 5), Decimal::new(5000, 0));
+    }
+
+    #[test]
+    fn test_market_buy_fills_whole_steps_it_can_afford() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol_spec(SymbolSpec {
+            step_size: Decimal::new(1, 3),
+            ..SymbolSpec::new("ETH/USDC".to_string(), "ETH".to_string(), "USDC".to_string())
+        }).unwrap();
+        dex.deposit("buyer1", "USDC", Decimal::new(1000, 0));
+        dex.deposit("seller1", "ETH", Decimal::new(3, 0));
+        dex.place_order(OrderRequest::new(
+            "seller1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Sell,
+            OrderType::Limit,
+            Decimal::new(3, 0),
+            Some(Decimal::new(3000, 0)),
+            None,
+        )).unwrap();
+
+        // 1000 USDC buys 0.3333... ETH at 3000; the fill stops at the last whole 0.001 step
+        let buy = dex.place_order(OrderRequest::new(
+            "buyer1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Market,
+            Decimal::new(1, 0),
+            None,
+            None,
+        )).unwrap();
+        assert_eq!(buy.fills.len(), 1);
+        assert_eq!(buy.fills[0].quantity, Decimal::new(333, 3));
+        assert_eq!(dex.get_balance("buyer1", "ETH").available, Decimal::new(333, 3));
+        assert_eq!(dex.get_balance("buyer1", "USDC"), Balance { available: Decimal::new(1, 0), locked: Decimal::ZERO });
+    }
+
+    #[test]
+    fn test_cancel_keeps_queue_and_depth_in_sync() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        let mut order_ids = Vec::new();
+        for trader in ["buyer1", "buyer2", "buyer3"] {
+            dex.deposit(trader, "USDC", Decimal::new(2000, 0));
+            order_ids.push(dex.place_order(OrderRequest::new(
+                trader.to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )).unwrap().id);
+        }
+
+        dex.cancel_order(&order_ids[1], "buyer2").unwrap();
+
+        let order_book = dex.get_order_book("ETH/USDC").unwrap();
+        assert!(!order_book.contains(&order_ids[1]));
+        assert_eq!(order_book.order_count(), 2);
+        assert_eq!(order_book.get_order_location(&order_ids[2]).unwrap().price, Decimal::new(2000, 0));
+        let level = &order_book.get_bid_levels(1)[0];
+        assert_eq!((level.quantity, level.order_count), (Decimal::new(2, 0), 2));
+
+        // A partial fill updates the resting order in place and keeps it first in the queue
+        dex.deposit("seller1", "ETH", Decimal::new(2, 0));
+        dex.place_order(OrderRequest::new(
+            "seller1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Sell,
+            OrderType::Market,
+            Decimal::new(5, 1),
+            None,
+            None,
+        )).unwrap();
+
+        let first = dex.get_order(&order_ids[0]).unwrap();
+        assert_eq!(first.status, OrderStatus::Partial);
+        assert_eq!(first.remaining_quantity, Decimal::new(5, 1));
+        let order_book = dex.get_order_book("ETH/USDC").unwrap();
+        assert_eq!(order_book.get_best_order(&OrderSide::Buy).unwrap().1, order_ids[0]);
+        assert_eq!(order_book.get_bid_levels(1)[0].quantity, Decimal::new(15, 1));
+    }
+
+    #[test]
+    fn test_amend_order_priority() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        let mut order_ids = Vec::new();
+        for trader in ["buyer1", "buyer2"] {
+            dex.deposit(trader, "USDC", Decimal::new(10000, 0));
+            order_ids.push(dex.place_order(OrderRequest::new(
+                trader.to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                Decimal::new(2, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )).unwrap().id);
+        }
+
+        // Reducing size keeps the place in the queue and frees part of the hold
+        let amended = dex.amend_order(&order_ids[0], "buyer1", Some(Decimal::new(1, 0)), None).unwrap();
+        assert!(amended.amend_history[0].kept_priority);
+        assert_eq!(dex.get_locked_balance("buyer1", "USDC"), Decimal::new(2000, 0));
+        let order_book = dex.get_order_book("ETH/USDC").unwrap();
+        assert_eq!(order_book.get_best_order(&OrderSide::Buy).unwrap().1, order_ids[0]);
+        assert_eq!(order_book.get_bid_levels(1)[0].quantity, Decimal::new(3, 0));
+
+        // Increasing it again loses priority to buyer2
+        let amended = dex.amend_order(&order_ids[0], "buyer1", Some(Decimal::new(3, 0)), None).unwrap();
+        assert!(!amended.amend_history[1].kept_priority);
+        assert_eq!(amended.id, order_ids[0]);
+        assert_eq!(dex.get_locked_balance("buyer1", "USDC"), Decimal::new(6000, 0));
+        let order_book = dex.get_order_book("ETH/USDC").unwrap();
+        assert_eq!(order_book.get_best_order(&OrderSide::Buy).unwrap().1, order_ids[1]);
+
+        // Moving the price through the offer trades straight away
+        dex.deposit("seller1", "ETH", Decimal::new(1, 0));
+        dex.place_order(OrderRequest::new(
+            "seller1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Sell,
+            OrderType::Limit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(2050, 0)),
+            None,
+        )).unwrap();
+        let amended = dex.amend_order(&order_ids[1], "buyer2", None, Some(Decimal::new(2050, 0))).unwrap();
+        assert_eq!(amended.filled_quantity, Decimal::new(1, 0));
+        assert_eq!(amended.status, OrderStatus::Partial);
+        assert_eq!(dex.trades.last().unwrap().price, Decimal::new(2050, 0));
+
+        assert!(dex.amend_order(&order_ids[1], "buyer2", Some(Decimal::new(1, 0)), None).is_err());
+    }
+
+    #[test]
+    fn test_post_only_orders() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        dex.deposit("seller1", "ETH", Decimal::new(1, 0));
+        dex.place_order(OrderRequest::new(
+            "seller1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Sell,
+            OrderType::Limit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        )).unwrap();
+
+        dex.deposit("maker1", "USDC", Decimal::new(10000, 0));
+        let result = dex.place_order(OrderRequest {
+            instructions: ExecInstructions { post_only: Some(PostOnly::Reject), ..Default::default() },
+            ..OrderRequest::new(
+                "maker1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(2005, 0)),
+                None,
+            )
+        });
+        assert!(result.is_err());
+
+        let order = dex.place_order(OrderRequest {
+            instructions: ExecInstructions { post_only: Some(PostOnly::Slide), ..Default::default() },
+            ..OrderRequest::new(
+                "maker1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(2005, 0)),
+                None,
+            )
+        }).unwrap();
+        assert_eq!(order.price, Some(Decimal::new(199999, 2)));
+        assert_eq!(order.instructions.post_only, Some(PostOnly::Slide));
+        assert!(order.fills.is_empty());
+        assert_eq!(dex.get_locked_balance("maker1", "USDC"), Decimal::new(199999, 2));
+    }
+
+    #[test]
+    fn test_hidden_and_reduce_only_orders() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        // A hidden offer is matched but never shows in depth
+        dex.deposit("seller1", "ETH", Decimal::new(2, 0));
+        dex.place_order(OrderRequest {
+            instructions: ExecInstructions { hidden: true, ..Default::default() },
+            ..OrderRequest::new(
+                "seller1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(2, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )
+        }).unwrap();
+        let order_book = dex.get_order_book("ETH/USDC").unwrap();
+        assert!(order_book.get_ask_levels(10).is_empty());
+        assert_eq!(order_book.fillable_quantity(&OrderSide::Buy, None), Decimal::new(2, 0));
+
+        dex.deposit("hedger1", "USDC", Decimal::new(10000, 0));
+        dex.place_order(OrderRequest::new(
+            "hedger1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Limit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        )).unwrap();
+        assert_eq!(dex.get_position("hedger1", "ETH/USDC"), Decimal::new(1, 0));
+
+        // Reduce-only cannot add to the long position, and a sell is capped at its size
+        let result = dex.place_order(OrderRequest {
+            instructions: ExecInstructions { reduce_only: true, ..Default::default() },
+            ..OrderRequest::new(
+                "hedger1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(1900, 0)),
+                None,
+            )
+        });
+        assert!(result.is_err());
+
+        let order = dex.place_order(OrderRequest {
+            instructions: ExecInstructions { reduce_only: true, ..Default::default() },
+            ..OrderRequest::new(
+                "hedger1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(5, 0),
+                Some(Decimal::new(2100, 0)),
+                None,
+            )
+        }).unwrap();
+        assert_eq!(order.quantity, Decimal::new(1, 0));
+        assert!(order.instructions.reduce_only);
+    }
+
+    #[test]
+    fn test_reduce_only_closes_a_position_bought_with_fees() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+        dex.set_fee_schedule("ETH/USDC", FeeSchedule::new(vec![
+            FeeTier { min_volume: Decimal::ZERO, maker_rate: Decimal::new(1, 3), taker_rate: Decimal::new(2, 3) },
+        ])).unwrap();
+        dex.deposit("seller1", "ETH", Decimal::new(1, 0));
+        dex.deposit("hedger1", "USDC", Decimal::new(10000, 0));
+        dex.deposit("buyer1", "USDC", Decimal::new(10000, 0));
+
+        let limit = |trader: &str, side: OrderSide, quantity: Decimal, price: i64, reduce_only: bool| OrderRequest {
+            instructions: ExecInstructions { reduce_only, ..Default::default() },
+            ..OrderRequest::new(
+                trader.to_string(),
+                "ETH/USDC".to_string(),
+                side,
+                OrderType::Limit,
+                quantity,
+                Some(Decimal::new(price, 0)),
+                None,
+            )
+        };
+        dex.place_order(limit("seller1", OrderSide::Sell, Decimal::ONE, 2000, false)).unwrap();
+        dex.place_order(limit("hedger1", OrderSide::Buy, Decimal::ONE, 2000, false)).unwrap();
+
+        // The taker fee came out of the ETH bought, and the position is what arrived
+        assert_eq!(dex.get_balance("hedger1", "ETH").available, Decimal::new(998, 3));
+        assert_eq!(dex.get_position("hedger1", "ETH/USDC"), Decimal::new(998, 3));
+
+        // So a reduce-only sell for all of it can be funded, and closing it leaves nothing open
+        let close = dex.place_order(limit("hedger1", OrderSide::Sell, Decimal::new(5, 0), 2100, true)).unwrap();
+        assert_eq!(close.quantity, Decimal::new(998, 3));
+        dex.place_order(limit("buyer1", OrderSide::Buy, Decimal::ONE, 2100, false)).unwrap();
+        assert_eq!(dex.get_order(&close.id).unwrap().status, OrderStatus::Filled);
+        assert_eq!(dex.get_position("hedger1", "ETH/USDC"), Decimal::ZERO);
+        assert_eq!(dex.get_balance("hedger1", "ETH").total(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_iceberg_refreshes_to_back_of_queue() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        dex.deposit("desk1", "ETH", Decimal::new(10, 0));
+        let iceberg_id = dex.place_order(OrderRequest {
+            instructions: ExecInstructions { display_quantity: Some(Decimal::new(2, 0)), ..Default::default() },
+            ..OrderRequest::new(
+                "desk1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(10, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )
+        }).unwrap().id;
+
+        dex.deposit("seller1", "ETH", Decimal::new(1, 0));
+        dex.place_order(OrderRequest::new(
+            "seller1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Sell,
+            OrderType::Limit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        )).unwrap();
+
+        let level = &dex.get_order_book("ETH/USDC").unwrap().get_ask_levels(1)[0];
+        assert_eq!((level.quantity, level.order_count), (Decimal::new(3, 0), 2));
+
+        // Taking the slice refreshes the iceberg behind seller1
+        dex.deposit("buyer1", "USDC", Decimal::new(10000, 0));
+        dex.place_order(OrderRequest::new(
+            "buyer1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Limit,
+            Decimal::new(3, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        )).unwrap();
+
+        assert_eq!(dex.trades.len(), 2);
+        assert_eq!((dex.trades[0].seller.as_str(), dex.trades[0].quantity), ("desk1", Decimal::new(2, 0)));
+        assert_eq!(dex.trades[1].seller, "seller1");
+
+        let level = &dex.get_order_book("ETH/USDC").unwrap().get_ask_levels(1)[0];
+        assert_eq!((level.quantity, level.order_count), (Decimal::new(2, 0), 1));
+        assert_eq!(dex.get_order(&iceberg_id).unwrap().remaining_quantity, Decimal::new(8, 0));
+    }
+
+    #[test]
+    fn test_trailing_stop_follows_prints() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        dex.deposit("maker1", "ETH", Decimal::new(10, 0));
+        dex.deposit("maker1", "USDC", Decimal::new(100000, 0));
+        dex.deposit("taker1", "ETH", Decimal::new(10, 0));
+        dex.deposit("taker1", "USDC", Decimal::new(100000, 0));
+        dex.deposit("trader1", "ETH", Decimal::new(1, 0));
+
+        let trade_at = |dex: &mut DEXEngine, price: i64| {
+            dex.place_order(OrderRequest::new(
+                "maker1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(price, 0)),
+                None,
+            )).unwrap();
+            dex.place_order(OrderRequest::new(
+                "taker1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Market,
+                Decimal::new(1, 0),
+                None,
+                None,
+            )).unwrap();
+        };
+
+        trade_at(&mut dex, 2000);
+        let order_id = dex.place_trailing_stop_order(
+            OrderRequest::new(
+                "trader1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::TrailingStop,
+                Decimal::new(1, 0),
+                None,
+                None,
+            ),
+            TrailingOffset::Percent(Decimal::new(5, 0)),
+            None,
+        ).unwrap().id;
+        assert_eq!(dex.get_order(&order_id).unwrap().stop_price, Some(Decimal::new(1900, 0)));
+
+        // The trigger level follows the market up but never back down
+        trade_at(&mut dex, 2200);
+        trade_at(&mut dex, 2100);
+        let order = dex.get_user_orders("trader1").into_iter().find(|o| o.id == order_id).unwrap();
+        assert_eq!(order.order_type, OrderType::TrailingStop);
+        assert_eq!(order.stop_price, Some(Decimal::new(2090, 0)));
+        assert_eq!(order.trailing.unwrap().best_price, Decimal::new(2200, 0));
+
+        // A print through the trigger level fires it as a market order into the bid
+        dex.place_order(OrderRequest::new(
+            "maker1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Limit,
+            Decimal::new(2, 0),
+            Some(Decimal::new(2080, 0)),
+            None,
+        )).unwrap();
+        dex.place_order(OrderRequest::new(
+            "taker1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Sell,
+            OrderType::Market,
+            Decimal::new(1, 0),
+            None,
+            None,
+        )).unwrap();
+
+        let order = dex.get_order(&order_id).unwrap();
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(order.fills[0].price, Decimal::new(2080, 0));
+    }
+
+    #[test]
+    fn test_oco_fill_shrinks_then_cancels_sibling() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        dex.deposit("trader1", "ETH", Decimal::new(1, 0));
+        dex.deposit("trader2", "USDC", Decimal::new(100000, 0));
+
+        // Both legs sell the same ETH, so the group holds it once
+        let group = dex.place_oco_order(
+            OrderRequest::new(
+                "trader1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(2200, 0)),
+                None,
+            ),
+            OrderLeg { order_type: OrderType::Stop, price: None, stop_price: Some(Decimal::new(1800, 0)) },
+        ).unwrap();
+        let (take_profit_id, stop_loss_id) = (group.order_ids[0].clone(), group.order_ids[1].clone());
+        assert_eq!(dex.get_locked_balance("trader1", "ETH"), Decimal::new(1, 0));
+
+        let buy = |dex: &mut DEXEngine, quantity: Decimal| {
+            dex.place_order(OrderRequest::new(
+                "trader2".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Market,
+                quantity,
+                None,
+                None,
+            )).unwrap();
+        };
+
+        // A partial fill on one leg shrinks the other to match
+        buy(&mut dex, Decimal::new(4, 1));
+        let stop_loss = dex.get_order(&stop_loss_id).unwrap();
+        assert_eq!(stop_loss.remaining_quantity, Decimal::new(6, 1));
+        assert_eq!(stop_loss.status, OrderStatus::Pending);
+        assert_eq!(dex.get_locked_balance("trader1", "ETH"), Decimal::new(6, 1));
+
+        // Filling it cancels the other and closes the group
+        buy(&mut dex, Decimal::new(6, 1));
+        assert_eq!(dex.get_order(&take_profit_id).unwrap().status, OrderStatus::Filled);
+        assert_eq!(dex.get_order(&stop_loss_id).unwrap().status, OrderStatus::Cancelled);
+        assert_eq!(dex.get_order_group(&group.id).unwrap().status, OrderGroupStatus::Completed);
+        assert_eq!(dex.get_balance("trader1", "ETH"), Balance::default());
+        assert_eq!(dex.get_user_balance("trader1", "USDC"), Decimal::new(2200, 0));
+    }
+
+    #[test]
+    fn test_bracket_exits_arm_after_entry_fills() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+
+        dex.deposit("trader1", "USDC", Decimal::new(10000, 0));
+        dex.deposit("trader2", "ETH", Decimal::new(1, 0));
+
+        let place_bracket = |dex: &mut DEXEngine| {
+            dex.place_bracket_order(
+                OrderRequest::new(
+                    "trader1".to_string(),
+                    "ETH/USDC".to_string(),
+                    OrderSide::Buy,
+                    OrderType::Limit,
+                    Decimal::new(1, 0),
+                    Some(Decimal::new(2000, 0)),
+                    None,
+                ),
+                Decimal::new(2200, 0),
+                Decimal::new(1900, 0),
+            ).unwrap()
+        };
+
+        let group = place_bracket(&mut dex);
+        let exit_ids = group.exit_order_ids();
+        for order in dex.get_group_orders(&group.id).iter().filter(|order| exit_ids.contains(&order.id)) {
+            assert_eq!(order.status, OrderStatus::Inactive);
+            assert_eq!(order.group_id, Some(group.id.clone()));
+        }
+        assert_eq!(dex.get_order_book("ETH/USDC").unwrap().order_count(), 1);
+
+        // Once the entry fills, the exits go live for the bought ETH
+        dex.place_order(OrderRequest::new(
+            "trader2".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Sell,
+            OrderType::Limit,
+            Decimal::new(1, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        )).unwrap();
+        assert_eq!(dex.get_order(&exit_ids[0]).unwrap().status, OrderStatus::Pending);
+        assert_eq!(dex.get_order(&exit_ids[1]).unwrap().status, OrderStatus::Pending);
+        assert_eq!(dex.get_order_book("ETH/USDC").unwrap().get_best_ask(), Some(Decimal::new(2200, 0)));
+        assert_eq!(dex.get_locked_balance("trader1", "ETH"), Decimal::new(1, 0));
+
+        let group = dex.cancel_order_group(&group.id, "trader1").unwrap();
+        assert_eq!(group.status, OrderGroupStatus::Cancelled);
+        assert!(dex.get_group_orders(&group.id).iter().all(|order| !order.is_open()));
+        assert_eq!(dex.get_user_balance("trader1", "ETH"), Decimal::new(1, 0));
+
+        // An entry cancelled before it fills takes its exits with it
+        let group = place_bracket(&mut dex);
+        let entry_id = group.entry_order_id.clone().unwrap();
+        dex.cancel_order(&entry_id, "trader1").unwrap();
+        assert!(group.exit_order_ids().iter().all(|id| dex.get_order(id).unwrap().status == OrderStatus::Cancelled));
+        assert_eq!(dex.get_order_group(&group.id).unwrap().status, OrderGroupStatus::Cancelled);
+        assert_eq!(dex.get_locked_balance("trader1", "USDC"), Decimal::ZERO);
+
+        // Grouped orders share one size and hold, so per-order instructions are refused
+        let entry = OrderRequest {
+            instructions: ExecInstructions { hidden: true, ..Default::default() },
+            ..OrderRequest::new(
+                "trader1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                Decimal::new(1, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )
+        };
+        let error = dex.place_bracket_order(entry, Decimal::new(2200, 0), Decimal::new(1900, 0)).unwrap_err();
+        assert_eq!(error, "Grouped orders cannot carry execution instructions");
+    }
+
+    #[test]
+    fn test_maker_taker_fees_and_volume_tiers() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+        dex.set_fee_schedule("ETH/USDC", FeeSchedule::new(vec![
+            FeeTier { min_volume: Decimal::ZERO, maker_rate: Decimal::new(1, 3), taker_rate: Decimal::new(2, 3) },
+            FeeTier { min_volume: Decimal::new(100000, 0), maker_rate: Decimal::ZERO, taker_rate: Decimal::new(1, 3) },
+        ])).unwrap();
+
+        dex.deposit("maker1", "ETH", Decimal::new(100, 0));
+        dex.deposit("taker1", "USDC", Decimal::new(1000000, 0));
+
+        let trade = |dex: &mut DEXEngine, quantity: i64| {
+            dex.place_order(OrderRequest::new(
+                "maker1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(quantity, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )).unwrap();
+            dex.place_order(OrderRequest::new(
+                "taker1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Market,
+                Decimal::new(quantity, 0),
+                None,
+                None,
+            )).unwrap();
+            dex.get_recent_trades("ETH/USDC", 1).remove(0)
+        };
+
+        // Each side pays in the asset it receives, at its maker or taker rate
+        let first = trade(&mut dex, 1);
+        assert_eq!(first.buyer_fee, Decimal::new(2, 3));
+        assert_eq!(first.seller_fee, Decimal::new(2, 0));
+        assert_eq!(dex.get_user_balance("taker1", "ETH"), Decimal::new(998, 3));
+        assert_eq!(dex.get_user_balance("maker1", "USDC"), Decimal::new(1998, 0));
+        assert_eq!(dex.get_user_balance("fees", "ETH"), Decimal::new(2, 3));
+        assert_eq!(dex.get_user_balance("fees", "USDC"), Decimal::new(2, 0));
 
-        // Match buy and sell orders
-        while let (Some(bid_price), Some(ask_price)) = (order_book.get_best_bid(), order_book.get_best_ask()) {
-            if bid_price < ask_price {
-                break; // No more matches possible
-            }
+        // Enough 30-day volume moves both traders into the next tier
+        trade(&mut dex, 49);
+        assert_eq!(dex.get_trading_volume("taker1", "ETH/USDC"), Decimal::new(100000, 0));
+        assert_eq!(dex.get_fee_rates("taker1", "ETH/USDC"), (Decimal::ZERO, Decimal::new(1, 3)));
 
-            let bid_orders = order_book.bids.get_mut(&bid_price).unwrap();
-            let ask_orders = order_book.asks.get_mut(&ask_price).unwrap();
+        let last = trade(&mut dex, 1);
+        assert_eq!(last.buyer_fee, Decimal::new(1, 3));
+        assert_eq!(last.seller_fee, Decimal::ZERO);
+    }
 
-            if bid_orders.is_empty() || ask_orders.is_empty() {
-                break;
-            }
+    #[test]
+    fn test_self_trade_prevention() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
 
-            let buy_order = bid_orders.front_mut().unwrap();
-            let sell_order = ask_orders.front_mut().unwrap();
+        dex.deposit("trader1", "ETH", Decimal::new(10, 0));
+        dex.deposit("trader1", "USDC", Decimal::new(100000, 0));
 
-            let match_quantity = buy_order.remaining_quantity.min(sell_order.remaining_quantity);
-            let match_price = if buy_order.created_at < sell_order.created_at { bid_price } else { ask_price };
+        let place = |dex: &mut DEXEngine, side: OrderSide, quantity: Decimal, price: i64,
+                     self_trade_prevention: Option<SelfTradePrevention>| {
+            dex.place_order(OrderRequest {
+                instructions: ExecInstructions { self_trade_prevention, ..Default::default() },
+                ..OrderRequest::new(
+                    "trader1".to_string(),
+                    "ETH/USDC".to_string(),
+                    side,
+                    OrderType::Limit,
+                    quantity,
+                    Some(Decimal::new(price, 0)),
+                    None,
+                )
+            }).unwrap()
+        };
 
-            self.execute_trade(buy_order, sell_order, match_price, match_quantity);
+        let first_sell = place(&mut dex, OrderSide::Sell, Decimal::new(1, 0), 2000, None);
+        let second_sell = place(&mut dex, OrderSide::Sell, Decimal::new(1, 0), 2010, None);
 
-            if buy_order.remaining_quantity == Decimal::ZERO {
-                bid_orders.pop_front();
-            }
-            if sell_order.remaining_quantity == Decimal::ZERO {
-                ask_orders.pop_front();
-            }
+        // The order's own instruction cancels the incoming buy and leaves the book alone
+        let buy = place(&mut dex, OrderSide::Buy, Decimal::new(1, 0), 2000, Some(SelfTradePrevention::CancelNewest));
+        assert_eq!(buy.status, OrderStatus::Cancelled);
+        assert_eq!(buy.self_trade_prevented, Decimal::new(1, 0));
+        assert_eq!(dex.get_order_book("ETH/USDC").unwrap().get_best_ask(), Some(Decimal::new(2000, 0)));
 
-            if bid_orders.is_empty() {
-                order_book.bids.remove(&bid_price);
-            }
-            if ask_orders.is_empty() {
-                order_book.asks.remove(&ask_price);
-            }
+        // The trader's setting cancels the resting sells instead, and the buy rests
+        dex.set_self_trade_prevention("trader1", Some(SelfTradePrevention::CancelOldest));
+        let buy = place(&mut dex, OrderSide::Buy, Decimal::new(2, 0), 2010, None);
+        assert_eq!(buy.status, OrderStatus::Pending);
+        for order_id in [&first_sell.id, &second_sell.id] {
+            let order = dex.get_order(order_id).unwrap();
+            assert_eq!(order.status, OrderStatus::Cancelled);
+            assert_eq!(order.self_trade_prevented, Decimal::new(1, 0));
         }
+        assert!(dex.get_recent_trades("ETH/USDC", 10).is_empty());
 
-        Ok(())
+        // Decrement-and-cancel takes the overlap off both orders
+        let sell = place(&mut dex, OrderSide::Sell, Decimal::new(5, 1), 2010, Some(SelfTradePrevention::DecrementAndCancel));
+        assert_eq!(sell.status, OrderStatus::Cancelled);
+        assert_eq!(sell.self_trade_prevented, Decimal::new(5, 1));
+        let buy = dex.get_order(&buy.id).unwrap();
+        assert_eq!(buy.quantity, Decimal::new(15, 1));
+        assert_eq!(buy.self_trade_prevented, Decimal::new(5, 1));
+        assert_eq!(dex.get_order_book("ETH/USDC").unwrap().get_bid_levels(1)[0].quantity, Decimal::new(15, 1));
+        assert_eq!(dex.get_locked_balance("trader1", "USDC"), Decimal::new(3015, 0));
+        assert_eq!(dex.get_locked_balance("trader1", "ETH"), Decimal::ZERO);
     }
 
-    fn execute_trade(&mut self, buy_order: &mut Order, sell_order: &mut Order, price: Decimal, quantity: Decimal) {
-        self.trade_counter += 1;
-        let trade_id = format!("trade_{}", self.trade_counter);
+    #[test]
+    fn test_fok_counts_only_what_self_trade_prevention_lets_trade() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+        dex.deposit("trader1", "ETH", Decimal::new(1, 0));
+        dex.deposit("trader1", "USDC", Decimal::new(10000, 0));
+        dex.deposit("trader2", "ETH", Decimal::new(2, 0));
 
-        let trade = Trade {
-            id: trade_id,
-            symbol: buy_order.symbol.clone(),
-            price,
-            quantity,
-            buy_order_id: buy_order.id.clone(),
-            sell_order_id: sell_order.id.clone(),
-            buyer: buy_order.trader.clone(),
-            seller: sell_order.trader.clone(),
-            timestamp: Utc::now(),
-            trade_type: "limit".to_string(),
+        let place = |dex: &mut DEXEngine, trader: &str, side: OrderSide, quantity: i64, price: i64,
+                     time_in_force: TimeInForce, self_trade_prevention: Option<SelfTradePrevention>| {
+            dex.place_order(OrderRequest {
+                time_in_force,
+                instructions: ExecInstructions { self_trade_prevention, ..Default::default() },
+                ..OrderRequest::new(
+                    trader.to_string(),
+                    "ETH/USDC".to_string(),
+                    side,
+                    OrderType::Limit,
+                    Decimal::new(quantity, 0),
+                    Some(Decimal::new(price, 0)),
+                    None,
+                )
+            })
         };
+        let own_sell = place(&mut dex, "trader1", OrderSide::Sell, 1, 2000, TimeInForce::GTC, None).unwrap();
+        place(&mut dex, "trader2", OrderSide::Sell, 1, 2001, TimeInForce::GTC, None).unwrap();
 
-        self.trades.push(trade);
+        // The trader's own sell would be cancelled or would cancel the buy, so it cannot count
+        for mode in [SelfTradePrevention::CancelOldest, SelfTradePrevention::CancelNewest, SelfTradePrevention::DecrementAndCancel] {
+            let error = place(&mut dex, "trader1", OrderSide::Buy, 2, 2001, TimeInForce::FOK, Some(mode)).unwrap_err();
+            assert_eq!(error, "Fill-or-kill order cannot be filled in full");
+        }
+        assert!(dex.get_order(&own_sell.id).unwrap().is_open());
+        assert!(dex.get_trades().is_empty());
 
-        // Update order quantities
-        buy_order.update_filled(quantity);
-        sell_order.update_filled(quantity);
+        // Once others cover the whole size, cancelling the trader's own sell lets it fill
+        place(&mut dex, "trader2", OrderSide::Sell, 1, 2001, TimeInForce::GTC, None).unwrap();
+        let buy = place(&mut dex, "trader1", OrderSide::Buy, 2, 2001, TimeInForce::FOK,
+                        Some(SelfTradePrevention::CancelOldest)).unwrap();
+        assert_eq!(buy.status, OrderStatus::Filled);
+        assert_eq!(dex.get_order(&own_sell.id).unwrap().status, OrderStatus::Cancelled);
+    }
 
-        // Update balances
-        let base_currency = self.get_base_currency(&buy_order.symbol);
-        let quote_currency = self.get_quote_currency(&buy_order.symbol);
+    #[test]
+    fn test_symbol_spec_validation() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol_spec(SymbolSpec {
+            tick_size: Decimal::new(5, 1),
+            step_size: Decimal::new(1, 3),
+            min_quantity: Decimal::new(1, 3),
+            max_quantity: Decimal::new(100, 0),
+            min_notional: Decimal::new(10, 0),
+            ..SymbolSpec::new("XBTUSD".to_string(), "BTC".to_string(), "USD".to_string())
+        }).unwrap();
+        assert!(dex.add_symbol_spec(SymbolSpec::new("XBTUSD".to_string(), "BTC".to_string(), "USD".to_string())).is_err());
 
-        let trade_value = price * quantity;
+        dex.deposit("trader1", "USD", Decimal::new(100000, 0));
+
+        let buy = |dex: &mut DEXEngine, quantity: Decimal, price: Decimal| {
+            dex.place_order(OrderRequest::new(
+                "trader1".to_string(),
+                "XBTUSD".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                quantity,
+                Some(price),
+                None,
+            ))
+        };
+
+        assert_eq!(
+            buy(&mut dex, Decimal::new(1, 0), Decimal::new(3000025, 2)).unwrap_err(),
+            "Price 30000.25 is not a multiple of the tick size 0.5 for XBTUSD",
+        );
+        assert_eq!(
+            buy(&mut dex, Decimal::new(15, 4), Decimal::new(30000, 0)).unwrap_err(),
+            "Quantity 0.0015 is not a multiple of the step size 0.001 for XBTUSD",
+        );
+        assert_eq!(
+            buy(&mut dex, Decimal::new(101, 0), Decimal::new(30000, 0)).unwrap_err(),
+            "Quantity 101 is outside the range 0.001 to 100 for XBTUSD",
+        );
+        assert_eq!(
+            buy(&mut dex, Decimal::new(1, 3), Decimal::new(5000, 0)).unwrap_err(),
+            "Order value 5 is below the minimum of 10 USD for XBTUSD",
+        );
+
+        // Holds come from the listed quote asset, not from the symbol's name
+        let order = buy(&mut dex, Decimal::new(1, 0), Decimal::new(300005, 1)).unwrap();
+        assert_eq!(dex.get_locked_balance("trader1", "USD"), Decimal::new(300005, 1));
 
-        // Buyer: -quote_currency, +base_currency
-        self.update_balance(&buy_order.trader, &quote_currency, self.get_user_balance(&buy_order.trader, &quote_currency) - trade_value);
-        self.update_balance(&buy_order.trader, &base_currency, self.get_user_balance(&buy_order.trader, &base_currency) + quantity);
+        // Amends are held to the same increments and leave the order as it was when they fail
+        assert_eq!(
+            dex.amend_order(&order.id, "trader1", None, Some(Decimal::new(3000025, 2))).unwrap_err(),
+            "Price 30000.25 is not a multiple of the tick size 0.5 for XBTUSD",
+        );
+        assert_eq!(
+            dex.amend_order(&order.id, "trader1", Some(Decimal::new(10015, 4)), None).unwrap_err(),
+            "Quantity 1.0015 is not a multiple of the step size 0.001 for XBTUSD",
+        );
+        let unchanged = dex.get_order(&order.id).unwrap();
+        assert_eq!((unchanged.quantity, unchanged.price), (order.quantity, order.price));
+        assert!(unchanged.amend_history.is_empty());
+        assert_eq!(dex.get_locked_balance("trader1", "USD"), Decimal::new(300005, 1));
 
-        // Seller: -base_currency, +quote_currency
-        self.update_balance(&sell_order.trader, &base_currency, self.get_user_balance(&sell_order.trader, &base_currency) - quantity);
-        self.update_balance(&sell_order.trader, &quote_currency, self.get_user_balance(&sell_order.trader, &quote_currency) + trade_value);
+        dex.set_trading_status("XBTUSD", TradingStatus::Halted).unwrap();
+        assert_eq!(
+            buy(&mut dex, Decimal::new(1, 0), Decimal::new(30000, 0)).unwrap_err(),
+            "Symbol XBTUSD is not trading (Halted)",
+        );
     }
 
-    pub fn cancel_order(&mut self, order_id: &str, trader: &str) -> Result<(), String> {
-        let order = self.orders.get(order_id)
-            .ok_or_else(|| "Order not found".to_string())?;
+    #[test]
+    fn test_depth_updates_follow_snapshot_sequence() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
 
-        if order.trader != trader {
-            return Err("Unauthorized".to_string());
-        }
+        dex.deposit("trader1", "ETH", Decimal::new(10, 0));
+        dex.deposit("trader2", "USDC", Decimal::new(100000, 0));
+        let updates = dex.subscribe_depth("ETH/USDC").unwrap();
 
-        if order.status != OrderStatus::Pending && order.status != OrderStatus::Partial {
-            return Err("Order cannot be cancelled".to_string());
-        }
+        let sell = |dex: &mut DEXEngine, quantity: i64, price: i64, hidden: bool| {
+            dex.place_order(OrderRequest {
+                instructions: ExecInstructions { hidden, ..Default::default() },
+                ..OrderRequest::new(
+                    "trader1".to_string(),
+                    "ETH/USDC".to_string(),
+                    OrderSide::Sell,
+                    OrderType::Limit,
+                    Decimal::new(quantity, 0),
+                    Some(Decimal::new(price, 0)),
+                    None,
+                )
+            }).unwrap()
+        };
 
-        // Remove from order book
-        if let Some(order_book) = self.order_books.get_mut(&order.symbol) {
-            order_book.remove_order(order_id);
-        }
+        sell(&mut dex, 1, 2000, false);
+        let second = sell(&mut dex, 2, 2000, false);
+        sell(&mut dex, 1, 2010, true);
+        dex.place_order(OrderRequest::new(
+            "trader2".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Market,
+            Decimal::new(15, 1),
+            None,
+            None,
+        )).unwrap();
 
-        // Update order status
-        let order = self.orders.get_mut(order_id).unwrap();
-        order.status = OrderStatus::Cancelled;
-        order.updated_at = Utc::now();
+        // The hidden order never shows up, and the sweep is one update for the level it left behind
+        let received: Vec<(u64, Decimal, usize)> = updates.try_iter()
+            .map(|update| (update.sequence, update.quantity, update.order_count))
+            .collect();
+        assert_eq!(received, vec![
+            (1, Decimal::new(1, 0), 1),
+            (2, Decimal::new(3, 0), 2),
+            (3, Decimal::new(15, 1), 1),
+        ]);
 
-        Ok(())
-    }
+        let snapshot = dex.get_depth_snapshot("ETH/USDC", 10).unwrap();
+        assert_eq!(snapshot.sequence, 3);
+        assert_eq!(snapshot.asks, vec![OrderBookLevel { price: Decimal::new(2000, 0), quantity: Decimal::new(15, 1), order_count: 1 }]);
 
-    pub fn get_order(&self, order_id: &str) -> Option<Order> {
-        self.orders.get(order_id).cloned()
+        dex.cancel_order(&second.id, "trader1").unwrap();
+        let update = updates.try_recv().unwrap();
+        assert_eq!((update.sequence, update.side, update.price, update.quantity), (4, OrderSide::Sell, Decimal::new(2000, 0), Decimal::ZERO));
+        assert!(updates.try_recv().is_err());
     }
 
-    pub fn get_user_orders(&self, trader: &str) -> Vec<Order> {
-        self.orders.values()
-            .filter(|order| order.trader == trader)
-            .cloned()
-            .collect()
-    }
+    #[test]
+    fn test_order_events_rebuild_book() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
 
-    pub fn get_order_book(&self, symbol: &str) -> Option<OrderBook> {
-        self.order_books.get(symbol).cloned()
-    }
+        dex.deposit("trader1", "ETH", Decimal::new(10, 0));
+        dex.deposit("trader2", "USDC", Decimal::new(100000, 0));
+        let events = dex.subscribe_order_events("ETH/USDC").unwrap();
 
-    pub fn get_recent_trades(&self, symbol: &str, limit: usize) -> Vec<Trade> {
-        self.trades.iter()
-            .filter(|trade| trade.symbol == symbol)
-            .rev()
-            .take(limit)
-            .cloned()
-            .collect()
-    }
+        let place = |dex: &mut DEXEngine, trader: &str, side: OrderSide, quantity: i64, price: i64,
+                     instructions: ExecInstructions| {
+            dex.place_order(OrderRequest {
+                instructions,
+                ..OrderRequest::new(
+                    trader.to_string(),
+                    "ETH/USDC".to_string(),
+                    side,
+                    OrderType::Limit,
+                    Decimal::new(quantity, 0),
+                    Some(Decimal::new(price, 0)),
+                    None,
+                )
+            }).unwrap()
+        };
 
-    pub fn get_ticker(&self, symbol: &str) -> Option<HashMap<String, Decimal>> {
-        let trades: Vec<&Trade> = self.trades.iter()
-            .filter(|trade| trade.symbol == symbol)
-            .collect();
+        place(&mut dex, "trader1", OrderSide::Sell, 3, 2000,
+              ExecInstructions { display_quantity: Some(Decimal::new(1, 0)), ..Default::default() });
+        place(&mut dex, "trader1", OrderSide::Sell, 1, 2000, ExecInstructions { hidden: true, ..Default::default() });
+        let resting_sell = place(&mut dex, "trader1", OrderSide::Sell, 2, 2010, ExecInstructions::default());
+        let bid = place(&mut dex, "trader2", OrderSide::Buy, 2, 1990, ExecInstructions::default());
+        place(&mut dex, "trader2", OrderSide::Buy, 1, 1990, ExecInstructions::default());
 
-        if trades.is_empty() {
-            return None;
+        // A repriced bid loses its place, a smaller one keeps it; the sweep refreshes the iceberg
+        dex.amend_order(&bid.id, "trader2", None, Some(Decimal::new(1995, 0))).unwrap();
+        dex.amend_order(&bid.id, "trader2", Some(Decimal::new(1, 0)), None).unwrap();
+        place(&mut dex, "trader2", OrderSide::Buy, 2, 2000, ExecInstructions::default());
+        dex.cancel_order(&resting_sell.id, "trader1").unwrap();
+
+        let mut replica = OrderBook::new("ETH/USDC".to_string());
+        let mut kinds = Vec::new();
+        for (expected_sequence, event) in (1..).zip(events.try_iter()) {
+            assert_eq!(event.sequence, expected_sequence);
+            replica.apply_event(&event);
+            if !kinds.contains(&event.kind) {
+                kinds.push(event.kind);
+            }
         }
+        assert_eq!(kinds.len(), 5);
+        assert!(!kinds.contains(&OrderEventKind::Expire));
 
-        let last_trade = trades.last().unwrap();
-        let mut prices: Vec<Decimal> = trades.iter().map(|t| t.price).collect();
-        prices.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let order_book = dex.get_order_book("ETH/USDC").unwrap();
+        assert_eq!(replica.bids, order_book.bids);
+        assert_eq!(replica.asks, order_book.asks);
+        assert_eq!(replica.order_count(), order_book.order_count());
+    }
 
-        let mut ticker = HashMap::new();
-        ticker.insert("last_price".to_string(), last_trade.price);
-        ticker.insert("bid".to_string(), self.order_books.get(symbol)?.get_best_bid().unwrap_or(Decimal::ZERO));
-        ticker.insert("ask".to_string(), self.order_books.get(symbol)?.get_best_ask().unwrap_or(Decimal::ZERO));
-        ticker.insert("high".to_string(), *prices.last().unwrap_or(&Decimal::ZERO));
-        ticker.insert("low".to_string(), *prices.first().unwrap_or(&Decimal::ZERO));
-        ticker.insert("volume".to_string(), trades.iter().map(|t| t.quantity).sum());
+    #[test]
+    fn test_amend_partially_filled_iceberg_keeps_replica_in_sync() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+        dex.deposit("desk1", "ETH", Decimal::new(10, 0));
+        dex.deposit("buyer1", "USDC", Decimal::new(100000, 0));
+        let events = dex.subscribe_order_events("ETH/USDC").unwrap();
+        let mut replica = OrderBook::new("ETH/USDC".to_string());
+        let mut assert_replica = |dex: &DEXEngine| {
+            for event in events.try_iter() {
+                replica.apply_event(&event);
+            }
+            let order_book = dex.get_order_book("ETH/USDC").unwrap();
+            assert_eq!((&replica.bids, &replica.asks), (&order_book.bids, &order_book.asks));
+        };
 
-        Some(ticker)
-    }
+        let iceberg_id = dex.place_order(OrderRequest {
+            instructions: ExecInstructions { display_quantity: Some(Decimal::new(2, 0)), ..Default::default() },
+            ..OrderRequest::new(
+                "desk1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(10, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )
+        }).unwrap().id;
+        dex.place_order(OrderRequest::new(
+            "buyer1".to_string(),
+            "ETH/USDC".to_string(),
+            OrderSide::Buy,
+            OrderType::Limit,
+            Decimal::new(3, 0),
+            Some(Decimal::new(2000, 0)),
+            None,
+        )).unwrap();
+        assert_replica(&dex);
 
-    pub fn get_user_balance(&self, user: &str, currency: &str) -> Decimal {
-        self.user_balances
-            .get(user)
-            .and_then(|balances| balances.get(currency))
-            .copied()
-            .unwrap_or(Decimal::ZERO)
-    }
+        // Shrinking below the refreshed slice shows only what is left, keeps priority and frees the ETH
+        assert!(dex.amend_order(&iceberg_id, "desk1", Some(Decimal::new(3, 0)), None).is_err());
+        let order = dex.amend_order(&iceberg_id, "desk1", Some(Decimal::new(4, 0)), None).unwrap();
+        assert_eq!((order.filled_quantity, order.remaining_quantity), (Decimal::new(3, 0), Decimal::new(1, 0)));
+        assert!(order.amend_history[0].kept_priority);
+        assert_eq!(dex.get_order_book("ETH/USDC").unwrap().get_ask_levels(1)[0].quantity, Decimal::new(1, 0));
+        assert_eq!(dex.get_balance("desk1", "ETH"), Balance { available: Decimal::new(6, 0), locked: Decimal::new(1, 0) });
+        assert_replica(&dex);
 
-    pub fn update_balance(&mut self, user: &str, currency: &str, amount: Decimal) {
-        self.user_balances
-            .entry(user.to_string())
-            .or_insert_with(HashMap::new)
-            .insert(currency.to_string(), amount);
-    }
+        // Growing and repricing it re-enters with a fresh slice
+        let order = dex.amend_order(&iceberg_id, "desk1", Some(Decimal::new(8, 0)), Some(Decimal::new(2010, 0))).unwrap();
+        assert_eq!(order.remaining_quantity, Decimal::new(5, 0));
+        let level = &dex.get_order_book("ETH/USDC").unwrap().get_ask_levels(1)[0];
+        assert_eq!((level.price, level.quantity), (Decimal::new(2010, 0), Decimal::new(2, 0)));
+        assert_eq!(dex.get_locked_balance("desk1", "ETH"), Decimal::new(5, 0));
+        assert_replica(&dex);
 
-    pub fn deposit(&mut self, user: &str, currency: &str, amount: Decimal) {
-        let current_balance = self.get_user_balance(user, currency);
-        self.update_balance(user, currency, current_balance + amount);
+        dex.cancel_order(&iceberg_id, "desk1").unwrap();
+        assert!(dex.get_order_book("ETH/USDC").unwrap().asks.is_empty());
+        assert_replica(&dex);
     }
 
-    pub fn withdraw(&mut self, user: &str, currency: &str, amount: Decimal) -> Result<(), String> {
-        let current_balance = self.get_user_balance(user, currency);
-        if current_balance < amount {
-            return Err("Insufficient balance".to_string());
-        }
-        self.update_balance(user, currency, current_balance - amount);
-        Ok(())
-    }
+    #[test]
+    fn test_amend_iceberg_below_its_slice_shrinks_the_slice() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+        dex.deposit("desk1", "ETH", Decimal::new(10, 0));
+        dex.deposit("buyer1", "USDC", Decimal::new(100000, 0));
 
-    fn validate_order(&self, order_type: &OrderType, price: Option<Decimal>, stop_price: Option<Decimal>) -> Result<(), String> {
-        match order_type {
-            OrderType::Limit => {
-                if price.is_none() || price.unwrap() <= Decimal::ZERO {
-                    return Err("Limit orders must have a valid price".to_string());
-                }
-            }
-            OrderType::Stop => {
-                if stop_price.is_none() || stop_price.unwrap() <= Decimal::ZERO {
-                    return Err("Stop orders must have a valid stop price".to_string());
-                }
-            }
-            OrderType::StopLimit => {
-                if price.is_none() || stop_price.is_none() ||
-                   price.unwrap() <= Decimal::ZERO || stop_price.unwrap() <= Decimal::ZERO {
-                    return Err("Stop-limit orders must have valid price and stop price".to_string());
-                }
-            }
-            OrderType::Market => {
-                // Market orders don't need price validation
-            }
-        }
-        Ok(())
-    }
+        let iceberg_id = dex.place_order(OrderRequest {
+            instructions: ExecInstructions { display_quantity: Some(Decimal::new(4, 0)), ..Default::default() },
+            ..OrderRequest::new(
+                "desk1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Sell,
+                OrderType::Limit,
+                Decimal::new(10, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )
+        }).unwrap().id;
+        let buy = |dex: &mut DEXEngine, quantity: i64| {
+            dex.place_order(OrderRequest::new(
+                "buyer1".to_string(),
+                "ETH/USDC".to_string(),
+                OrderSide::Buy,
+                OrderType::Limit,
+                Decimal::new(quantity, 0),
+                Some(Decimal::new(2000, 0)),
+                None,
+            )).unwrap()
+        };
+        buy(&mut dex, 1);
 
-    fn get_base_currency(&self, symbol: &str) -> String {
-        // Simple implementation - in real DEX, this would be configurable
-        symbol.split('/').next().unwrap_or("BASE").to_string()
-    }
+        // Three of the slice's four are showing; cutting the order to two left cuts the slice to two
+        let order = dex.amend_order(&iceberg_id, "desk1", Some(Decimal::new(3, 0)), None).unwrap();
+        assert_eq!((order.remaining_quantity, order.display_remaining), (Decimal::new(2, 0), Decimal::new(2, 0)));
+        assert_eq!(dex.get_order_book("ETH/USDC").unwrap().get_ask_levels(1)[0].quantity, Decimal::new(2, 0));
 
-    fn get_quote_currency(&self, symbol: &str) -> String {
-        // Simple implementation - in real DEX, this would be configurable
-        symbol.split('/').nth(1).unwrap_or("QUOTE").to_string()
+        // The last two fill the order without a leftover slice
+        let taker = buy(&mut dex, 5);
+        assert_eq!(taker.filled_quantity, Decimal::new(2, 0));
+        let order = dex.get_order(&iceberg_id).unwrap();
+        assert_eq!((order.status, order.display_remaining), (OrderStatus::Filled, Decimal::ZERO));
+        assert!(dex.get_order_book("ETH/USDC").unwrap().asks.is_empty());
     }
 
-    pub fn process_pending_orders(&mut self) {
-        let expired_orders: Vec<String> = self.orders.iter()
-            .filter(|(_, order)| order.status == OrderStatus::Pending && order.is_expired())
-            .map(|(id, _)| id.clone())
-            .collect();
+    #[test]
+    fn test_rolling_ticker_window() {
+        let start: DateTime<Utc> = "2024-03-01T00:00:00Z".parse().unwrap();
+        let mut window = TickerWindow::default();
+        window.record(start, Decimal::new(2100, 0), Decimal::new(1, 0));
+        window.record(start + Duration::hours(1), Decimal::new(1900, 0), Decimal::new(2, 0));
+        window.record(start + Duration::hours(20), Decimal::new(2000, 0), Decimal::new(1, 0));
 
-        for order_id in expired_orders {
-            if let Some(order) = self.orders.get_mut(&order_id) {
-                order.status = OrderStatus::Expired;
-                // Remove from order book
-                if let Some(order_book) = self.order_books.get_mut(&order.symbol) {
-                    order_book.remove_order(&order_id);
-                }
-            }
+        // The 2100 print has left the window, so it no longer sets the open or the high
+        assert_eq!(window.stats_since(start + Duration::minutes(30)),
+                   Some((Decimal::new(1900, 0), Decimal::new(2000, 0), Decimal::new(1900, 0),
+                         Decimal::new(3, 0), Decimal::new(5800, 0), 2)));
+        assert_eq!(window.stats_since(start + Duration::hours(21)), None);
+
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+        dex.add_symbol("BTC/USDC".to_string());
+        dex.deposit("trader1", "ETH", Decimal::new(10, 0));
+        dex.deposit("trader2", "USDC", Decimal::new(100000, 0));
+
+        for (side, trader, quantity, price) in [
+            (OrderSide::Sell, "trader1", 1, 2000),
+            (OrderSide::Buy, "trader2", 1, 2000),
+            (OrderSide::Sell, "trader1", 3, 2100),
+            (OrderSide::Buy, "trader2", 1, 2100),
+            (OrderSide::Buy, "trader2", 2, 1950),
+        ] {
+            dex.place_order(OrderRequest::new(
+                trader.to_string(),
+                "ETH/USDC".to_string(),
+                side,
+                OrderType::Limit,
+                Decimal::new(quantity, 0),
+                Some(Decimal::new(price, 0)),
+                None,
+            )).unwrap();
         }
+
+        let ticker = dex.get_ticker("ETH/USDC").unwrap();
+        assert_eq!((ticker.open, ticker.high, ticker.low, ticker.last),
+                   (Decimal::new(2000, 0), Decimal::new(2100, 0), Decimal::new(2000, 0), Decimal::new(2100, 0)));
+        assert_eq!(ticker.change, Decimal::new(100, 0));
+        assert_eq!(ticker.change_percent, Decimal::new(500, 2));
+        assert_eq!(ticker.vwap, Decimal::new(2050, 0));
+        assert_eq!((ticker.volume, ticker.quote_volume, ticker.trade_count),
+                   (Decimal::new(2, 0), Decimal::new(4100, 0), 2));
+        assert_eq!((ticker.best_bid, ticker.best_bid_quantity), (Some(Decimal::new(1950, 0)), Decimal::new(2, 0)));
+        assert_eq!((ticker.best_ask, ticker.best_ask_quantity), (Some(Decimal::new(2100, 0)), Decimal::new(2, 0)));
+
+        let tickers = dex.get_tickers();
+        assert_eq!(tickers.iter().map(|ticker| ticker.symbol.as_str()).collect::<Vec<_>>(), vec!["BTC/USDC", "ETH/USDC"]);
+        assert_eq!((tickers[0].last, tickers[0].trade_count, tickers[0].best_bid), (Decimal::ZERO, 0, None));
+        assert!(dex.get_ticker("SOL/USDC").is_none());
     }
 
-    pub fn get_market_stats(&self) -> HashMap<String, String> {
-        let mut stats = HashMap::new();
+    #[test]
+    fn test_call_auction_uncrosses_at_equilibrium() {
+        let mut dex = DEXEngine::new();
+        dex.add_symbol("ETH/USDC".to_string());
+        dex.deposit("trader1", "USDC", Decimal::new(100000, 0));
+        dex.deposit("trader2", "ETH", Decimal::new(100, 0));
 
-        let total_symbols = self.order_books.len();
-        stats.insert("total_symbols".to_string(), total_symbols.to_string());
+        assert!(dex.set_trading_status("ETH/USDC", TradingStatus::PreOpen).is_err());
+        dex.set_trading_status("ETH/USDC", TradingStatus::Closed).unwrap();
+        dex.set_trading_status("ETH/USDC", TradingStatus::PreOpen).unwrap();
+        let indicative = dex.subscribe_indicative_uncross("ETH/USDC").unwrap();
 
-        let total_orders = self.orders.len();
-        stats.insert("total_orders".to_string(), total_orders.to_string());
+        let place = |dex: &mut DEXEngine, trader: &str, side: OrderSide, order_type: OrderType, quantity: i64, price: i64| {
+            dex.place_order(OrderRequest::new(
+                trader.to_string(),
+                "ETH/USDC".to_string(),
+                side,
+                order_type,
+                Decimal::new(quantity, 0),
+                Some(Decimal::new(price, 0)),
+                None,
+            ))
+        };
+        place(&mut dex, "trader1", OrderSide::Buy, OrderType::Limit, 10, 101).unwrap();
+        place(&mut dex, "trader1", OrderSide::Buy, OrderType::Limit, 5, 100).unwrap();
+        place(&mut dex, "trader2", OrderSide::Sell, OrderType::Limit, 8, 99).unwrap();
+        place(&mut dex, "trader2", OrderSide::Sell, OrderType::Limit, 6, 100).unwrap();
+        place(&mut dex, "trader2", OrderSide::Sell, OrderType::Limit, 4, 102).unwrap();
+        assert!(place(&mut dex, "trader1", OrderSide::Buy, OrderType::Market, 1, 101).is_err());
 
-        let total_trades = self.trades.len();
-        stats.insert("total_trades".to_string(), total_trades.to_string());
+        // Nothing trades during the call; the book crosses and the indicative price follows it
+        assert!(dex.get_recent_trades("ETH/USDC", 10).is_empty());
+        let published: Vec<IndicativeUncross> = indicative.try_iter().collect();
+        assert_eq!(published.len(), 2);
+        let latest = published.last().unwrap();
+        assert_eq!(latest.price, Some(Decimal::new(100, 0)));
+        assert_eq!(latest.matched_quantity, Decimal::new(14, 0));
+        assert_eq!((latest.imbalance_quantity, latest.imbalance_side.clone()), (Decimal::new(1, 0), Some(OrderSide::Buy)));
+        assert_eq!(dex.get_indicative_uncross("ETH/USDC").unwrap().price, latest.price);
 
-        let total_users = self.user_balances.len();
-        stats.insert("total_users".to_string(), total_users.to_string());
+        dex.set_trading_status("ETH/USDC", TradingStatus::Trading).unwrap();
+        let trades = dex.get_recent_trades("ETH/USDC", 10);
+        assert_eq!(trades.len(), 3);
+        assert!(trades.iter().all(|trade| trade.price == Decimal::new(100, 0)));
+        assert_eq!(trades.iter().map(|trade| trade.quantity).sum::<Decimal>(), Decimal::new(14, 0));
+        let order_book = dex.get_order_book("ETH/USDC").unwrap();
+        assert_eq!((order_book.get_best_bid(), order_book.get_best_ask()), (Some(Decimal::new(100, 0)), Some(Decimal::new(102, 0))));
+        assert!(dex.get_indicative_uncross("ETH/USDC").is_none());
 
-        stats
-    }
-}
+        // Ties on volume and imbalance go to the price nearest the reference
+        dex.set_trading_status("ETH/USDC", TradingStatus::ClosingAuction).unwrap();
+        place(&mut dex, "trader1", OrderSide::Buy, OrderType::Limit, 4, 103).unwrap();
+        let order_book = dex.get_order_book("ETH/USDC").unwrap();
+        assert_eq!(order_book.equilibrium(Some(Decimal::new(100, 0))).unwrap().0, Decimal::new(102, 0));
+        assert_eq!(order_book.equilibrium(Some(Decimal::new(104, 0))).unwrap().0, Decimal::new(103, 0));
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // A halt in the middle of the auction leaves the book crossed for when it comes back
+        dex.set_trading_status("ETH/USDC", TradingStatus::Halted).unwrap();
+        assert_eq!(dex.get_trades().len(), 3);
+        assert!(dex.get_order_book("ETH/USDC").unwrap().equilibrium(None).is_some());
+        dex.set_trading_status("ETH/USDC", TradingStatus::ClosingAuction).unwrap();
+        dex.set_trading_status("ETH/USDC", TradingStatus::Closed).unwrap();
+        assert_eq!(dex.get_recent_trades("ETH/USDC", 1)[0].price, Decimal::new(102, 0));
+        assert_eq!(dex.get_symbol_spec("ETH/USDC").unwrap().status, TradingStatus::Closed);
+    }
 
     #[test]
-    fn test_place_limit_order() {
+    fn test_call_auction_without_a_cross_trades_nothing() {
         let mut dex = DEXEngine::new();
         dex.add_symbol("ETH/USDC".to_string());
+        dex.deposit("trader1", "USDC", Decimal::new(100000, 0));
+        dex.deposit("trader2", "ETH", Decimal::new(100, 0));
+        dex.set_trading_status("ETH/USDC", TradingStatus::Closed).unwrap();
+        dex.set_trading_status("ETH/USDC", TradingStatus::PreOpen).unwrap();
 
-        dex.deposit("trader1", "USDC", Decimal::new(10000, 0));
+        for (trader, side, price) in [("trader1", OrderSide::Buy, 99), ("trader2", OrderSide::Sell, 101)] {
+            dex.place_order(OrderRequest::new(
+                trader.to_string(),
+                "ETH/USDC".to_string(),
+                side,
+                OrderType::Limit,
+                Decimal::new(5, 0),
+                Some(Decimal::new(price, 0)),
+                None,
+            )).unwrap();
+        }
 
-        let result = dex.place_order(
-            "trader1".to_string(),
-            "ETH/USDC".to_string(),
-            OrderSide::Buy,
-            OrderType::Limit,
-            Decimal::new(1, 0),
-            Some(Decimal::new(2000, 0)),
-            None,
-            TimeInForce::GTC,
-            None,
-        );
+        // No price, no matched volume and no imbalance side while the book is apart
+        let indicative = dex.get_indicative_uncross("ETH/USDC").unwrap();
+        assert_eq!(indicative.price, None);
+        assert_eq!(indicative.matched_quantity, Decimal::ZERO);
+        assert_eq!((indicative.imbalance_quantity, indicative.imbalance_side), (Decimal::ZERO, None));
 
-        assert!(result.is_ok());
+        // Opening and closing leave both orders resting and set no reference price
+        dex.set_trading_status("ETH/USDC", TradingStatus::Trading).unwrap();
+        dex.set_trading_status("ETH/USDC", TradingStatus::ClosingAuction).unwrap();
+        dex.set_trading_status("ETH/USDC", TradingStatus::Closed).unwrap();
+        assert!(dex.get_trades().is_empty());
+        assert_eq!(dex.get_reference_price("ETH/USDC"), None);
+        let order_book = dex.get_order_book("ETH/USDC").unwrap();
+        assert_eq!((order_book.get_best_bid(), order_book.get_best_ask()), (Some(Decimal::new(99, 0)), Some(Decimal::new(101, 0))));
     }
 
     #[test]
-    fn test_market_order_matching() {
+    fn test_price_bands_and_volatility_halt() {
         let mut dex = DEXEngine::new();
         dex.add_symbol("ETH/USDC".to_string());
+        dex.deposit("trader1", "USDC", Decimal::new(100000, 0));
+        dex.deposit("trader2", "ETH", Decimal::new(100, 0));
 
-        // Add sell order
-        dex.deposit("seller1", "ETH", Decimal::new(1, 0));
-        dex.place_order(
-            "seller1".to_string(),
-            "ETH/USDC".to_string(),
-            OrderSide::Sell,
-            OrderType::Limit,
-            Decimal::new(1, 0),
-            Some(Decimal::new(2000, 0)),
-            None,
-            TimeInForce::GTC,
-            None,
-        ).unwrap();
+        let start: DateTime<Utc> = "2024-03-01T10:00:00Z".parse().unwrap();
+        dex.set_clock(Some(start));
+        let mut bands = PriceBands::new(Some(Decimal::new(10, 0)), Some(Decimal::new(5, 0)));
+        bands.volatility_percent = Some(Decimal::new(3, 0));
+        bands.volatility_window_seconds = 60;
+        bands.halt_seconds = 30;
+        bands.auction_seconds = 10;
+        assert!(dex.set_price_bands("ETH/USDC", PriceBands::new(Some(Decimal::new(100, 0)), None)).is_err());
+        dex.set_price_bands("ETH/USDC", bands).unwrap();
+        dex.set_reference_price("ETH/USDC", Decimal::new(2000, 0)).unwrap();
 
-        // Add buy order
-        dex.deposit("buyer1", "USDC", Decimal::new(2000, 0));
-        dex.place_order(
-            "buyer1".to_string(),
-            "ETH/USDC".to_string(),
-            OrderSide::Buy,
-            OrderType::Market,
-            Decimal::new(1, 0),
-            None,
-            None,
-            TimeInForce::GTC,
-            None,
-        ).unwrap();
+        let place = |dex: &mut DEXEngine, trader: &str, side: OrderSide, order_type: OrderType, quantity: i64, price: Option<i64>| {
+            dex.place_order(OrderRequest::new(
+                trader.to_string(),
+                "ETH/USDC".to_string(),
+                side,
+                order_type,
+                Decimal::new(quantity, 0),
+                price.map(|price| Decimal::new(price, 0)),
+                None,
+            ))
+        };
+        let error = place(&mut dex, "trader2", OrderSide::Sell, OrderType::Limit, 1, Some(2300)).unwrap_err();
+        assert_eq!(error, "Price 2300 is outside the static band 1800 to 2200 for ETH/USDC");
 
-        // Check that trade occurred
-        assert_eq!(dex.trades.len(), 1);
-        assert_eq!(dex.get_user_balance("buyer1", "ETH"), Decimal::new(1, 0));
-        assert_eq!(dex.get_user_balance("seller1", "USDC"), Decimal::new(2000, 0));
+        // The sweep stops and halts the symbol at the print that moves more than 3% within the minute
+        for price in [2000, 2050, 2090] {
+            place(&mut dex, "trader2", OrderSide::Sell, OrderType::Limit, 1, Some(price)).unwrap();
+        }
+        let order = place(&mut dex, "trader1", OrderSide::Buy, OrderType::Market, 3, None).unwrap();
+        assert_eq!(order.filled_quantity, Decimal::new(2, 0));
+        assert_eq!(order.status, OrderStatus::Cancelled);
+        assert_eq!(dex.get_symbol_spec("ETH/USDC").unwrap().status, TradingStatus::Halted);
+        let halt = dex.get_active_halt("ETH/USDC").unwrap();
+        assert_eq!(halt.source, HaltSource::VolatilityInterruption);
+        assert_eq!(halt.resume_at, Some(start + Duration::seconds(30)));
+        assert!(place(&mut dex, "trader1", OrderSide::Buy, OrderType::Limit, 1, Some(2090)).is_err());
+
+        // The halt ends in a reopening auction that uncrosses once it has run its time
+        dex.set_clock(Some(start + Duration::seconds(31)));
+        dex.process_pending_orders();
+        assert_eq!(dex.get_symbol_spec("ETH/USDC").unwrap().status, TradingStatus::PreOpen);
+        place(&mut dex, "trader1", OrderSide::Buy, OrderType::Limit, 1, Some(2090)).unwrap();
+        assert_eq!(dex.get_recent_trades("ETH/USDC", 10).len(), 2);
+        dex.set_clock(Some(start + Duration::seconds(41)));
+        dex.process_pending_orders();
+        assert_eq!(dex.get_symbol_spec("ETH/USDC").unwrap().status, TradingStatus::Trading);
+        assert_eq!(dex.get_recent_trades("ETH/USDC", 1)[0].price, Decimal::new(2090, 0));
+        assert_eq!(dex.get_reference_price("ETH/USDC"), Some(Decimal::new(2090, 0)));
+        assert!(dex.get_active_halt("ETH/USDC").is_none());
+
+        // A limit order priced outside the dynamic band around the last trade is refused on entry
+        let error = place(&mut dex, "trader1", OrderSide::Buy, OrderType::Limit, 1, Some(2200)).unwrap_err();
+        assert_eq!(error, "Price 2200 is outside the dynamic band 1985.5 to 2194.5 for ETH/USDC");
+        assert_eq!(dex.get_locked_balance("trader1", "USDC"), Decimal::ZERO);
+
+        // Operators halt and resume by hand, with the reasons kept in the halt history
+        assert!(dex.resume_symbol("ETH/USDC", "Not halted").is_err());
+        dex.halt_symbol("ETH/USDC", "Pending announcement").unwrap();
+        assert!(dex.halt_symbol("ETH/USDC", "Again").is_err());
+        dex.resume_symbol("ETH/USDC", "Announcement published").unwrap();
+        assert_eq!(dex.get_symbol_spec("ETH/USDC").unwrap().status, TradingStatus::PreOpen);
+        dex.set_clock(Some(start + Duration::seconds(51)));
+        dex.process_pending_orders();
+        assert_eq!(dex.get_symbol_spec("ETH/USDC").unwrap().status, TradingStatus::Trading);
+        let halts = dex.get_trading_halts("ETH/USDC");
+        assert_eq!(halts.len(), 2);
+        assert_eq!((halts[1].source.clone(), halts[1].reason.as_str()), (HaltSource::Operator, "Pending announcement"));
+        assert_eq!(halts[1].resume_reason.as_deref(), Some("Announcement published"));
+        assert_eq!(halts[1].resumed_at, Some(start + Duration::seconds(51)));
     }
 
     #[test]
-    fn test_cancel_order() {
+    fn test_volatility_check_skips_self_trades_and_old_prints() {
         let mut dex = DEXEngine::new();
         dex.add_symbol("ETH/USDC".to_string());
+        dex.deposit("trader1", "USDC", Decimal::new(100000, 0));
+        dex.deposit("trader1", "ETH", Decimal::new(10, 0));
+        dex.deposit("trader2", "ETH", Decimal::new(10, 0));
 
-        dex.deposit("trader1", "USDC", Decimal::new(10000, 0));
+        let start: DateTime<Utc> = "2024-03-01T10:00:00Z".parse().unwrap();
+        dex.set_clock(Some(start));
+        let mut bands = PriceBands::new(None, None);
+        bands.volatility_percent = Some(Decimal::new(3, 0));
+        bands.volatility_window_seconds = 60;
+        bands.halt_seconds = 30;
+        dex.set_price_bands("ETH/USDC", bands).unwrap();
 
-        let order_id = dex.place_order(
-            "trader1".to_string(),
-            "ETH/USDC".to_string(),
-            OrderSide::Buy,
-            OrderType::Limit,
-            Decimal::new(1, 0),
-            Some(Decimal::new(2000, 0)),
-            None,
-            TimeInForce::GTC,
-            None,
-        ).unwrap();
+        let place = |dex: &mut DEXEngine, trader: &str, side: OrderSide, price: i64,
+                     self_trade_prevention: Option<SelfTradePrevention>| {
+            dex.place_order(OrderRequest {
+                instructions: ExecInstructions { self_trade_prevention, ..Default::default() },
+                ..OrderRequest::new(
+                    trader.to_string(),
+                    "ETH/USDC".to_string(),
+                    side,
+                    OrderType::Limit,
+                    Decimal::new(1, 0),
+                    Some(Decimal::new(price, 0)),
+                    None,
+                )
+            }).unwrap()
+        };
+        place(&mut dex, "trader2", OrderSide::Sell, 2000, None);
+        place(&mut dex, "trader1", OrderSide::Buy, 2000, None);
 
-        let result = dex.cancel_order(&order_id, "trader1");
-        assert!(result.is_ok());
+        // A self-trade that prevention cancels never prints, so it cannot trip the interruption
+        let resting_sell = place(&mut dex, "trader1", OrderSide::Sell, 2100, None);
+        let buy = place(&mut dex, "trader1", OrderSide::Buy, 2100, Some(SelfTradePrevention::CancelOldest));
+        assert_eq!(dex.get_order(&resting_sell.id).unwrap().status, OrderStatus::Cancelled);
+        assert_eq!(buy.status, OrderStatus::Pending);
+        assert_eq!(dex.get_symbol_spec("ETH/USDC").unwrap().status, TradingStatus::Trading);
 
-        let order = dex.get_order(&order_id).unwrap();
-        assert_eq!(order.status, OrderStatus::Cancelled);
+        // Prints older than the window no longer count towards the range
+        dex.set_clock(Some(start + Duration::seconds(61)));
+        place(&mut dex, "trader2", OrderSide::Sell, 2100, None);
+        assert_eq!(dex.get_recent_trades("ETH/USDC", 1)[0].price, Decimal::new(2100, 0));
+        assert_eq!(dex.get_symbol_spec("ETH/USDC").unwrap().status, TradingStatus::Trading);
+        assert!(dex.get_active_halt("ETH/USDC").is_none());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use rust_decimal::Decimal;
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+
+use crate::dex_engine::{
+    Balance, DEXEngine, DepthSnapshot, DepthUpdate, Order, OrderEvent, OrderGroup, OrderLeg, OrderRequest, OrderSide,
+    OrderType, SelfTradePrevention, SymbolSpec, Ticker, Trade, TrailingOffset,
+};
+use crate::journal::{CommandOutput, EngineCommand};
+use crate::ledger::Ledger;
+
+const COMMAND_BUFFER: usize = 1024;
+const ID_SEPARATOR: char = '-'; // Between a symbol's listing number and the engine's own id
+
+/// A call for a symbol's task to make on its engine; it sends its own reply.
+type Job = Box<dyn FnOnce(&mut DEXEngine) + Send>;
+
+/// Runs one symbol's engine: jobs are taken off the channel one at a time until the cluster goes away.
+async fn run_symbol(mut engine: DEXEngine, mut jobs: mpsc::Receiver<Job>) {
+    while let Some(job) = jobs.recv().await {
+        job(&mut engine);
+    }
+}
+
+#[derive(Default)]
+struct Listings {
+    specs: HashMap<String, SymbolSpec>, // With `id` as the listing number
+    symbols: HashMap<u32, String>,      // By listing number
+    jobs: HashMap<String, mpsc::Sender<Job>>,
+}
+
+/// Settings that apply across symbols, so every engine gets them, including engines listed later.
+#[derive(Default)]
+struct Defaults {
+    fee_account: Option<String>,
+    self_trade_prevention: HashMap<String, Option<SelfTradePrevention>>,
+}
+
+impl Defaults {
+    fn apply(&self, engine: &mut DEXEngine) {
+        if let Some(account) = &self.fee_account {
+            engine.set_fee_account(account);
+        }
+        for (trader, mode) in &self.self_trade_prevention {
+            engine.set_self_trade_prevention(trader, mode.clone());
+        }
+    }
+}
+
+/// `DEXEngine` split so symbols match in parallel: each symbol has an engine of its own on its own
+/// tokio task, fed through a command channel, and every engine keeps its balances in one shared
+/// `Ledger`. Orders reserve straight from the balance the other symbols see, and fills settle
+/// there as they happen, so nothing is copied between engines.
+///
+/// Ids of orders, groups and trades start with the symbol's listing number, as in `2-order_15`,
+/// and calls that only name an id find the symbol from it. `execute` and `with_symbol` reach the
+/// rest of a symbol's engine: trading status, halts, bands and its feeds. Must be used inside a
+/// tokio runtime.
+pub struct EngineCluster {
+    ledger: Ledger,
+    listings: RwLock<Listings>,
+    defaults: Mutex<Defaults>,
+}
+
+impl Default for EngineCluster {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EngineCluster {
+    pub fn new() -> Self {
+        Self {
+            ledger: Ledger::new(),
+            listings: RwLock::new(Listings::default()),
+            defaults: Mutex::new(Defaults::default()),
+        }
+    }
+
+    pub fn ledger(&self) -> Ledger {
+        self.ledger.share()
+    }
+
+    /// Lists a "BASE/QUOTE" symbol with the default spec.
+    pub async fn add_symbol(&self, symbol: String) -> Result<(), String> {
+        let mut engine = DEXEngine::with_ledger(self.ledger.share());
+        engine.add_symbol(symbol);
+        self.start_symbol(engine).await
+    }
+
+    pub async fn add_symbol_spec(&self, spec: SymbolSpec) -> Result<(), String> {
+        let mut engine = DEXEngine::with_ledger(self.ledger.share());
+        engine.add_symbol_spec(spec)?;
+        self.start_symbol(engine).await
+    }
+
+    async fn start_symbol(&self, mut engine: DEXEngine) -> Result<(), String> {
+        let mut listings = self.listings.write().await;
+        let mut spec = engine.get_symbol_specs().pop().unwrap();
+        if listings.specs.contains_key(&spec.symbol) {
+            return Err(format!("Symbol {} is already listed", spec.symbol));
+        }
+
+        spec.id = listings.specs.len() as u32 + 1;
+        engine.set_id_prefix(&format!("{}{}", spec.id, ID_SEPARATOR));
+        self.defaults.lock().await.apply(&mut engine);
+        let (sender, receiver) = mpsc::channel(COMMAND_BUFFER);
+        tokio::spawn(run_symbol(engine, receiver));
+        listings.symbols.insert(spec.id, spec.symbol.clone());
+        listings.jobs.insert(spec.symbol.clone(), sender);
+        listings.specs.insert(spec.symbol.clone(), spec);
+        Ok(())
+    }
+
+    pub async fn is_listed(&self, symbol: &str) -> bool {
+        self.listings.read().await.specs.contains_key(symbol)
+    }
+
+    pub async fn get_symbols(&self) -> Vec<String> {
+        let mut symbols: Vec<String> = self.listings.read().await.specs.keys().cloned().collect();
+        symbols.sort();
+        symbols
+    }
+
+    /// Specs in listing order, with listing numbers as their ids.
+    pub async fn get_symbol_specs(&self) -> Vec<SymbolSpec> {
+        let mut specs: Vec<SymbolSpec> = self.listings.read().await.specs.values().cloned().collect();
+        specs.sort_by_key(|spec| spec.id);
+        specs
+    }
+
+    /// The symbol an order, group or trade id belongs to.
+    pub async fn symbol_of(&self, id: &str) -> Option<String> {
+        let (number, _) = id.split_once(ID_SEPARATOR)?;
+        self.listings.read().await.symbols.get(&number.parse().ok()?).cloned()
+    }
+
+    /// Runs `call` on the symbol's engine, on the symbol's task, and hands back what it returns.
+    pub async fn with_symbol<T, F>(&self, symbol: &str, call: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut DEXEngine) -> T + Send + 'static,
+    {
+        let sender = self.listings.read().await.jobs.get(symbol)
+            .cloned()
+            .ok_or_else(|| "Symbol not supported".to_string())?;
+        let (reply, response) = oneshot::channel();
+        let job: Job = Box::new(move |engine| {
+            let _ = reply.send(call(engine));
+        });
+        sender.send(job).await.map_err(|_| format!("Matching for {} has stopped", symbol))?;
+        response.await.map_err(|_| format!("Matching for {} has stopped", symbol))
+    }
+
+    /// Runs `call` on every symbol's engine at once and collects what each returns, in symbol order.
+    async fn with_every_symbol<T, F>(&self, call: F) -> Vec<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut DEXEngine) -> T + Clone + Send + 'static,
+    {
+        let mut senders: Vec<(String, mpsc::Sender<Job>)> = self.listings.read().await.jobs.iter()
+            .map(|(symbol, sender)| (symbol.clone(), sender.clone()))
+            .collect();
+        senders.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut responses = Vec::new();
+        for (_, sender) in senders {
+            let (reply, response) = oneshot::channel();
+            let call = call.clone();
+            let job: Job = Box::new(move |engine| {
+                let _ = reply.send(call(engine));
+            });
+            if sender.send(job).await.is_ok() {
+                responses.push(response);
+            }
+        }
+        let mut results = Vec::new();
+        for response in responses {
+            results.extend(response.await.ok());
+        }
+        results
+    }
+
+    /// Runs a journal command on the symbol's engine. Symbols are listed through the cluster instead.
+    pub async fn execute(&self, symbol: &str, command: EngineCommand) -> Result<CommandOutput, String> {
+        if matches!(command, EngineCommand::AddSymbol { .. } | EngineCommand::AddSymbolSpec { .. }) {
+            return Err("Symbols are listed through the cluster".to_string());
+        }
+        self.with_symbol(symbol, move |engine| command.apply(engine)).await?
+    }
+
+    /// Runs `call` on the engine of the symbol `id` belongs to, or fails with `not_found`.
+    async fn with_owner<T, F>(&self, id: &str, not_found: &str, call: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut DEXEngine) -> Result<T, String> + Send + 'static,
+    {
+        let symbol = self.symbol_of(id).await.ok_or_else(|| not_found.to_string())?;
+        self.with_symbol(&symbol, call).await?
+    }
+
+    pub async fn deposit(&self, user: &str, currency: &str, amount: Decimal) {
+        self.ledger.deposit(user, currency, amount);
+    }
+
+    pub async fn withdraw(&self, user: &str, currency: &str, amount: Decimal) -> Result<(), String> {
+        self.ledger.withdraw(user, currency, amount)
+    }
+
+    pub async fn get_balance(&self, user: &str, currency: &str) -> Balance {
+        self.ledger.get_balance(user, currency)
+    }
+
+    pub async fn get_user_balances(&self, user: &str) -> HashMap<String, Balance> {
+        self.ledger.get_user_balances(user)
+    }
+
+    /// Account credited with the fees charged on every symbol.
+    pub async fn set_fee_account(&self, account: &str) {
+        self.defaults.lock().await.fee_account = Some(account.to_string());
+        let account = account.to_string();
+        self.with_every_symbol(move |engine| engine.set_fee_account(&account)).await;
+    }
+
+    /// Sets the trader's default self-trade prevention on every symbol.
+    pub async fn set_self_trade_prevention(&self, trader: &str, mode: Option<SelfTradePrevention>) {
+        self.defaults.lock().await.self_trade_prevention.insert(trader.to_string(), mode.clone());
+        let trader = trader.to_string();
+        self.with_every_symbol(move |engine| engine.set_self_trade_prevention(&trader, mode)).await;
+    }
+
+    pub async fn place_order(&self, request: OrderRequest) -> Result<Order, String> {
+        let symbol = request.symbol.clone();
+        self.with_symbol(&symbol, move |engine| engine.place_order(request)).await?
+    }
+
+    pub async fn place_trailing_stop_order(&self, request: OrderRequest, offset: TrailingOffset,
+                                           limit_offset: Option<Decimal>) -> Result<Order, String> {
+        let symbol = request.symbol.clone();
+        self.with_symbol(&symbol, move |engine| engine.place_trailing_stop_order(request, offset, limit_offset))
+            .await?
+    }
+
+    pub async fn place_oco_order(&self, request: OrderRequest, second: OrderLeg) -> Result<OrderGroup, String> {
+        let symbol = request.symbol.clone();
+        self.with_symbol(&symbol, move |engine| engine.place_oco_order(request, second)).await?
+    }
+
+    pub async fn place_bracket_order(&self, entry: OrderRequest, take_profit_price: Decimal,
+                                     stop_loss_price: Decimal) -> Result<OrderGroup, String> {
+        let symbol = entry.symbol.clone();
+        self.with_symbol(&symbol, move |engine| engine.place_bracket_order(entry, take_profit_price, stop_loss_price))
+            .await?
+    }
+
+    pub async fn amend_order(&self, order_id: &str, trader: &str, new_quantity: Option<Decimal>,
+                             new_price: Option<Decimal>) -> Result<Order, String> {
+        let (owned, trader) = (order_id.to_string(), trader.to_string());
+        self.with_owner(order_id, "Order not found", move |engine| {
+            engine.amend_order(&owned, &trader, new_quantity, new_price)
+        }).await
+    }
+
+    pub async fn cancel_order(&self, order_id: &str, trader: &str) -> Result<(), String> {
+        let (owned, trader) = (order_id.to_string(), trader.to_string());
+        self.with_owner(order_id, "Order not found", move |engine| engine.cancel_order(&owned, &trader)).await
+    }
+
+    pub async fn cancel_order_group(&self, group_id: &str, trader: &str) -> Result<OrderGroup, String> {
+        let (owned, trader) = (group_id.to_string(), trader.to_string());
+        self.with_owner(group_id, "Order group not found", move |engine| engine.cancel_order_group(&owned, &trader))
+            .await
+    }
+
+    /// Expires due orders on every symbol, all symbols at once.
+    pub async fn process_pending_orders(&self) {
+        self.with_every_symbol(|engine| engine.process_pending_orders()).await;
+    }
+
+    pub async fn get_order(&self, order_id: &str) -> Option<Order> {
+        let owned = order_id.to_string();
+        self.with_owner(order_id, "Order not found", move |engine| Ok(engine.get_order(&owned)))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub async fn get_user_orders(&self, trader: &str) -> Vec<Order> {
+        let trader = trader.to_string();
+        self.with_every_symbol(move |engine| engine.get_user_orders(&trader)).await.concat()
+    }
+
+    pub async fn get_depth_snapshot(&self, symbol: &str, depth: usize) -> Option<DepthSnapshot> {
+        let owned = symbol.to_string();
+        self.with_symbol(symbol, move |engine| engine.get_depth_snapshot(&owned, depth))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub async fn get_recent_trades(&self, symbol: &str, limit: usize) -> Vec<Trade> {
+        let owned = symbol.to_string();
+        self.with_symbol(symbol, move |engine| engine.get_recent_trades(&owned, limit))
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn get_ticker(&self, symbol: &str) -> Option<Ticker> {
+        let owned = symbol.to_string();
+        self.with_symbol(symbol, move |engine| engine.get_ticker(&owned)).await.ok().flatten()
+    }
+
+    /// Tickers for every listed symbol, sorted by symbol.
+    pub async fn get_tickers(&self) -> Vec<Ticker> {
+        self.with_every_symbol(|engine| engine.get_tickers()).await.concat()
+    }
+
+    pub async fn subscribe_depth(&self, symbol: &str) -> Result<Receiver<DepthUpdate>, String> {
+        let owned = symbol.to_string();
+        self.with_symbol(symbol, move |engine| engine.subscribe_depth(&owned)).await?
+    }
+
+    pub async fn subscribe_order_events(&self, symbol: &str) -> Result<Receiver<OrderEvent>, String> {
+        let owned = symbol.to_string();
+        self.with_symbol(symbol, move |engine| engine.subscribe_order_events(&owned)).await?
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchmarkResult {
+    pub symbols: usize,
+    pub orders: usize,
+    pub trades: usize,
+    pub elapsed: Duration,
+    pub orders_per_second: f64,
+}
+
+/// Sends `orders_per_symbol` orders to each of `symbols` new symbols at once, one client per
+/// symbol, alternating resting sells with buys that take them. Throughput grows with the symbol
+/// count until every runtime worker is busy.
+pub async fn benchmark(symbols: usize, orders_per_symbol: usize) -> BenchmarkResult {
+    let cluster = Arc::new(EngineCluster::new());
+    for index in 0..symbols {
+        cluster.add_symbol(format!("SYM{}/USDC", index)).await.unwrap();
+        cluster.deposit(&format!("maker{}", index), &format!("SYM{}", index), Decimal::from(orders_per_symbol)).await;
+        cluster.deposit(&format!("taker{}", index), "USDC", Decimal::from(orders_per_symbol * 110)).await;
+    }
+
+    let start = Instant::now();
+    let clients: Vec<_> = (0..symbols).map(|index| {
+        let cluster = cluster.clone();
+        tokio::spawn(async move {
+            let symbol = format!("SYM{}/USDC", index);
+            let (maker, taker) = (format!("maker{}", index), format!("taker{}", index));
+            for order in 0..orders_per_symbol / 2 {
+                let price = Decimal::from(100 + order % 10);
+                cluster.place_order(OrderRequest::new(maker.clone(), symbol.clone(), OrderSide::Sell,
+                                                      OrderType::Limit, Decimal::ONE, Some(price), None)).await.unwrap();
+                cluster.place_order(OrderRequest::new(taker.clone(), symbol.clone(), OrderSide::Buy, OrderType::Limit,
+                                                      Decimal::ONE, Some(Decimal::from(110)), None)).await.unwrap();
+            }
+        })
+    }).collect();
+    for client in clients {
+        client.await.unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    let mut trades = 0;
+    for index in 0..symbols {
+        trades += cluster.with_symbol(&format!("SYM{}/USDC", index), |engine| engine.get_trades().len()).await.unwrap();
+    }
+    let orders = symbols * (orders_per_symbol / 2) * 2;
+    BenchmarkResult {
+        symbols,
+        orders,
+        trades,
+        elapsed,
+        orders_per_second: orders as f64 / elapsed.as_secs_f64(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex_engine::OrderStatus;
+
+    fn limit(trader: &str, symbol: &str, side: OrderSide, quantity: i64, price: i64) -> OrderRequest {
+        OrderRequest::new(trader.to_string(), symbol.to_string(), side, OrderType::Limit, Decimal::new(quantity, 0),
+                          Some(Decimal::new(price, 0)), None)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_cluster_matches_symbols_against_one_ledger() {
+        let cluster = EngineCluster::new();
+        cluster.add_symbol("ETH/USDC".to_string()).await.unwrap();
+        cluster.add_symbol("BTC/USDC".to_string()).await.unwrap();
+        assert!(cluster.add_symbol("ETH/USDC".to_string()).await.is_err());
+        cluster.deposit("trader1", "USDC", Decimal::new(10000, 0)).await;
+        cluster.deposit("trader2", "ETH", Decimal::new(5, 0)).await;
+
+        // Both symbols reserve from the same balance at once, and a third order finds it spent
+        let (eth_buy, btc_buy) = tokio::join!(
+            cluster.place_order(limit("trader1", "ETH/USDC", OrderSide::Buy, 2, 2000)),
+            cluster.place_order(OrderRequest::new("trader1".to_string(), "BTC/USDC".to_string(), OrderSide::Buy,
+                                                  OrderType::Limit, Decimal::new(1, 1), Some(Decimal::new(50000, 0)),
+                                                  None)),
+        );
+        let (eth_buy, btc_buy) = (eth_buy.unwrap(), btc_buy.unwrap());
+        assert_eq!((eth_buy.id.as_str(), btc_buy.id.as_str()), ("1-order_1", "2-order_1"));
+        let error = cluster.place_order(limit("trader1", "ETH/USDC", OrderSide::Buy, 1, 2000)).await.unwrap_err();
+        assert_eq!(error, "Insufficient balance");
+        assert_eq!(cluster.get_balance("trader1", "USDC").await,
+                   Balance { available: Decimal::new(1000, 0), locked: Decimal::new(9000, 0) });
+
+        // Fills settle straight into the ledger; the unfilled seller's base stays locked with its order
+        let sell = cluster.place_order(limit("trader2", "ETH/USDC", OrderSide::Sell, 3, 1990)).await.unwrap();
+        assert_eq!(sell.filled_quantity, Decimal::new(2, 0));
+        assert_eq!(cluster.get_recent_trades("ETH/USDC", 10).await[0].price, Decimal::new(2000, 0));
+        assert_eq!(cluster.get_balance("trader1", "ETH").await.available, Decimal::new(2, 0));
+        assert_eq!(cluster.get_balance("trader2", "USDC").await.available, Decimal::new(4000, 0));
+        assert_eq!(cluster.get_balance("trader2", "ETH").await,
+                   Balance { available: Decimal::new(2, 0), locked: Decimal::new(1, 0) });
+
+        // Amends lock more from the ledger and cancels hand the hold back, found by id alone
+        cluster.amend_order(&btc_buy.id, "trader1", None, Some(Decimal::new(55000, 0))).await.unwrap();
+        assert_eq!(cluster.get_balance("trader1", "USDC").await.available, Decimal::new(500, 0));
+        cluster.cancel_order(&btc_buy.id, "trader1").await.unwrap();
+        assert_eq!(cluster.get_balance("trader1", "USDC").await,
+                   Balance { available: Decimal::new(6000, 0), locked: Decimal::ZERO });
+        assert_eq!(cluster.get_order(&btc_buy.id).await.unwrap().status, OrderStatus::Cancelled);
+        assert!(cluster.get_depth_snapshot("BTC/USDC", 10).await.unwrap().bids.is_empty());
+        assert_eq!(cluster.cancel_order("9-order_1", "trader1").await.unwrap_err(), "Order not found");
+        assert_eq!(cluster.get_user_orders("trader1").await.len(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_cluster_runs_the_full_order_surface() {
+        let cluster = EngineCluster::new();
+        cluster.add_symbol("ETH/USDC".to_string()).await.unwrap();
+        cluster.add_symbol("BTC/USDC".to_string()).await.unwrap();
+        cluster.deposit("maker", "ETH", Decimal::new(10, 0)).await;
+        cluster.deposit("maker", "USDC", Decimal::new(10000, 0)).await;
+        cluster.deposit("bob", "USDC", Decimal::new(10000, 0)).await;
+        cluster.deposit("carol", "USDC", Decimal::new(10000, 0)).await;
+        let depth = cluster.subscribe_depth("ETH/USDC").await.unwrap();
+
+        // A stop buy takes its hold from the ledger when it triggers and fills against the book
+        cluster.place_order(limit("maker", "ETH/USDC", OrderSide::Sell, 1, 2100)).await.unwrap();
+        cluster.place_order(limit("maker", "ETH/USDC", OrderSide::Sell, 2, 2110)).await.unwrap();
+        let stop = cluster.place_order(OrderRequest::new("bob".to_string(), "ETH/USDC".to_string(), OrderSide::Buy,
+                                                         OrderType::Stop, Decimal::ONE, None,
+                                                         Some(Decimal::new(2100, 0)))).await.unwrap();
+        assert_eq!(cluster.get_balance("bob", "USDC").await.locked, Decimal::ZERO);
+        cluster.place_order(limit("carol", "ETH/USDC", OrderSide::Buy, 1, 2100)).await.unwrap();
+        assert_eq!(cluster.get_order(&stop.id).await.unwrap().status, OrderStatus::Filled);
+        assert_eq!(cluster.get_balance("bob", "ETH").await.available, Decimal::ONE);
+        assert_eq!(cluster.get_balance("bob", "USDC").await,
+                   Balance { available: Decimal::new(7890, 0), locked: Decimal::ZERO });
+        assert_eq!(depth.try_iter().count(), 4);
+
+        // Grouped orders, cancelled as a group by id
+        let oco = cluster.place_oco_order(limit("maker", "ETH/USDC", OrderSide::Sell, 1, 2500), OrderLeg {
+            order_type: OrderType::Stop,
+            price: None,
+            stop_price: Some(Decimal::new(1900, 0)),
+        }).await.unwrap();
+        assert_eq!(cluster.get_balance("maker", "ETH").await.locked, Decimal::new(2, 0));
+        cluster.cancel_order_group(&oco.id, "maker").await.unwrap();
+        assert_eq!(cluster.get_balance("maker", "ETH").await.locked, Decimal::ONE);
+
+        // Self-trade prevention is a trader setting, so it reaches symbols listed after it too
+        cluster.set_self_trade_prevention("maker", Some(SelfTradePrevention::CancelNewest)).await;
+        cluster.add_symbol("SOL/USDC".to_string()).await.unwrap();
+        cluster.deposit("maker", "SOL", Decimal::new(10, 0)).await;
+        cluster.place_order(limit("maker", "SOL/USDC", OrderSide::Sell, 1, 100)).await.unwrap();
+        let own = cluster.place_order(limit("maker", "SOL/USDC", OrderSide::Buy, 1, 100)).await.unwrap();
+        assert_eq!(own.status, OrderStatus::Cancelled);
+        assert_eq!(cluster.get_symbol_specs().await.iter().map(|spec| spec.id).collect::<Vec<u32>>(), vec![1, 2, 3]);
+
+        // Halts and the rest of the engine go through `execute` and `with_symbol`
+        cluster.execute("BTC/USDC", EngineCommand::HaltSymbol {
+            symbol: "BTC/USDC".to_string(),
+            reason: "maintenance".to_string(),
+        }).await.unwrap();
+        assert!(cluster.with_symbol("BTC/USDC", |engine| engine.get_active_halt("BTC/USDC")).await.unwrap().is_some());
+        assert!(cluster.with_symbol("ETH/USDC", |engine| engine.get_active_halt("ETH/USDC")).await.unwrap().is_none());
+        assert!(cluster.execute("BTC/USDC", EngineCommand::AddSymbol { symbol: "XRP/USDC".to_string() }).await.is_err());
+        assert_eq!(cluster.get_tickers().await.len(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_benchmark_fills_every_order() {
+        let result = benchmark(4, 200).await;
+        assert_eq!((result.orders, result.trades), (800, 400));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark; run with --ignored"]
+    async fn bench_throughput_scales_with_symbol_count() {
+        let workers = std::thread::available_parallelism().map_or(1, |workers| workers.get());
+        if workers < 2 {
+            return; // Nothing to scale across
+        }
+        let single = benchmark(1, 20_000).await;
+        let several = benchmark(workers.min(8), 20_000).await;
+        assert!(several.orders_per_second > single.orders_per_second * 1.5,
+                "{} symbols: {:.0} orders/s against {:.0} for one", several.symbols, several.orders_per_second,
+                single.orders_per_second);
+    }
+}
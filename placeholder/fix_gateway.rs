@@ -0,0 +1,1396 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::Duration;
+use rust_decimal::Decimal;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Instant;
+
+use crate::dex_engine::{
+    DEXEngine, Fill, Order, OrderEvent, OrderRequest, OrderSide, OrderStatus, OrderType, TimeInForce, Trade,
+};
+use crate::engine_cluster::EngineCluster;
+
+pub const BEGIN_STRING: &str = "FIX.4.4";
+const SOH: char = '\x01';
+const TIME_FORMAT: &str = "%Y%m%d-%H:%M:%S%.3f";
+const LOGON_TIMEOUT: Duration = Duration::from_secs(10);
+const TIMER_INTERVAL: Duration = Duration::from_millis(250);
+const CLIENT_RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tag numbers the gateway reads or writes.
+pub mod tags {
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const TRANSACT_TIME: u32 = 60;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const STOP_PX: u32 = 99;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const EXPIRE_TIME: u32 = 126;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+}
+
+pub mod msg_types {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+}
+
+use msg_types::*;
+
+/// Session-level messages, which are gap-filled rather than resent.
+fn is_admin(msg_type: &str) -> bool {
+    [HEARTBEAT, TEST_REQUEST, RESEND_REQUEST, REJECT, SEQUENCE_RESET, LOGOUT, LOGON].contains(&msg_type)
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format(TIME_FORMAT).to_string()
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    NaiveDateTime::parse_from_str(value, "%Y%m%d-%H:%M:%S%.f")
+        .map(|time| time.and_utc())
+        .map_err(|_| format!("Invalid UTC timestamp: {}", value))
+}
+
+/// A FIX message as its tag=value fields in order, starting with MsgType. BeginString,
+/// BodyLength and CheckSum are left out; `encode` adds them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixMessage {
+    fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        Self {
+            fields: vec![(tags::MSG_TYPE, msg_type.to_string())],
+        }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.set(tag, value);
+        self
+    }
+
+    /// Replaces the field's value, or appends the field.
+    pub fn set(&mut self, tag: u32, value: impl ToString) {
+        let value = value.to_string();
+        match self.fields.iter_mut().find(|(field, _)| *field == tag) {
+            Some(field) => field.1 = value,
+            None => self.fields.push((tag, value)),
+        }
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|(field, _)| *field == tag).map(|(_, value)| value.as_str())
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tags::MSG_TYPE).unwrap_or_default()
+    }
+
+    pub fn seq_num(&self) -> Option<u64> {
+        self.get(tags::MSG_SEQ_NUM)?.parse().ok()
+    }
+
+    fn require(&self, tag: u32) -> Result<&str, String> {
+        self.get(tag).ok_or_else(|| format!("Required tag {} missing", tag))
+    }
+
+    fn parse_field<T: FromStr>(&self, tag: u32) -> Result<Option<T>, String> {
+        self.get(tag)
+            .map(|value| value.parse().map_err(|_| format!("Invalid value for tag {}: {}", tag, value)))
+            .transpose()
+    }
+
+    /// Copy of the message behind a fresh standard header. PossDupFlag and OrigSendingTime are
+    /// header fields too, so they move up with it.
+    fn with_header(&self, sender_comp_id: &str, target_comp_id: &str, seq: u64, sending_time: DateTime<Utc>) -> Self {
+        const HEADER: [u32; 7] = [
+            tags::MSG_TYPE, tags::SENDER_COMP_ID, tags::TARGET_COMP_ID, tags::MSG_SEQ_NUM, tags::SENDING_TIME,
+            tags::POSS_DUP_FLAG, tags::ORIG_SENDING_TIME,
+        ];
+        let mut message = FixMessage::new(self.msg_type())
+            .with(tags::SENDER_COMP_ID, sender_comp_id)
+            .with(tags::TARGET_COMP_ID, target_comp_id)
+            .with(tags::MSG_SEQ_NUM, seq)
+            .with(tags::SENDING_TIME, format_time(sending_time));
+        for tag in [tags::POSS_DUP_FLAG, tags::ORIG_SENDING_TIME] {
+            if let Some(value) = self.get(tag) {
+                message.set(tag, value);
+            }
+        }
+        message.fields.extend(self.fields.iter().filter(|(tag, _)| !HEADER.contains(tag)).cloned());
+        message
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let body: String = self.fields.iter().map(|(tag, value)| format!("{}={}{}", tag, value, SOH)).collect();
+        let mut message = format!("8={}{}9={}{}{}", BEGIN_STRING, SOH, body.len(), SOH, body);
+        let checksum = message.bytes().map(u32::from).sum::<u32>() % 256;
+        message.push_str(&format!("10={:03}{}", checksum, SOH));
+        message.into_bytes()
+    }
+
+    /// Reads the first message in `buffer` and how many bytes it took, or None while the message
+    /// is still arriving. Errors mean the stream cannot be trusted any more.
+    pub fn decode(buffer: &[u8]) -> Result<Option<(FixMessage, usize)>, String> {
+        let prefix = format!("8={}{}9=", BEGIN_STRING, SOH);
+        if buffer.len() < prefix.len() {
+            return match prefix.as_bytes().starts_with(buffer) {
+                true => Ok(None),
+                false => Err(format!("Message does not start with BeginString {}", BEGIN_STRING)),
+            };
+        }
+        if !buffer.starts_with(prefix.as_bytes()) {
+            return Err(format!("Message does not start with BeginString {}", BEGIN_STRING));
+        }
+
+        let length_end = match buffer[prefix.len()..].iter().position(|byte| *byte == SOH as u8) {
+            Some(position) => prefix.len() + position,
+            None if buffer.len() - prefix.len() > 10 => return Err("BodyLength is not terminated".to_string()),
+            None => return Ok(None),
+        };
+        let body_length: usize = std::str::from_utf8(&buffer[prefix.len()..length_end]).ok()
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| "Invalid BodyLength".to_string())?;
+        let body_start = length_end + 1;
+        let checksum_start = body_start + body_length;
+        let end = checksum_start + 7; // "10=nnn" and its delimiter
+        if buffer.len() < end {
+            return Ok(None);
+        }
+
+        let trailer = &buffer[checksum_start..end];
+        if !trailer.starts_with(b"10=") || trailer[6] != SOH as u8 {
+            return Err("BodyLength does not match the message".to_string());
+        }
+        let checksum: u32 = std::str::from_utf8(&trailer[3..6]).ok()
+            .and_then(|checksum| checksum.parse().ok())
+            .ok_or_else(|| "Invalid CheckSum".to_string())?;
+        let expected = buffer[..checksum_start].iter().map(|byte| u32::from(*byte)).sum::<u32>() % 256;
+        if checksum != expected {
+            return Err(format!("CheckSum {} does not match {}", checksum, expected));
+        }
+
+        let body = std::str::from_utf8(&buffer[body_start..checksum_start])
+            .map_err(|_| "Message is not valid UTF-8".to_string())?;
+        let fields = body.split(SOH)
+            .filter(|field| !field.is_empty())
+            .map(|field| {
+                let (tag, value) = field.split_once('=').ok_or_else(|| format!("Malformed field: {}", field))?;
+                let tag = tag.parse().map_err(|_| format!("Malformed tag: {}", tag))?;
+                Ok((tag, value.to_string()))
+            })
+            .collect::<Result<Vec<(u32, String)>, String>>()?;
+        if fields.first().map(|(tag, _)| *tag) != Some(tags::MSG_TYPE) {
+            return Err("MsgType must be the first body field".to_string());
+        }
+        Ok(Some((FixMessage { fields }, end)))
+    }
+}
+
+/// Sequence state of one configured counterparty. It outlives connections, so a session that
+/// reconnects carries on from the same numbers unless its Logon asks for a reset.
+struct Session {
+    next_incoming: u64,
+    next_outgoing: u64,
+    sent: BTreeMap<u64, FixMessage>, // Application messages as sent, for resend requests
+    connection: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    last_sent: Instant,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            next_incoming: 1,
+            next_outgoing: 1,
+            sent: BTreeMap::new(),
+            connection: None,
+            last_sent: Instant::now(),
+        }
+    }
+
+    fn write(&mut self, message: &FixMessage) {
+        if let Some(connection) = &self.connection {
+            if connection.send(message.encode()).is_ok() {
+                self.last_sent = Instant::now();
+            }
+        }
+    }
+}
+
+struct ClientOrder {
+    session: String,
+    cl_ord_id: String, // Latest ClOrdID; cancels and replaces move it on
+    triggered: bool,   // A stop whose trigger has been reported
+    closed: bool,      // Cancelled or expired has been reported
+}
+
+/// What changed on one symbol between two drains: its new trades, and the orders that had events,
+/// as they stood at the drain.
+#[derive(Default)]
+struct Changes {
+    trades: Vec<Trade>,
+    orders: Vec<Order>,
+}
+
+/// The gateway's place in one symbol's trades and order events. It is drained on the symbol's task
+/// around every call the gateway makes, so fills and closes reach the sessions whoever caused them.
+#[derive(Default)]
+struct Feed {
+    order_events: Option<Receiver<OrderEvent>>,
+    trades_seen: Option<usize>, // None until the first drain; trades before it are history, not news
+}
+
+impl Feed {
+    fn drain(&mut self, engine: &mut DEXEngine, symbol: &str) -> Changes {
+        if self.order_events.is_none() {
+            self.order_events = engine.subscribe_order_events(symbol).ok();
+        }
+        let trades = engine.get_trades();
+        let new_trades = trades[self.trades_seen.unwrap_or(trades.len())..].to_vec();
+        self.trades_seen = Some(trades.len());
+
+        let mut order_ids: Vec<String> = Vec::new();
+        for event in self.order_events.iter().flat_map(Receiver::try_iter) {
+            if !order_ids.contains(&event.order_id) {
+                order_ids.push(event.order_id);
+            }
+        }
+        Changes {
+            trades: new_trades,
+            orders: order_ids.iter().filter_map(|order_id| engine.get_order(order_id)).collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct GatewayState {
+    sessions: HashMap<String, Session>,
+    orders: HashMap<String, ClientOrder>,          // By engine order id
+    cl_ord_ids: HashMap<(String, String), String>, // Session and ClOrdID to engine order id
+    feeds: HashMap<String, Arc<std::sync::Mutex<Feed>>>,
+    exec_counter: u64,
+}
+
+impl GatewayState {
+    /// Stamps the session's next sequence number on the message and writes it out if the session
+    /// is connected. Application messages are kept either way, so a session that was away gets
+    /// them with a resend request.
+    fn send(&mut self, comp_id: &str, session_id: &str, message: FixMessage) {
+        let session = match self.sessions.get_mut(session_id) {
+            Some(session) => session,
+            None => return,
+        };
+        let seq = session.next_outgoing;
+        session.next_outgoing += 1;
+        let message = message.with_header(comp_id, session_id, seq, Utc::now());
+        if !is_admin(message.msg_type()) {
+            session.sent.insert(seq, message.clone());
+        }
+        session.write(&message);
+    }
+
+    fn next_exec_id(&mut self) -> String {
+        self.exec_counter += 1;
+        format!("exec_{}", self.exec_counter)
+    }
+
+    fn track(&mut self, order_id: &str, session_id: &str, cl_ord_id: &str) {
+        self.cl_ord_ids.insert((session_id.to_string(), cl_ord_id.to_string()), order_id.to_string());
+        let order = self.orders.entry(order_id.to_string()).or_insert_with(|| ClientOrder {
+            session: session_id.to_string(),
+            cl_ord_id: String::new(),
+            triggered: false,
+            closed: false,
+        });
+        order.cl_ord_id = cl_ord_id.to_string();
+    }
+
+    /// Marks the order closed and returns its session and ClOrdID, unless it is not a gateway
+    /// order or its close has been reported already.
+    fn close(&mut self, order_id: &str) -> Option<(String, String)> {
+        match self.orders.get_mut(order_id) {
+            Some(order) if !order.closed => {
+                order.closed = true;
+                Some((order.session.clone(), order.cl_ord_id.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    fn find_order(&self, session_id: &str, cl_ord_id: &str) -> Option<String> {
+        self.cl_ord_ids.get(&(session_id.to_string(), cl_ord_id.to_string())).cloned()
+    }
+}
+
+/// What one TCP connection knows about itself; the session it logs on to holds the rest.
+struct Connection {
+    session: Option<String>, // Set once logged on
+    heartbeat: Duration,
+    opened: Instant,
+    last_received: Instant,
+    test_request_sent: bool,
+    awaiting_resend: bool, // A resend request is out for a gap in incoming sequence numbers
+    sender: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+struct NewOrder {
+    symbol: String,
+    side: OrderSide,
+    order_type: OrderType,
+    quantity: Decimal,
+    price: Option<Decimal>,
+    stop_price: Option<Decimal>,
+    time_in_force: TimeInForce,
+    expire_at: Option<DateTime<Utc>>,
+}
+
+impl NewOrder {
+    fn parse(message: &FixMessage) -> Result<Self, String> {
+        let side = match message.require(tags::SIDE)? {
+            "1" => OrderSide::Buy,
+            "2" => OrderSide::Sell,
+            other => return Err(format!("Unsupported Side {}", other)),
+        };
+        let order_type = match message.require(tags::ORD_TYPE)? {
+            "1" => OrderType::Market,
+            "2" => OrderType::Limit,
+            "3" => OrderType::Stop,
+            "4" => OrderType::StopLimit,
+            other => return Err(format!("Unsupported OrdType {}", other)),
+        };
+        let time_in_force = match message.get(tags::TIME_IN_FORCE).unwrap_or("1") {
+            "1" => TimeInForce::GTC,
+            "3" => TimeInForce::IOC,
+            "4" => TimeInForce::FOK,
+            "6" => TimeInForce::GTD,
+            other => return Err(format!("Unsupported TimeInForce {}", other)),
+        };
+
+        Ok(Self {
+            symbol: message.require(tags::SYMBOL)?.to_string(),
+            side,
+            order_type,
+            quantity: message.parse_field(tags::ORDER_QTY)?
+                .ok_or_else(|| format!("Required tag {} missing", tags::ORDER_QTY))?,
+            price: message.parse_field(tags::PRICE)?,
+            stop_price: message.parse_field(tags::STOP_PX)?,
+            time_in_force,
+            expire_at: message.get(tags::EXPIRE_TIME).map(parse_time).transpose()?,
+        })
+    }
+}
+
+fn ord_status(order: &Order) -> &'static str {
+    match order.status {
+        OrderStatus::Inactive | OrderStatus::Pending | OrderStatus::Triggered => "0",
+        OrderStatus::Partial => "1",
+        OrderStatus::Filled => "2",
+        OrderStatus::Cancelled => "4",
+        OrderStatus::Expired => "C",
+    }
+}
+
+/// ExecutionReport for the order as of `fills`, the fills it had when the event happened.
+fn execution_report(order: &Order, cl_ord_id: &str, exec_id: String, exec_type: &str, ord_status: &str,
+                    fills: &[Fill]) -> FixMessage {
+    let cum_qty: Decimal = fills.iter().map(|fill| fill.quantity).sum();
+    let avg_px = match cum_qty > Decimal::ZERO {
+        true => fills.iter().map(|fill| fill.price * fill.quantity).sum::<Decimal>() / cum_qty,
+        false => Decimal::ZERO,
+    };
+    let leaves_qty = match ord_status {
+        "2" | "4" | "8" | "C" => Decimal::ZERO,
+        _ => order.quantity - cum_qty,
+    };
+
+    let mut report = FixMessage::new(EXECUTION_REPORT)
+        .with(tags::ORDER_ID, &order.id)
+        .with(tags::CL_ORD_ID, cl_ord_id)
+        .with(tags::EXEC_ID, exec_id)
+        .with(tags::EXEC_TYPE, exec_type)
+        .with(tags::ORD_STATUS, ord_status)
+        .with(tags::SYMBOL, &order.symbol)
+        .with(tags::SIDE, if order.side == OrderSide::Buy { "1" } else { "2" })
+        .with(tags::ORDER_QTY, order.quantity.normalize());
+    if let Some(price) = order.price {
+        report.set(tags::PRICE, price.normalize());
+    }
+    report = report
+        .with(tags::LEAVES_QTY, leaves_qty.normalize())
+        .with(tags::CUM_QTY, cum_qty.normalize())
+        .with(tags::AVG_PX, avg_px.normalize());
+    if let (Some(last), "F") = (fills.last(), exec_type) {
+        report.set(tags::LAST_QTY, last.quantity.normalize());
+        report.set(tags::LAST_PX, last.price.normalize());
+    }
+    report.with(tags::TRANSACT_TIME, format_time(Utc::now()))
+}
+
+fn rejection_report(request: &FixMessage, exec_id: String, text: &str) -> FixMessage {
+    let mut report = FixMessage::new(EXECUTION_REPORT)
+        .with(tags::ORDER_ID, "NONE")
+        .with(tags::CL_ORD_ID, request.get(tags::CL_ORD_ID).unwrap_or_default())
+        .with(tags::EXEC_ID, exec_id)
+        .with(tags::EXEC_TYPE, "8")
+        .with(tags::ORD_STATUS, "8");
+    for tag in [tags::SYMBOL, tags::SIDE, tags::ORDER_QTY] {
+        if let Some(value) = request.get(tag) {
+            report.set(tag, value);
+        }
+    }
+    report
+        .with(tags::LEAVES_QTY, 0)
+        .with(tags::CUM_QTY, 0)
+        .with(tags::AVG_PX, 0)
+        .with(tags::TEXT, text)
+}
+
+/// OrderCancelReject for a cancel (`response_to` "1") or cancel/replace ("2") that failed.
+fn cancel_reject(request: &FixMessage, order: Option<&Order>, response_to: &str, text: &str) -> FixMessage {
+    FixMessage::new(ORDER_CANCEL_REJECT)
+        .with(tags::ORDER_ID, order.map_or("NONE", |order| order.id.as_str()))
+        .with(tags::CL_ORD_ID, request.get(tags::CL_ORD_ID).unwrap_or_default())
+        .with(tags::ORIG_CL_ORD_ID, request.get(tags::ORIG_CL_ORD_ID).unwrap_or_default())
+        .with(tags::ORD_STATUS, order.map_or("8", ord_status))
+        .with(tags::CXL_REJ_RESPONSE_TO, response_to)
+        .with(tags::CXL_REJ_REASON, if order.is_none() { 1 } else { 99 }) // Unknown order, or other
+        .with(tags::TEXT, text)
+}
+
+/// FIX 4.4 acceptor in front of an `EngineCluster`, so it trades on the same venue as the API.
+/// Counterparties are configured up front with `add_session`, and each one trades as the trader
+/// named by its SenderCompID. Execution reports cover acks, fills, cancels and rejects for orders
+/// entered over FIX; fills and closes go to the order's own session whoever caused them.
+pub struct FixGateway {
+    comp_id: String,
+    cluster: Arc<EngineCluster>,
+    state: Mutex<GatewayState>,
+}
+
+impl FixGateway {
+    pub fn new(comp_id: &str, cluster: Arc<EngineCluster>) -> Self {
+        Self {
+            comp_id: comp_id.to_string(),
+            cluster,
+            state: Mutex::new(GatewayState::default()),
+        }
+    }
+
+    /// Lets the counterparty with this SenderCompID log on.
+    pub async fn add_session(&self, client_comp_id: &str) {
+        self.state.lock().await.sessions.entry(client_comp_id.to_string()).or_insert_with(Session::new);
+    }
+
+    /// Accepts connections until the listener fails, each on its own task. Expiries, stop triggers
+    /// and fills from outside the gateway are processed and reported in the background meanwhile.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Result<(), String> {
+        let gateway = self.clone();
+        let timer = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TIMER_INTERVAL);
+            loop {
+                interval.tick().await;
+                gateway.process_pending_orders().await;
+            }
+        });
+        let result = loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(error) => break Err(error.to_string()),
+            };
+            let gateway = self.clone();
+            tokio::spawn(async move { gateway.run_connection(stream).await });
+        };
+        timer.abort();
+        result
+    }
+
+    /// Expires due orders on every symbol and reports what changed there since the last look.
+    async fn process_pending_orders(&self) {
+        let mut state = self.state.lock().await;
+        for symbol in self.cluster.get_symbols().await {
+            let processed = self.on_symbol(&mut state, &symbol, |engine| engine.process_pending_orders()).await;
+            if let Ok((before, (), after)) = processed {
+                self.report_changes(&mut state, &before);
+                self.report_changes(&mut state, &after);
+            }
+        }
+    }
+
+    async fn run_connection(&self, stream: TcpStream) {
+        let (mut reader, mut writer) = stream.into_split();
+        let (sender, mut outgoing) = mpsc::unbounded_channel::<Vec<u8>>();
+        let writer_task = tokio::spawn(async move {
+            while let Some(bytes) = outgoing.recv().await {
+                if writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+            let _ = writer.shutdown().await;
+        });
+
+        let mut connection = Connection {
+            session: None,
+            heartbeat: Duration::from_secs(30),
+            opened: Instant::now(),
+            last_received: Instant::now(),
+            test_request_sent: false,
+            awaiting_resend: false,
+            sender,
+        };
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let mut timer = tokio::time::interval(TIMER_INTERVAL);
+
+        'connection: loop {
+            tokio::select! {
+                read = reader.read(&mut chunk) => {
+                    let read = match read {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => read,
+                    };
+                    buffer.extend_from_slice(&chunk[..read]);
+                    loop {
+                        match FixMessage::decode(&buffer) {
+                            Ok(Some((message, used))) => {
+                                buffer.drain(..used);
+                                if !self.on_message(&mut connection, message).await {
+                                    break 'connection;
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(text) => {
+                                self.logout(&connection, &text).await;
+                                break 'connection;
+                            }
+                        }
+                    }
+                }
+                _ = timer.tick() => {
+                    if !self.on_timer(&mut connection).await {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if let Some(session_id) = &connection.session {
+            if let Some(session) = self.state.lock().await.sessions.get_mut(session_id) {
+                session.connection = None;
+            }
+        }
+        drop(connection);
+        let _ = writer_task.await;
+    }
+
+    async fn logout(&self, connection: &Connection, text: &str) {
+        if let Some(session_id) = &connection.session {
+            let logout = FixMessage::new(LOGOUT).with(tags::TEXT, text);
+            self.state.lock().await.send(&self.comp_id, session_id, logout);
+        }
+    }
+
+    /// Handles one message off the wire; false once the connection should close.
+    async fn on_message(&self, connection: &mut Connection, message: FixMessage) -> bool {
+        connection.last_received = Instant::now();
+        connection.test_request_sent = false;
+        let session_id = match &connection.session {
+            Some(session_id) => session_id.clone(),
+            None => return self.on_logon(connection, &message).await,
+        };
+        if message.get(tags::SENDER_COMP_ID) != Some(session_id.as_str())
+            || message.get(tags::TARGET_COMP_ID) != Some(self.comp_id.as_str()) {
+            self.logout(connection, "CompID problem").await;
+            return false;
+        }
+        let seq = match message.seq_num() {
+            Some(seq) => seq,
+            None => {
+                self.logout(connection, "MsgSeqNum missing").await;
+                return false;
+            }
+        };
+
+        {
+            let mut state = self.state.lock().await;
+            let session = state.sessions.get_mut(&session_id).unwrap();
+            let expected = session.next_incoming;
+            let gap_fill = message.get(tags::GAP_FILL_FLAG) == Some("Y");
+            let new_seq_no = message.parse_field::<u64>(tags::NEW_SEQ_NO).ok().flatten();
+
+            // A reset outside gap-fill mode moves the expected number whatever this message's is
+            if message.msg_type() == SEQUENCE_RESET && !gap_fill {
+                if let Some(new_seq_no) = new_seq_no {
+                    session.next_incoming = session.next_incoming.max(new_seq_no);
+                }
+                return true;
+            }
+            if seq > expected {
+                if !connection.awaiting_resend {
+                    connection.awaiting_resend = true;
+                    let resend = FixMessage::new(RESEND_REQUEST)
+                        .with(tags::BEGIN_SEQ_NO, expected)
+                        .with(tags::END_SEQ_NO, 0);
+                    state.send(&self.comp_id, &session_id, resend);
+                }
+                return true;
+            }
+            if seq < expected {
+                if message.get(tags::POSS_DUP_FLAG) == Some("Y") {
+                    return true;
+                }
+                let text = format!("MsgSeqNum too low, expecting {} but received {}", expected, seq);
+                state.send(&self.comp_id, &session_id, FixMessage::new(LOGOUT).with(tags::TEXT, text));
+                return false;
+            }
+
+            session.next_incoming += 1;
+            connection.awaiting_resend = false;
+            if message.msg_type() == SEQUENCE_RESET {
+                session.next_incoming = session.next_incoming.max(new_seq_no.unwrap_or_default());
+                return true;
+            }
+        }
+
+        match message.msg_type() {
+            HEARTBEAT => true,
+            TEST_REQUEST => {
+                let mut heartbeat = FixMessage::new(HEARTBEAT);
+                if let Some(test_req_id) = message.get(tags::TEST_REQ_ID) {
+                    heartbeat.set(tags::TEST_REQ_ID, test_req_id);
+                }
+                self.state.lock().await.send(&self.comp_id, &session_id, heartbeat);
+                true
+            }
+            RESEND_REQUEST => {
+                self.resend(&session_id, &message).await;
+                true
+            }
+            LOGOUT => {
+                self.logout(connection, "Logout acknowledged").await;
+                false
+            }
+            NEW_ORDER_SINGLE => {
+                self.new_order_single(&session_id, &message).await;
+                true
+            }
+            ORDER_CANCEL_REQUEST => {
+                self.order_cancel_request(&session_id, &message).await;
+                true
+            }
+            ORDER_CANCEL_REPLACE_REQUEST => {
+                self.order_cancel_replace_request(&session_id, &message).await;
+                true
+            }
+            msg_type => {
+                let (reason, text) = match msg_type {
+                    LOGON => (99, "Already logged on"),
+                    _ => (11, "Unsupported MsgType"),
+                };
+                let reject = FixMessage::new(REJECT)
+                    .with(tags::REF_SEQ_NUM, seq)
+                    .with(tags::REF_MSG_TYPE, msg_type)
+                    .with(tags::SESSION_REJECT_REASON, reason)
+                    .with(tags::TEXT, text);
+                self.state.lock().await.send(&self.comp_id, &session_id, reject);
+                true
+            }
+        }
+    }
+
+    /// The first message on a connection has to be a Logon from a configured session that is not
+    /// already connected. Anything else drops the connection unanswered.
+    async fn on_logon(&self, connection: &mut Connection, message: &FixMessage) -> bool {
+        let session_id = message.get(tags::SENDER_COMP_ID).unwrap_or_default().to_string();
+        if message.msg_type() != LOGON || message.get(tags::TARGET_COMP_ID) != Some(self.comp_id.as_str()) {
+            return false;
+        }
+
+        let mut state = self.state.lock().await;
+        let session = match state.sessions.get_mut(&session_id) {
+            Some(session) if session.connection.is_none() => session,
+            _ => return false,
+        };
+        let reset = message.get(tags::RESET_SEQ_NUM_FLAG) == Some("Y");
+        if reset {
+            session.next_incoming = 1;
+            session.next_outgoing = 1;
+            session.sent.clear();
+        }
+        session.connection = Some(connection.sender.clone());
+        connection.session = Some(session_id.clone());
+        let expected = session.next_incoming;
+
+        let heartbeat = match message.parse_field::<u64>(tags::HEART_BT_INT) {
+            Ok(Some(heartbeat)) if heartbeat > 0 => heartbeat,
+            _ => {
+                let text = "HeartBtInt must be a positive number of seconds";
+                state.send(&self.comp_id, &session_id, FixMessage::new(LOGOUT).with(tags::TEXT, text));
+                return false;
+            }
+        };
+        let seq = message.seq_num().unwrap_or_default();
+        if seq < expected {
+            let text = format!("MsgSeqNum too low, expecting {} but received {}", expected, seq);
+            state.send(&self.comp_id, &session_id, FixMessage::new(LOGOUT).with(tags::TEXT, text));
+            return false;
+        }
+        if seq == expected {
+            state.sessions.get_mut(&session_id).unwrap().next_incoming += 1;
+        }
+
+        connection.heartbeat = Duration::from_secs(heartbeat);
+        let mut logon = FixMessage::new(LOGON)
+            .with(tags::ENCRYPT_METHOD, 0)
+            .with(tags::HEART_BT_INT, heartbeat);
+        if reset {
+            logon.set(tags::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        state.send(&self.comp_id, &session_id, logon);
+        if seq > expected {
+            connection.awaiting_resend = true;
+            let resend = FixMessage::new(RESEND_REQUEST)
+                .with(tags::BEGIN_SEQ_NO, expected)
+                .with(tags::END_SEQ_NO, 0);
+            state.send(&self.comp_id, &session_id, resend);
+        }
+        true
+    }
+
+    /// Sends a heartbeat when the line has been quiet for the interval, a test request when the
+    /// counterparty has, and gives up on it after a second interval without an answer.
+    async fn on_timer(&self, connection: &mut Connection) -> bool {
+        let session_id = match &connection.session {
+            Some(session_id) => session_id.clone(),
+            None => return connection.opened.elapsed() < LOGON_TIMEOUT,
+        };
+
+        let mut state = self.state.lock().await;
+        let grace = connection.heartbeat + connection.heartbeat / 5;
+        let idle = connection.last_received.elapsed();
+        if connection.test_request_sent && idle >= grace * 2 {
+            state.send(&self.comp_id, &session_id, FixMessage::new(LOGOUT).with(tags::TEXT, "Heartbeat timeout"));
+            return false;
+        }
+        if !connection.test_request_sent && idle >= grace {
+            connection.test_request_sent = true;
+            let test_request = FixMessage::new(TEST_REQUEST).with(tags::TEST_REQ_ID, format_time(Utc::now()));
+            state.send(&self.comp_id, &session_id, test_request);
+        }
+        if state.sessions[&session_id].last_sent.elapsed() >= connection.heartbeat {
+            state.send(&self.comp_id, &session_id, FixMessage::new(HEARTBEAT));
+        }
+        true
+    }
+
+    /// Answers a ResendRequest: application messages go out again as possible duplicates under
+    /// their original numbers, and each run of session messages is skipped with a gap fill.
+    async fn resend(&self, session_id: &str, request: &FixMessage) {
+        let begin = request.parse_field::<u64>(tags::BEGIN_SEQ_NO).ok().flatten().unwrap_or(1).max(1);
+        let end = request.parse_field::<u64>(tags::END_SEQ_NO).ok().flatten().unwrap_or_default();
+
+        let mut state = self.state.lock().await;
+        let session = state.sessions.get_mut(session_id).unwrap();
+        let last = session.next_outgoing - 1;
+        let end = if end == 0 || end > last { last } else { end };
+        let now = Utc::now();
+
+        let mut seq = begin;
+        while seq <= end {
+            match session.sent.get(&seq) {
+                Some(original) => {
+                    let original_time = original.get(tags::SENDING_TIME).unwrap_or_default().to_string();
+                    let message = original.clone()
+                        .with(tags::POSS_DUP_FLAG, "Y")
+                        .with(tags::ORIG_SENDING_TIME, original_time)
+                        .with_header(&self.comp_id, session_id, seq, now);
+                    session.write(&message);
+                    seq += 1;
+                }
+                None => {
+                    let next = session.sent.range(seq..=end).next().map_or(end + 1, |(next, _)| *next);
+                    let gap_fill = FixMessage::new(SEQUENCE_RESET)
+                        .with(tags::POSS_DUP_FLAG, "Y")
+                        .with(tags::GAP_FILL_FLAG, "Y")
+                        .with(tags::NEW_SEQ_NO, next)
+                        .with_header(&self.comp_id, session_id, seq, now);
+                    session.write(&gap_fill);
+                    seq = next;
+                }
+            }
+        }
+    }
+
+    /// Runs `call` on the symbol's engine. It comes back with what changed there: first what others
+    /// changed since the gateway last looked, then what the call changed.
+    async fn on_symbol<T, F>(&self, state: &mut GatewayState, symbol: &str, call: F)
+                             -> Result<(Changes, T, Changes), String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut DEXEngine) -> T + Send + 'static,
+    {
+        let feed = state.feeds.entry(symbol.to_string()).or_default().clone();
+        let owned = symbol.to_string();
+        self.cluster.with_symbol(symbol, move |engine| {
+            let mut feed = feed.lock().unwrap();
+            let before = feed.drain(engine, &owned);
+            let output = call(engine);
+            (before, output, feed.drain(engine, &owned))
+        }).await
+    }
+
+    async fn new_order_single(&self, session_id: &str, message: &FixMessage) {
+        let mut state = self.state.lock().await;
+        let cl_ord_id = message.get(tags::CL_ORD_ID).unwrap_or_default();
+
+        let placed = match NewOrder::parse(message) {
+            _ if cl_ord_id.is_empty() => Err(format!("Required tag {} missing", tags::CL_ORD_ID)),
+            _ if state.find_order(session_id, cl_ord_id).is_some() => Err("Duplicate ClOrdID".to_string()),
+            Ok(request) => {
+                let symbol = request.symbol.clone();
+                let request = OrderRequest {
+                    time_in_force: request.time_in_force,
+                    expire_at: request.expire_at,
+                    ..OrderRequest::new(session_id.to_string(), request.symbol, request.side, request.order_type,
+                                        request.quantity, request.price, request.stop_price)
+                };
+                self.on_symbol(&mut state, &symbol, move |engine| engine.place_order(request)).await
+            }
+            Err(text) => Err(text),
+        };
+        let (before, result, after) = placed.unwrap_or_else(|text| (Changes::default(), Err(text), Changes::default()));
+
+        self.report_changes(&mut state, &before);
+        let order = match result {
+            Ok(order) => order,
+            Err(text) => {
+                let report = rejection_report(message, state.next_exec_id(), &text);
+                state.send(&self.comp_id, session_id, report);
+                return;
+            }
+        };
+        state.track(&order.id, session_id, cl_ord_id);
+        let ack = execution_report(&order, cl_ord_id, state.next_exec_id(), "0", "0", &[]);
+        state.send(&self.comp_id, session_id, ack);
+        self.report_changes(&mut state, &after);
+        self.report_closed(&mut state, &order);
+    }
+
+    async fn order_cancel_request(&self, session_id: &str, message: &FixMessage) {
+        let mut state = self.state.lock().await;
+        let cl_ord_id = message.get(tags::CL_ORD_ID).unwrap_or_default();
+        let order_id = state.find_order(session_id, message.get(tags::ORIG_CL_ORD_ID).unwrap_or_default());
+        let symbol = match &order_id {
+            Some(order_id) => self.cluster.symbol_of(order_id).await,
+            None => None,
+        };
+
+        let cancelled = match (&order_id, symbol) {
+            _ if cl_ord_id.is_empty() => Err(format!("Required tag {} missing", tags::CL_ORD_ID)),
+            (Some(order_id), Some(symbol)) => {
+                let (owned, trader) = (order_id.clone(), session_id.to_string());
+                self.on_symbol(&mut state, &symbol, move |engine| {
+                    (engine.cancel_order(&owned, &trader), engine.get_order(&owned))
+                }).await
+            }
+            _ => Err("Unknown order".to_string()),
+        };
+        let (before, (result, order), after) = match cancelled {
+            Ok(cancelled) => cancelled,
+            Err(text) => {
+                let order = match &order_id {
+                    Some(order_id) => self.cluster.get_order(order_id).await,
+                    None => None,
+                };
+                state.send(&self.comp_id, session_id, cancel_reject(message, order.as_ref(), "1", &text));
+                return;
+            }
+        };
+
+        self.report_changes(&mut state, &before);
+        match (result, order) {
+            (Ok(()), Some(order)) => {
+                state.track(&order.id, session_id, cl_ord_id);
+                state.close(&order.id);
+                let report = execution_report(&order, cl_ord_id, state.next_exec_id(), "4", "4", &order.fills)
+                    .with(tags::ORIG_CL_ORD_ID, message.get(tags::ORIG_CL_ORD_ID).unwrap_or_default());
+                state.send(&self.comp_id, session_id, report);
+            }
+            (result, order) => {
+                let text = result.err().unwrap_or_else(|| "Unknown order".to_string());
+                state.send(&self.comp_id, session_id, cancel_reject(message, order.as_ref(), "1", &text));
+            }
+        }
+        self.report_changes(&mut state, &after);
+    }
+
+    /// Maps a cancel/replace onto `amend_order`, so the order keeps its OrderID and, when only its
+    /// quantity shrinks, its queue priority.
+    async fn order_cancel_replace_request(&self, session_id: &str, message: &FixMessage) {
+        let mut state = self.state.lock().await;
+        let cl_ord_id = message.get(tags::CL_ORD_ID).unwrap_or_default();
+        let order_id = state.find_order(session_id, message.get(tags::ORIG_CL_ORD_ID).unwrap_or_default());
+        let symbol = match &order_id {
+            Some(order_id) => self.cluster.symbol_of(order_id).await,
+            None => None,
+        };
+
+        let amendment = message.parse_field::<Decimal>(tags::ORDER_QTY)
+            .and_then(|quantity| Ok((quantity, message.parse_field::<Decimal>(tags::PRICE)?)));
+        let amended = match (&order_id, symbol, amendment) {
+            _ if cl_ord_id.is_empty() => Err(format!("Required tag {} missing", tags::CL_ORD_ID)),
+            (Some(_), Some(_), Err(text)) => Err(text),
+            (Some(order_id), Some(symbol), Ok((quantity, price))) => {
+                let (owned, trader) = (order_id.clone(), session_id.to_string());
+                self.on_symbol(&mut state, &symbol, move |engine| {
+                    let fills_before = engine.get_order(&owned).map_or(0, |order| order.fills.len());
+                    let result = engine.amend_order(&owned, &trader, quantity, price);
+                    (fills_before, result, engine.get_order(&owned))
+                }).await
+            }
+            _ => Err("Unknown order".to_string()),
+        };
+        let (before, (fills_before, result, current), after) = match amended {
+            Ok(amended) => amended,
+            Err(text) => {
+                let order = match &order_id {
+                    Some(order_id) => self.cluster.get_order(order_id).await,
+                    None => None,
+                };
+                state.send(&self.comp_id, session_id, cancel_reject(message, order.as_ref(), "2", &text));
+                return;
+            }
+        };
+
+        self.report_changes(&mut state, &before);
+        match result {
+            Ok(order) => {
+                state.track(&order.id, session_id, cl_ord_id);
+                let status = if fills_before > 0 { "1" } else { "0" };
+                let report = execution_report(&order, cl_ord_id, state.next_exec_id(), "5", status,
+                                              &order.fills[..fills_before])
+                    .with(tags::ORIG_CL_ORD_ID, message.get(tags::ORIG_CL_ORD_ID).unwrap_or_default());
+                state.send(&self.comp_id, session_id, report);
+            }
+            Err(text) => {
+                state.send(&self.comp_id, session_id, cancel_reject(message, current.as_ref(), "2", &text));
+            }
+        }
+        self.report_changes(&mut state, &after);
+    }
+
+    /// Reports the gateway's stop orders that triggered, then sends a fill report for each side of
+    /// each trade to the session that entered that order, then reports the gateway orders the
+    /// engine closed itself.
+    fn report_changes(&self, state: &mut GatewayState, changes: &Changes) {
+        for order in &changes.orders {
+            if !order.status_history.iter().any(|change| change.status == OrderStatus::Triggered) {
+                continue;
+            }
+            let (session_id, cl_ord_id) = match state.orders.get_mut(&order.id) {
+                Some(client_order) if !client_order.triggered => {
+                    client_order.triggered = true;
+                    (client_order.session.clone(), client_order.cl_ord_id.clone())
+                }
+                _ => continue,
+            };
+            let report = execution_report(order, &cl_ord_id, state.next_exec_id(), "L", "0", &[]);
+            state.send(&self.comp_id, &session_id, report);
+        }
+        for trade in &changes.trades {
+            for order_id in [&trade.buy_order_id, &trade.sell_order_id] {
+                let (session_id, cl_ord_id) = match state.orders.get(order_id) {
+                    Some(client_order) => (client_order.session.clone(), client_order.cl_ord_id.clone()),
+                    None => continue,
+                };
+                let order = match changes.orders.iter().find(|order| &order.id == order_id) {
+                    Some(order) => order,
+                    None => continue,
+                };
+                let fills = match order.fills.iter().position(|fill| fill.trade_id == trade.id) {
+                    Some(position) => &order.fills[..=position],
+                    None => continue,
+                };
+
+                let filled: Decimal = fills.iter().map(|fill| fill.quantity).sum();
+                let status = if filled >= order.quantity { "2" } else { "1" };
+                let report = execution_report(order, &cl_ord_id, state.next_exec_id(), "F", status, fills);
+                state.send(&self.comp_id, &session_id, report);
+            }
+        }
+        for order in &changes.orders {
+            self.report_closed(state, order);
+        }
+    }
+
+    /// Reports an order the engine closed itself, such as an IOC or market remainder or an expiry,
+    /// once.
+    fn report_closed(&self, state: &mut GatewayState, order: &Order) {
+        if !matches!(order.status, OrderStatus::Cancelled | OrderStatus::Expired) {
+            return;
+        }
+        let (session_id, cl_ord_id) = match state.close(&order.id) {
+            Some(client_order) => client_order,
+            None => return,
+        };
+        let status = ord_status(order);
+        let report = execution_report(order, &cl_ord_id, state.next_exec_id(), status, status, &order.fills);
+        state.send(&self.comp_id, &session_id, report);
+    }
+}
+
+/// Initiator end of a session, enough to drive the gateway from tests or a local tool. It stamps
+/// its own sequence numbers and hands back every message the gateway sends.
+pub struct FixClient {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    sender_comp_id: String,
+    target_comp_id: String,
+    next_seq: u64,
+}
+
+impl FixClient {
+    pub async fn connect(address: impl ToSocketAddrs, sender_comp_id: &str, target_comp_id: &str)
+                         -> Result<Self, String> {
+        let stream = TcpStream::connect(address).await.map_err(|e| e.to_string())?;
+        Ok(Self {
+            stream,
+            buffer: Vec::new(),
+            sender_comp_id: sender_comp_id.to_string(),
+            target_comp_id: target_comp_id.to_string(),
+            next_seq: 1,
+        })
+    }
+
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    pub fn set_next_seq(&mut self, seq: u64) {
+        self.next_seq = seq;
+    }
+
+    /// Sends under the next sequence number and returns it.
+    pub async fn send(&mut self, message: FixMessage) -> Result<u64, String> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.send_with_seq(message, seq).await?;
+        Ok(seq)
+    }
+
+    /// Sends under a chosen sequence number, leaving the next one alone.
+    pub async fn send_with_seq(&mut self, message: FixMessage, seq: u64) -> Result<(), String> {
+        let message = message.with_header(&self.sender_comp_id, &self.target_comp_id, seq, Utc::now());
+        self.stream.write_all(&message.encode()).await.map_err(|e| e.to_string())
+    }
+
+    pub async fn receive(&mut self) -> Result<FixMessage, String> {
+        loop {
+            if let Some((message, used)) = FixMessage::decode(&self.buffer)? {
+                self.buffer.drain(..used);
+                return Ok(message);
+            }
+
+            let mut chunk = [0u8; 4096];
+            let read = tokio::time::timeout(CLIENT_RECEIVE_TIMEOUT, self.stream.read(&mut chunk)).await
+                .map_err(|_| "Timed out waiting for a message".to_string())?
+                .map_err(|e| e.to_string())?;
+            if read == 0 {
+                return Err("Connection closed".to_string());
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    /// Next message that is not a heartbeat.
+    pub async fn receive_except_heartbeats(&mut self) -> Result<FixMessage, String> {
+        loop {
+            let message = self.receive().await?;
+            if message.msg_type() != HEARTBEAT {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn start_gateway(sessions: &[&str]) -> (Arc<EngineCluster>, std::net::SocketAddr) {
+        let cluster = Arc::new(EngineCluster::new());
+        cluster.add_symbol("ETH/USDC".to_string()).await.unwrap();
+        let gateway = Arc::new(FixGateway::new("DEX", cluster.clone()));
+        for session in sessions {
+            gateway.add_session(session).await;
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(gateway.serve(listener));
+        (cluster, address)
+    }
+
+    async fn logon(address: std::net::SocketAddr, comp_id: &str, heartbeat: u64) -> FixClient {
+        let mut client = FixClient::connect(address, comp_id, "DEX").await.unwrap();
+        client.send(FixMessage::new(LOGON).with(tags::ENCRYPT_METHOD, 0).with(tags::HEART_BT_INT, heartbeat))
+            .await.unwrap();
+        let reply = client.receive().await.unwrap();
+        assert_eq!((reply.msg_type(), reply.get(tags::HEART_BT_INT)), (LOGON, Some(heartbeat.to_string().as_str())));
+        client
+    }
+
+    fn new_order(cl_ord_id: &str, side: &str, quantity: &str, price: &str) -> FixMessage {
+        FixMessage::new(NEW_ORDER_SINGLE)
+            .with(tags::CL_ORD_ID, cl_ord_id)
+            .with(tags::SYMBOL, "ETH/USDC")
+            .with(tags::SIDE, side)
+            .with(tags::ORDER_QTY, quantity)
+            .with(tags::ORD_TYPE, "2")
+            .with(tags::PRICE, price)
+            .with(tags::TIME_IN_FORCE, "1")
+    }
+
+    #[tokio::test]
+    async fn test_fix_order_entry_and_execution_reports() {
+        let (cluster, address) = start_gateway(&["BUYER", "SELLER"]).await;
+        cluster.deposit("BUYER", "USDC", Decimal::new(100000, 0)).await;
+        cluster.deposit("SELLER", "ETH", Decimal::new(10, 0)).await;
+        let mut seller = logon(address, "SELLER", 30).await;
+        let mut buyer = logon(address, "BUYER", 30).await;
+
+        seller.send(new_order("s1", "2", "2", "2000")).await.unwrap();
+        let ack = seller.receive().await.unwrap();
+        assert_eq!((ack.get(tags::EXEC_TYPE), ack.get(tags::ORD_STATUS), ack.get(tags::LEAVES_QTY)),
+                   (Some("0"), Some("0"), Some("2")));
+
+        // The buyer's ack comes before its fill, and the resting seller hears about its own fill
+        buyer.send(new_order("b1", "1", "3", "2000")).await.unwrap();
+        let ack = buyer.receive().await.unwrap();
+        assert_eq!((ack.msg_type(), ack.get(tags::CL_ORD_ID), ack.get(tags::EXEC_TYPE)),
+                   (EXECUTION_REPORT, Some("b1"), Some("0")));
+        let fill = buyer.receive().await.unwrap();
+        assert_eq!((fill.get(tags::EXEC_TYPE), fill.get(tags::ORD_STATUS)), (Some("F"), Some("1")));
+        assert_eq!((fill.get(tags::LAST_QTY), fill.get(tags::LAST_PX)), (Some("2"), Some("2000")));
+        assert_eq!((fill.get(tags::CUM_QTY), fill.get(tags::LEAVES_QTY)), (Some("2"), Some("1")));
+        let maker_fill = seller.receive().await.unwrap();
+        assert_eq!((maker_fill.get(tags::CL_ORD_ID), maker_fill.get(tags::ORD_STATUS)), (Some("s1"), Some("2")));
+
+        // Cancel/replace keeps the OrderID, and the cancel refers to the latest ClOrdID
+        buyer.send(FixMessage::new(ORDER_CANCEL_REPLACE_REQUEST)
+            .with(tags::ORIG_CL_ORD_ID, "b1")
+            .with(tags::CL_ORD_ID, "b2")
+            .with(tags::SYMBOL, "ETH/USDC")
+            .with(tags::SIDE, "1")
+            .with(tags::ORDER_QTY, "4")
+            .with(tags::ORD_TYPE, "2")
+            .with(tags::PRICE, "1990")).await.unwrap();
+        let replaced = buyer.receive().await.unwrap();
+        assert_eq!((replaced.get(tags::EXEC_TYPE), replaced.get(tags::ORIG_CL_ORD_ID)), (Some("5"), Some("b1")));
+        assert_eq!((replaced.get(tags::ORDER_ID), replaced.get(tags::LEAVES_QTY)), (ack.get(tags::ORDER_ID), Some("2")));
+
+        buyer.send(FixMessage::new(ORDER_CANCEL_REQUEST)
+            .with(tags::ORIG_CL_ORD_ID, "b2")
+            .with(tags::CL_ORD_ID, "b3")
+            .with(tags::SYMBOL, "ETH/USDC")
+            .with(tags::SIDE, "1")).await.unwrap();
+        let cancelled = buyer.receive().await.unwrap();
+        assert_eq!((cancelled.get(tags::EXEC_TYPE), cancelled.get(tags::CUM_QTY)), (Some("4"), Some("2")));
+
+        buyer.send(FixMessage::new(ORDER_CANCEL_REQUEST)
+            .with(tags::ORIG_CL_ORD_ID, "missing")
+            .with(tags::CL_ORD_ID, "b4")).await.unwrap();
+        let reject = buyer.receive().await.unwrap();
+        assert_eq!((reject.msg_type(), reject.get(tags::CXL_REJ_REASON)), (ORDER_CANCEL_REJECT, Some("1")));
+
+        // Engine validation comes back as a rejected ExecutionReport
+        buyer.send(new_order("b5", "1", "1", "2000.001")).await.unwrap();
+        let rejected = buyer.receive().await.unwrap();
+        assert_eq!(rejected.get(tags::EXEC_TYPE), Some("8"));
+        assert!(rejected.get(tags::TEXT).unwrap().contains("tick"));
+        assert_eq!(cluster.get_balance("BUYER", "ETH").await.available, Decimal::new(2, 0));
+    }
+
+    #[tokio::test]
+    async fn test_fix_reports_fills_from_outside_the_gateway() {
+        let (cluster, address) = start_gateway(&["SELLER"]).await;
+        cluster.deposit("SELLER", "ETH", Decimal::new(10, 0)).await;
+        cluster.deposit("api_buyer", "USDC", Decimal::new(100000, 0)).await;
+        let mut seller = logon(address, "SELLER", 30).await;
+
+        seller.send(new_order("s1", "2", "2", "2000")).await.unwrap();
+        let ack = seller.receive().await.unwrap();
+        assert_eq!(ack.get(tags::EXEC_TYPE), Some("0"));
+
+        // A buyer trading on the cluster directly, as API clients do, takes part of the FIX order
+        cluster.place_order(OrderRequest::new("api_buyer".to_string(), "ETH/USDC".to_string(), OrderSide::Buy,
+                                              OrderType::Limit, Decimal::ONE, Some(Decimal::new(2000, 0)), None))
+            .await.unwrap();
+
+        // The fill is reported before anything the session asks for next
+        seller.send(new_order("s2", "2", "1", "2100")).await.unwrap();
+        let fill = seller.receive_except_heartbeats().await.unwrap();
+        assert_eq!((fill.get(tags::CL_ORD_ID), fill.get(tags::EXEC_TYPE), fill.get(tags::ORD_STATUS)),
+                   (Some("s1"), Some("F"), Some("1")));
+        assert_eq!((fill.get(tags::LAST_QTY), fill.get(tags::LEAVES_QTY)), (Some("1"), Some("1")));
+        let ack = seller.receive_except_heartbeats().await.unwrap();
+        assert_eq!((ack.get(tags::CL_ORD_ID), ack.get(tags::EXEC_TYPE)), (Some("s2"), Some("0")));
+        assert_eq!(cluster.get_balance("api_buyer", "ETH").await.available, Decimal::ONE);
+    }
+
+    #[tokio::test]
+    async fn test_fix_reports_expiries_and_triggers_unprompted() {
+        let (cluster, address) = start_gateway(&["CLIENT"]).await;
+        cluster.deposit("CLIENT", "ETH", Decimal::new(10, 0)).await;
+        cluster.deposit("api_buyer", "USDC", Decimal::new(100000, 0)).await;
+        cluster.deposit("api_seller", "ETH", Decimal::new(10, 0)).await;
+        let mut client = logon(address, "CLIENT", 30).await;
+
+        // A good-till-date order expires on the gateway's timer and the session hears of it
+        let expire_at = Utc::now() + chrono::Duration::milliseconds(300);
+        client.send(new_order("gtd", "2", "1", "2500")
+            .with(tags::TIME_IN_FORCE, "6")
+            .with(tags::EXPIRE_TIME, format_time(expire_at))).await.unwrap();
+        assert_eq!(client.receive_except_heartbeats().await.unwrap().get(tags::EXEC_TYPE), Some("0"));
+        let expired = client.receive_except_heartbeats().await.unwrap();
+        assert_eq!((expired.get(tags::CL_ORD_ID), expired.get(tags::EXEC_TYPE), expired.get(tags::ORD_STATUS)),
+                   (Some("gtd"), Some("C"), Some("C")));
+
+        // A stop set off by trades made on the cluster directly is reported triggered, then filled
+        client.send(FixMessage::new(NEW_ORDER_SINGLE)
+            .with(tags::CL_ORD_ID, "stop")
+            .with(tags::SYMBOL, "ETH/USDC")
+            .with(tags::SIDE, "2")
+            .with(tags::ORDER_QTY, "1")
+            .with(tags::ORD_TYPE, "3")
+            .with(tags::STOP_PX, "1900")).await.unwrap();
+        assert_eq!(client.receive_except_heartbeats().await.unwrap().get(tags::EXEC_TYPE), Some("0"));
+        cluster.place_order(OrderRequest::new("api_buyer".to_string(), "ETH/USDC".to_string(), OrderSide::Buy,
+                                              OrderType::Limit, Decimal::new(2, 0), Some(Decimal::new(1900, 0)), None))
+            .await.unwrap();
+        cluster.place_order(OrderRequest::new("api_seller".to_string(), "ETH/USDC".to_string(), OrderSide::Sell,
+                                              OrderType::Limit, Decimal::ONE, Some(Decimal::new(1900, 0)), None))
+            .await.unwrap();
+        let triggered = client.receive_except_heartbeats().await.unwrap();
+        assert_eq!((triggered.get(tags::CL_ORD_ID), triggered.get(tags::EXEC_TYPE)), (Some("stop"), Some("L")));
+        let fill = client.receive_except_heartbeats().await.unwrap();
+        assert_eq!((fill.get(tags::EXEC_TYPE), fill.get(tags::ORD_STATUS), fill.get(tags::LAST_PX)),
+                   (Some("F"), Some("2"), Some("1900")));
+        assert_eq!(cluster.get_balance("CLIENT", "USDC").await.available, Decimal::new(1900, 0));
+    }
+
+    #[tokio::test]
+    async fn test_fix_session_sequencing_resends_and_heartbeats() {
+        let (cluster, address) = start_gateway(&["CLIENT"]).await;
+        cluster.deposit("CLIENT", "ETH", Decimal::new(10, 0)).await;
+        let mut client = logon(address, "CLIENT", 1).await;
+
+        client.send(new_order("c1", "2", "1", "2000")).await.unwrap();
+        assert_eq!(client.receive_except_heartbeats().await.unwrap().get(tags::MSG_SEQ_NUM), Some("2"));
+
+        // The Logon is gap-filled and the ExecutionReport comes again as a possible duplicate
+        client.send(FixMessage::new(RESEND_REQUEST).with(tags::BEGIN_SEQ_NO, 1).with(tags::END_SEQ_NO, 0))
+            .await.unwrap();
+        let gap_fill = client.receive_except_heartbeats().await.unwrap();
+        assert_eq!((gap_fill.msg_type(), gap_fill.get(tags::MSG_SEQ_NUM), gap_fill.get(tags::NEW_SEQ_NO)),
+                   (SEQUENCE_RESET, Some("1"), Some("2")));
+        let resent = client.receive_except_heartbeats().await.unwrap();
+        assert_eq!((resent.get(tags::MSG_SEQ_NUM), resent.get(tags::POSS_DUP_FLAG)), (Some("2"), Some("Y")));
+        assert!(resent.get(tags::ORIG_SENDING_TIME).is_some());
+
+        // A gap in the client's numbers is asked for again and closed with a gap fill
+        let skipped = client.next_seq();
+        client.set_next_seq(skipped + 1);
+        client.send(FixMessage::new(HEARTBEAT)).await.unwrap();
+        let request = client.receive_except_heartbeats().await.unwrap();
+        assert_eq!((request.msg_type(), request.get(tags::BEGIN_SEQ_NO)), (RESEND_REQUEST, Some(skipped.to_string().as_str())));
+        client.send_with_seq(FixMessage::new(SEQUENCE_RESET)
+            .with(tags::POSS_DUP_FLAG, "Y")
+            .with(tags::GAP_FILL_FLAG, "Y")
+            .with(tags::NEW_SEQ_NO, client.next_seq()), skipped).await.unwrap();
+        client.send(FixMessage::new(TEST_REQUEST).with(tags::TEST_REQ_ID, "ping")).await.unwrap();
+        let heartbeat = client.receive().await.unwrap();
+        assert_eq!((heartbeat.msg_type(), heartbeat.get(tags::TEST_REQ_ID)), (HEARTBEAT, Some("ping")));
+
+        // A quiet line gets heartbeats; logout is acknowledged and the numbers survive a reconnect
+        let heartbeat = client.receive().await.unwrap();
+        assert_eq!((heartbeat.msg_type(), heartbeat.get(tags::TEST_REQ_ID)), (HEARTBEAT, None));
+        client.send(FixMessage::new(LOGOUT)).await.unwrap();
+        assert_eq!(client.receive_except_heartbeats().await.unwrap().msg_type(), LOGOUT);
+        let next_seq = client.next_seq();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut client = FixClient::connect(address, "CLIENT", "DEX").await.unwrap();
+        client.send(FixMessage::new(LOGON).with(tags::HEART_BT_INT, 30)).await.unwrap();
+        let logout = client.receive().await.unwrap();
+        assert_eq!(logout.msg_type(), LOGOUT);
+        assert!(logout.get(tags::TEXT).unwrap().contains("too low"));
+
+        let mut client = FixClient::connect(address, "CLIENT", "DEX").await.unwrap();
+        client.set_next_seq(next_seq);
+        client.send(FixMessage::new(LOGON).with(tags::HEART_BT_INT, 30)).await.unwrap();
+        assert_eq!(client.receive().await.unwrap().msg_type(), LOGON);
+    }
+
+    #[test]
+    fn test_fix_message_encoding() {
+        let message = FixMessage::new(HEARTBEAT).with_header("A", "B", 7, parse_time("20240301-10:00:00").unwrap());
+        let encoded = message.encode();
+        assert!(String::from_utf8(encoded.clone()).unwrap()
+            .starts_with("8=FIX.4.4\x019=45\x0135=0\x0149=A\x0156=B\x0134=7\x0152=20240301-10:00:00.000\x0110="));
+        assert_eq!(FixMessage::decode(&encoded).unwrap(), Some((message, encoded.len())));
+        assert_eq!(FixMessage::decode(&encoded[..20]).unwrap(), None);
+
+        let mut corrupted = encoded.clone();
+        corrupted[25] = b'1';
+        assert!(FixMessage::decode(&corrupted).unwrap_err().contains("CheckSum"));
+    }
+}
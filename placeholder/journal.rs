@@ -0,0 +1,408 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use sha3::{Digest, Sha3_256};
+
+use crate::dex_engine::{
+    DEXEngine, DepthUpdate, FeeSchedule, Order, OrderEvent, OrderGroup, OrderLeg, OrderRequest, PriceBands,
+    SelfTradePrevention, SymbolSpec, TradingStatus, TrailingOffset,
+};
+
+const JOURNAL_FILE: &str = "journal.log";
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+
+/// A state-changing `DEXEngine` call, as recorded in the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum EngineCommand {
+    AddSymbol { symbol: String },
+    AddSymbolSpec { spec: SymbolSpec },
+    SetTradingStatus { symbol: String, status: TradingStatus },
+    SetPriceBands { symbol: String, bands: PriceBands },
+    SetReferencePrice { symbol: String, price: Decimal },
+    HaltSymbol { symbol: String, reason: String },
+    ResumeSymbol { symbol: String, reason: String },
+    SetFeeSchedule { symbol: String, schedule: FeeSchedule },
+    SetFeeAccount { account: String },
+    SetSelfTradePrevention { trader: String, mode: Option<SelfTradePrevention> },
+    Deposit { user: String, currency: String, amount: Decimal },
+    Withdraw { user: String, currency: String, amount: Decimal },
+    UpdateBalance { user: String, currency: String, amount: Decimal },
+    PlaceOrder(OrderRequest),
+    PlaceTrailingStopOrder {
+        #[serde(flatten)]
+        request: OrderRequest,
+        offset: TrailingOffset,
+        limit_offset: Option<Decimal>,
+    },
+    PlaceOcoOrder {
+        #[serde(flatten)]
+        first: OrderRequest,
+        second: OrderLeg,
+    },
+    PlaceBracketOrder {
+        #[serde(flatten)]
+        entry: OrderRequest,
+        take_profit_price: Decimal,
+        stop_loss_price: Decimal,
+    },
+    AmendOrder { order_id: String, trader: String, new_quantity: Option<Decimal>, new_price: Option<Decimal> },
+    CancelOrder { order_id: String, trader: String },
+    CancelOrderGroup { group_id: String, trader: String },
+    ProcessLimitOrderMatching { symbol: String },
+    ProcessPendingOrders,
+}
+
+/// What a command returned, for the calls that return something.
+#[derive(Debug, Clone)]
+pub enum CommandOutput {
+    None,
+    Order(Box<Order>),
+    OrderGroup(Box<OrderGroup>),
+}
+
+impl EngineCommand {
+    pub fn apply(&self, engine: &mut DEXEngine) -> Result<CommandOutput, String> {
+        match self.clone() {
+            EngineCommand::AddSymbol { symbol } => engine.add_symbol(symbol),
+            EngineCommand::AddSymbolSpec { spec } => engine.add_symbol_spec(spec)?,
+            EngineCommand::SetTradingStatus { symbol, status } => engine.set_trading_status(&symbol, status)?,
+            EngineCommand::SetPriceBands { symbol, bands } => engine.set_price_bands(&symbol, bands)?,
+            EngineCommand::SetReferencePrice { symbol, price } => engine.set_reference_price(&symbol, price)?,
+            EngineCommand::HaltSymbol { symbol, reason } => engine.halt_symbol(&symbol, &reason)?,
+            EngineCommand::ResumeSymbol { symbol, reason } => engine.resume_symbol(&symbol, &reason)?,
+            EngineCommand::SetFeeSchedule { symbol, schedule } => engine.set_fee_schedule(&symbol, schedule)?,
+            EngineCommand::SetFeeAccount { account } => engine.set_fee_account(&account),
+            EngineCommand::SetSelfTradePrevention { trader, mode } => engine.set_self_trade_prevention(&trader, mode),
+            EngineCommand::Deposit { user, currency, amount } => engine.deposit(&user, &currency, amount),
+            EngineCommand::Withdraw { user, currency, amount } => engine.withdraw(&user, &currency, amount)?,
+            EngineCommand::UpdateBalance { user, currency, amount } => engine.update_balance(&user, &currency, amount),
+            EngineCommand::PlaceOrder(request) => {
+                return engine.place_order(request).map(|order| CommandOutput::Order(Box::new(order)));
+            }
+            EngineCommand::PlaceTrailingStopOrder { request, offset, limit_offset } => {
+                return engine.place_trailing_stop_order(request, offset, limit_offset)
+                    .map(|order| CommandOutput::Order(Box::new(order)));
+            }
+            EngineCommand::PlaceOcoOrder { first, second } => {
+                return engine.place_oco_order(first, second).map(|group| CommandOutput::OrderGroup(Box::new(group)));
+            }
+            EngineCommand::PlaceBracketOrder { entry, take_profit_price, stop_loss_price } => {
+                return engine.place_bracket_order(entry, take_profit_price, stop_loss_price)
+                    .map(|group| CommandOutput::OrderGroup(Box::new(group)));
+            }
+            EngineCommand::AmendOrder { order_id, trader, new_quantity, new_price } => {
+                return engine.amend_order(&order_id, &trader, new_quantity, new_price).map(|order| CommandOutput::Order(Box::new(order)));
+            }
+            EngineCommand::CancelOrder { order_id, trader } => engine.cancel_order(&order_id, &trader)?,
+            EngineCommand::CancelOrderGroup { group_id, trader } => {
+                return engine.cancel_order_group(&group_id, &trader).map(|group| CommandOutput::OrderGroup(Box::new(group)));
+            }
+            EngineCommand::ProcessLimitOrderMatching { symbol } => engine.process_limit_order_matching(&symbol)?,
+            EngineCommand::ProcessPendingOrders => engine.process_pending_orders(),
+        }
+        Ok(CommandOutput::None)
+    }
+}
+
+/// One journal line: the command and the engine time it ran at, so a replay stamps the same times.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub command: EngineCommand,
+}
+
+fn checksum(bytes: &[u8]) -> String {
+    let mut hasher = Sha3_256::new();
+    hasher.update(bytes);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Splits a "<checksum> <payload>" record and checks the payload against it.
+fn verify_record<'a>(record: &'a str, what: &str) -> Result<&'a str, String> {
+    let (expected, payload) = record.split_once(' ')
+        .ok_or_else(|| format!("{} is malformed", what))?;
+    if checksum(payload.as_bytes()) != expected {
+        return Err(format!("{} failed its checksum", what));
+    }
+    Ok(payload)
+}
+
+/// A `DEXEngine` whose state-changing calls are written to an append-only journal before they run.
+/// Snapshots of the whole engine are taken every `snapshot_interval` commands (never when zero),
+/// and `open` recovers by loading the latest snapshot and replaying the journal after it.
+///
+/// Files in the directory:
+/// - `journal.log`: one "<sha3> <entry json>" line per command, rejected ones included since
+///   replaying them rejects them again.
+/// - `snapshot-<sequence>.json`: a "<sha3> <sequence>" header line followed by the engine json,
+///   taken after the command with that sequence.
+pub struct JournaledEngine {
+    engine: DEXEngine,
+    directory: PathBuf,
+    journal: File,
+    sequence: u64,
+    snapshot_sequence: u64,
+    snapshot_interval: u64,
+}
+
+impl JournaledEngine {
+    pub fn open(directory: impl AsRef<Path>, snapshot_interval: u64) -> Result<Self, String> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory).map_err(|e| format!("Cannot create {}: {}", directory.display(), e))?;
+
+        let (mut engine, snapshot_sequence) = match Self::latest_snapshot(&directory)? {
+            Some(path) => Self::load_snapshot(&path)?,
+            None => (DEXEngine::new(), 0),
+        };
+
+        let journal_path = directory.join(JOURNAL_FILE);
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&journal_path)
+            .map_err(|e| format!("Cannot open {}: {}", journal_path.display(), e))?;
+
+        let mut sequence = 0;
+        let mut complete_length = 0;
+        let mut reader = BufReader::new(&mut journal);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line).map_err(|e| format!("Cannot read journal: {}", e))?;
+            // An unterminated last line is a write the crash interrupted; the command never ran
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            complete_length += read as u64;
+
+            let what = format!("Journal entry {}", sequence + 1);
+            let payload = verify_record(line.trim_end_matches('\n'), &what)?;
+            let entry: JournalEntry = serde_json::from_str(payload).map_err(|e| format!("{} is invalid: {}", what, e))?;
+            if entry.sequence != sequence + 1 {
+                return Err(format!("Journal skips from entry {} to {}", sequence, entry.sequence));
+            }
+            sequence = entry.sequence;
+
+            if entry.sequence > snapshot_sequence {
+                engine.set_clock(Some(entry.timestamp));
+                let _ = entry.command.apply(&mut engine);
+                engine.set_clock(None);
+            }
+        }
+        journal.set_len(complete_length).map_err(|e| format!("Cannot truncate journal: {}", e))?;
+
+        if sequence < snapshot_sequence {
+            return Err(format!("Journal ends at entry {} but the latest snapshot is at {}", sequence, snapshot_sequence));
+        }
+
+        Ok(Self { engine, directory, journal, sequence, snapshot_sequence, snapshot_interval })
+    }
+
+    pub fn engine(&self) -> &DEXEngine {
+        &self.engine
+    }
+
+    /// Sequence number of the last journaled command.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Journals the command, then runs it at the time it was journaled with.
+    pub fn execute(&mut self, command: EngineCommand) -> Result<CommandOutput, String> {
+        let entry = JournalEntry { sequence: self.sequence + 1, timestamp: self.engine.now(), command };
+        let payload = serde_json::to_string(&entry).map_err(|e| format!("Cannot serialize command: {}", e))?;
+        writeln!(self.journal, "{} {}", checksum(payload.as_bytes()), payload)
+            .and_then(|_| self.journal.sync_data())
+            .map_err(|e| format!("Cannot write journal: {}", e))?;
+        self.sequence = entry.sequence;
+
+        self.engine.set_clock(Some(entry.timestamp));
+        let output = entry.command.apply(&mut self.engine);
+        self.engine.set_clock(None);
+
+        if self.snapshot_interval > 0 && self.sequence - self.snapshot_sequence >= self.snapshot_interval {
+            self.snapshot()?;
+        }
+        output
+    }
+
+    pub fn place_order(&mut self, request: OrderRequest) -> Result<Order, String> {
+        match self.execute(EngineCommand::PlaceOrder(request))? {
+            CommandOutput::Order(order) => Ok(*order),
+            _ => unreachable!("placing an order returns the order"),
+        }
+    }
+
+    pub fn cancel_order(&mut self, order_id: &str, trader: &str) -> Result<(), String> {
+        self.execute(EngineCommand::CancelOrder { order_id: order_id.to_string(), trader: trader.to_string() })
+            .map(|_| ())
+    }
+
+    pub fn deposit(&mut self, user: &str, currency: &str, amount: Decimal) -> Result<(), String> {
+        self.execute(EngineCommand::Deposit { user: user.to_string(), currency: currency.to_string(), amount })
+            .map(|_| ())
+    }
+
+    pub fn withdraw(&mut self, user: &str, currency: &str, amount: Decimal) -> Result<(), String> {
+        self.execute(EngineCommand::Withdraw { user: user.to_string(), currency: currency.to_string(), amount })
+            .map(|_| ())
+    }
+
+    pub fn add_symbol(&mut self, symbol: String) -> Result<(), String> {
+        self.execute(EngineCommand::AddSymbol { symbol }).map(|_| ())
+    }
+
+    pub fn subscribe_depth(&mut self, symbol: &str) -> Result<Receiver<DepthUpdate>, String> {
+        self.engine.subscribe_depth(symbol)
+    }
+
+    pub fn subscribe_order_events(&mut self, symbol: &str) -> Result<Receiver<OrderEvent>, String> {
+        self.engine.subscribe_order_events(symbol)
+    }
+
+    /// Writes the engine state as of the last journaled command.
+    pub fn snapshot(&mut self) -> Result<(), String> {
+        let body = serde_json::to_string(&self.engine).map_err(|e| format!("Cannot serialize engine: {}", e))?;
+        let header = self.sequence.to_string();
+        let path = self.directory.join(format!("{}{:020}.json", SNAPSHOT_PREFIX, self.sequence));
+        let partial_path = path.with_extension("partial");
+
+        // Written aside and renamed so a crash never leaves a half-written snapshot behind
+        File::create(&partial_path)
+            .and_then(|mut file| {
+                writeln!(file, "{} {}", checksum(format!("{}\n{}", header, body).as_bytes()), header)?;
+                file.write_all(body.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&partial_path, &path))
+            .map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+
+        self.snapshot_sequence = self.sequence;
+        Ok(())
+    }
+
+    fn latest_snapshot(directory: &Path) -> Result<Option<PathBuf>, String> {
+        let entries = fs::read_dir(directory).map_err(|e| format!("Cannot list {}: {}", directory.display(), e))?;
+        Ok(entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension().is_some_and(|extension| extension == "json")
+                    && path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(SNAPSHOT_PREFIX))
+            })
+            .max())
+    }
+
+    fn load_snapshot(path: &Path) -> Result<(DEXEngine, u64), String> {
+        let what = format!("Snapshot {}", path.display());
+        let contents = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let (header_line, body) = contents.split_once('\n')
+            .ok_or_else(|| format!("{} is malformed", what))?;
+        let (expected, header) = header_line.split_once(' ')
+            .ok_or_else(|| format!("{} is malformed", what))?;
+        if checksum(format!("{}\n{}", header, body).as_bytes()) != expected {
+            return Err(format!("{} failed its checksum", what));
+        }
+
+        let sequence = header.parse().map_err(|_| format!("{} is malformed", what))?;
+        let mut engine: DEXEngine = serde_json::from_str(body).map_err(|e| format!("{} is invalid: {}", what, e))?;
+        engine.rebuild_trade_statistics();
+        Ok((engine, sequence))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex_engine::{OrderSide, OrderType};
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("dex_journal_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn place(journaled: &mut JournaledEngine, trader: &str, side: OrderSide, quantity: i64, price: i64) -> Result<Order, String> {
+        journaled.place_order(OrderRequest::new(
+            trader.to_string(),
+            "ETH/USDC".to_string(),
+            side,
+            OrderType::Limit,
+            Decimal::new(quantity, 0),
+            Some(Decimal::new(price, 0)),
+            None,
+        ))
+    }
+
+    #[test]
+    fn test_recovery_replays_journal_after_snapshot() {
+        let directory = temp_directory("recovery");
+        let mut journaled = JournaledEngine::open(&directory, 4).unwrap();
+        journaled.add_symbol("ETH/USDC".to_string()).unwrap();
+        journaled.deposit("trader1", "ETH", Decimal::new(10, 0)).unwrap();
+        journaled.deposit("trader2", "USDC", Decimal::new(100000, 0)).unwrap();
+        place(&mut journaled, "trader1", OrderSide::Sell, 3, 2000).unwrap();
+        let resting = place(&mut journaled, "trader1", OrderSide::Sell, 2, 2100).unwrap();
+        place(&mut journaled, "trader2", OrderSide::Buy, 1, 2000).unwrap();
+        assert!(place(&mut journaled, "trader2", OrderSide::Buy, 1000, 2000).is_err());
+        journaled.cancel_order(&resting.id, "trader1").unwrap();
+        journaled.withdraw("trader2", "USDC", Decimal::new(500, 0)).unwrap();
+        assert_eq!(journaled.sequence(), 9);
+
+        let expected = serde_json::to_value(journaled.engine()).unwrap();
+        drop(journaled);
+
+        // Snapshot at 8, then entry 9 replayed on top
+        let recovered = JournaledEngine::open(&directory, 4).unwrap();
+        assert_eq!(recovered.sequence(), 9);
+        assert_eq!(serde_json::to_value(recovered.engine()).unwrap(), expected);
+        assert_eq!(recovered.engine().get_trading_volume("trader1", "ETH/USDC"), Decimal::new(2000, 0));
+
+        // Journal alone reproduces the same state once the snapshots are gone
+        for entry in fs::read_dir(&directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                fs::remove_file(path).unwrap();
+            }
+        }
+        let replayed = JournaledEngine::open(&directory, 0).unwrap();
+        assert_eq!(serde_json::to_value(replayed.engine()).unwrap(), expected);
+        drop(replayed);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_recovery_checks_journal_integrity() {
+        let directory = temp_directory("integrity");
+        let mut journaled = JournaledEngine::open(&directory, 0).unwrap();
+        journaled.add_symbol("ETH/USDC".to_string()).unwrap();
+        journaled.deposit("trader1", "USDC", Decimal::new(100, 0)).unwrap();
+        drop(journaled);
+
+        // A torn last write is dropped and the journal carries on after it
+        let journal_path = directory.join(JOURNAL_FILE);
+        let mut file = OpenOptions::new().append(true).open(&journal_path).unwrap();
+        write!(file, "0123 {{\"sequence\":3").unwrap();
+        drop(file);
+        let mut journaled = JournaledEngine::open(&directory, 0).unwrap();
+        assert_eq!(journaled.sequence(), 2);
+        journaled.deposit("trader1", "USDC", Decimal::new(50, 0)).unwrap();
+        drop(journaled);
+        let journaled = JournaledEngine::open(&directory, 0).unwrap();
+        assert_eq!(journaled.engine().get_user_balance("trader1", "USDC"), Decimal::new(150, 0));
+        drop(journaled);
+
+        // A tampered entry is refused
+        let contents = fs::read_to_string(&journal_path).unwrap();
+        fs::write(&journal_path, contents.replace("\"amount\":\"100\"", "\"amount\":\"900\"")).unwrap();
+        let error = JournaledEngine::open(&directory, 0).err().unwrap();
+        assert_eq!(error, "Journal entry 2 failed its checksum");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::dex_engine::Balance;
+
+type Account = Arc<Mutex<HashMap<String, Balance>>>; // One user's balances by currency
+
+/// Balances by user and currency. Every change to a balance is atomic, and each user's balances
+/// sit behind their own lock, so engines sharing a ledger reserve funds for different users
+/// without waiting on each other.
+///
+/// `clone` copies the balances; `share` hands out another handle to the same ones.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    accounts: Arc<RwLock<HashMap<String, Account>>>,
+}
+
+impl Clone for Ledger {
+    fn clone(&self) -> Self {
+        Self::from_balances(self.balances())
+    }
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn share(&self) -> Self {
+        Self {
+            accounts: self.accounts.clone(),
+        }
+    }
+
+    fn from_balances(balances: HashMap<String, HashMap<String, Balance>>) -> Self {
+        let accounts = balances.into_iter()
+            .map(|(user, balances)| (user, Arc::new(Mutex::new(balances))))
+            .collect();
+        Self {
+            accounts: Arc::new(RwLock::new(accounts)),
+        }
+    }
+
+    /// Every user's balances at one moment per user.
+    pub fn balances(&self) -> HashMap<String, HashMap<String, Balance>> {
+        let accounts = self.accounts.read().unwrap();
+        accounts.iter()
+            .map(|(user, account)| (user.clone(), account.lock().unwrap().clone()))
+            .collect()
+    }
+
+    fn account(&self, user: &str) -> Account {
+        if let Some(account) = self.accounts.read().unwrap().get(user) {
+            return account.clone();
+        }
+        self.accounts.write().unwrap().entry(user.to_string()).or_default().clone()
+    }
+
+    pub fn get_balance(&self, user: &str, currency: &str) -> Balance {
+        let account = match self.accounts.read().unwrap().get(user) {
+            Some(account) => account.clone(),
+            None => return Balance::default(),
+        };
+        let balances = account.lock().unwrap();
+        balances.get(currency).cloned().unwrap_or_default()
+    }
+
+    pub fn get_user_balances(&self, user: &str) -> HashMap<String, Balance> {
+        let account = self.accounts.read().unwrap().get(user).cloned();
+        account.map(|account| account.lock().unwrap().clone()).unwrap_or_default()
+    }
+
+    pub fn user_count(&self) -> usize {
+        self.accounts.read().unwrap().len()
+    }
+
+    /// Applies `change` to one balance under its user's lock.
+    pub fn update<T>(&self, user: &str, currency: &str, change: impl FnOnce(&mut Balance) -> T) -> T {
+        let account = self.account(user);
+        let mut balances = account.lock().unwrap();
+        change(balances.entry(currency.to_string()).or_default())
+    }
+
+    pub fn deposit(&self, user: &str, currency: &str, amount: Decimal) {
+        self.update(user, currency, |balance| balance.available += amount);
+    }
+
+    pub fn withdraw(&self, user: &str, currency: &str, amount: Decimal) -> Result<(), String> {
+        if amount <= Decimal::ZERO {
+            return Err("Withdrawal amount must be positive".to_string());
+        }
+        self.update(user, currency, |balance| {
+            if balance.available < amount {
+                return Err("Insufficient balance".to_string());
+            }
+            balance.available -= amount;
+            Ok(())
+        })
+    }
+
+    /// Moves `amount` from available to locked, or nothing if less than that is available.
+    pub fn lock(&self, user: &str, currency: &str, amount: Decimal) -> Result<(), String> {
+        if amount < Decimal::ZERO {
+            return Err("Cannot lock a negative amount".to_string());
+        }
+        self.update(user, currency, |balance| {
+            if balance.available < amount {
+                return Err("Insufficient balance".to_string());
+            }
+            balance.available -= amount;
+            balance.locked += amount;
+            Ok(())
+        })
+    }
+
+    pub fn unlock(&self, user: &str, currency: &str, amount: Decimal) {
+        self.update(user, currency, |balance| {
+            balance.locked -= amount;
+            balance.available += amount;
+        });
+    }
+}
+
+impl Serialize for Ledger {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.balances().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ledger {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        HashMap::deserialize(deserializer).map(Self::from_balances)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_locks_never_overdraw() {
+        let ledger = Ledger::new();
+        ledger.deposit("alice", "USDC", Decimal::new(5, 0));
+        ledger.deposit("bob", "USDC", Decimal::new(8, 0));
+
+        // Sixteen threads lock one unit at a time from each user; only what was there is handed out
+        let locked: Vec<(usize, usize)> = (0..16)
+            .map(|_| {
+                let ledger = ledger.share();
+                thread::spawn(move || {
+                    let alice = ledger.lock("alice", "USDC", Decimal::ONE).is_ok() as usize;
+                    let bob = ledger.lock("bob", "USDC", Decimal::ONE).is_ok() as usize;
+                    (alice, bob)
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        assert_eq!(locked.iter().map(|(alice, _)| alice).sum::<usize>(), 5);
+        assert_eq!(locked.iter().map(|(_, bob)| bob).sum::<usize>(), 8);
+        assert_eq!(ledger.get_balance("alice", "USDC"), Balance { available: Decimal::ZERO, locked: Decimal::new(5, 0) });
+        assert_eq!(ledger.lock("bob", "USDC", Decimal::ONE).unwrap_err(), "Insufficient balance");
+
+        // Shared handles see each other's changes; clones are copies
+        let copy = ledger.clone();
+        ledger.share().unlock("bob", "USDC", Decimal::new(3, 0));
+        assert_eq!(ledger.get_balance("bob", "USDC").available, Decimal::new(3, 0));
+        assert_eq!(copy.get_balance("bob", "USDC").available, Decimal::ZERO);
+        assert!(ledger.withdraw("bob", "USDC", Decimal::new(4, 0)).is_err());
+        assert!(ledger.withdraw("bob", "USDC", Decimal::new(-1000, 0)).is_err());
+        assert!(ledger.lock("bob", "USDC", Decimal::new(-1000, 0)).is_err());
+        assert_eq!(ledger.get_balance("bob", "USDC"), Balance { available: Decimal::new(3, 0), locked: Decimal::new(5, 0) });
+        ledger.withdraw("bob", "USDC", Decimal::new(3, 0)).unwrap();
+
+        let restored: Ledger = serde_json::from_value(serde_json::to_value(&ledger).unwrap()).unwrap();
+        assert_eq!(restored.balances(), ledger.balances());
+        assert_eq!(restored.user_count(), 2);
+    }
+}
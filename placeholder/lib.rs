@@ -0,0 +1,13 @@
+//! Exchange engines shared by the API server binary and anything else built on them.
+
+pub mod api_server;
+pub mod backtest;
+pub mod candles;
+pub mod defi_protocol;
+pub mod dex_engine;
+pub mod engine_cluster;
+pub mod fix_gateway;
+pub mod journal;
+pub mod ledger;
+pub mod market_data_codec;
+pub mod nft_marketplace;
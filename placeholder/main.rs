@@ -0,0 +1,64 @@
+//! API server binary. Configured from the environment:
+//!
+//! - `API_ADDR`: address to listen on, `127.0.0.1:8080` by default
+//! - `API_SYMBOLS`: comma-separated "BASE/QUOTE" symbols to list, `BTC/USDT,ETH/USDT` by default
+//! - `API_TOKENS`: comma-separated `token:trader` pairs allowed to trade
+//! - `API_OPERATOR_TOKENS`: comma-separated tokens allowed to credit deposits
+//! - `FIX_ADDR`: address for the FIX gateway to listen on; the gateway only runs when this is set
+//! - `FIX_COMP_ID`: the gateway's own CompID, `DEX` by default
+//! - `FIX_SESSIONS`: comma-separated SenderCompIDs allowed to log on, each trading as that trader
+
+use std::env;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+use quant_terminal::api_server::ApiServer;
+use quant_terminal::defi_protocol::DeFiProtocol;
+use quant_terminal::engine_cluster::EngineCluster;
+use quant_terminal::fix_gateway::FixGateway;
+use quant_terminal::nft_marketplace::NFTMarketplace;
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let address = env::var("API_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
+    let symbols = env::var("API_SYMBOLS").unwrap_or_else(|_| "BTC/USDT,ETH/USDT".to_string());
+    let tokens = env::var("API_TOKENS").unwrap_or_default();
+    let operator_tokens = env::var("API_OPERATOR_TOKENS").unwrap_or_default();
+    let fix_address = env::var("FIX_ADDR").ok();
+    let fix_comp_id = env::var("FIX_COMP_ID").unwrap_or_else(|_| "DEX".to_string());
+    let fix_sessions = env::var("FIX_SESSIONS").unwrap_or_default();
+
+    let cluster = Arc::new(EngineCluster::new());
+    for symbol in symbols.split(',').map(str::trim).filter(|symbol| !symbol.is_empty()) {
+        cluster.add_symbol(symbol.to_string()).await?;
+    }
+    if let Some(fix_address) = fix_address {
+        let gateway = Arc::new(FixGateway::new(&fix_comp_id, cluster.clone()));
+        for session in fix_sessions.split(',').map(str::trim).filter(|session| !session.is_empty()) {
+            gateway.add_session(session).await;
+        }
+        let listener = TcpListener::bind(&fix_address).await.map_err(|e| e.to_string())?;
+        println!("FIX gateway listening on {} as {}", fix_address, fix_comp_id);
+        tokio::spawn(async move {
+            if let Err(error) = gateway.serve(listener).await {
+                eprintln!("FIX gateway stopped: {}", error);
+            }
+        });
+    }
+    let server = Arc::new(ApiServer::new(cluster,
+                                         Arc::new(Mutex::new(DeFiProtocol::new())),
+                                         Arc::new(Mutex::new(NFTMarketplace::new()))));
+    for pair in tokens.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (token, trader) = pair.split_once(':')
+            .ok_or_else(|| format!("API_TOKENS entry {} is not token:trader", pair))?;
+        server.add_trader(token, trader).await;
+    }
+    for token in operator_tokens.split(',').map(str::trim).filter(|token| !token.is_empty()) {
+        server.add_operator(token).await;
+    }
+
+    let listener = TcpListener::bind(&address).await.map_err(|e| e.to_string())?;
+    println!("API server listening on {}", address);
+    server.serve(listener).await
+}
@@ -0,0 +1,535 @@
+use rust_decimal::Decimal;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use crate::dex_engine::{DepthSnapshot, DepthUpdate, OrderBookLevel, OrderSide, SymbolSpec, Ticker, Trade};
+
+/// Bumped whenever a message layout changes; decoders refuse versions they were not built for.
+pub const SCHEMA_VERSION: u16 = 2;
+/// Version (u16), message type (u8) and body length (u32).
+pub const HEADER_LEN: usize = 7;
+
+const NULL_SCALE: u8 = u8::MAX; // Scale byte of an absent Option<Decimal>
+const MAX_SCALE: u8 = 28;
+
+/// Widths of the NUL-padded text fields.
+pub const ID_LEN: usize = 24;
+pub const TRADER_LEN: usize = 32;
+pub const TRADE_TYPE_LEN: usize = 8;
+
+const DEPTH_UPDATE: u8 = 1;
+const DEPTH_SNAPSHOT: u8 = 2;
+const TRADE: u8 = 3;
+const TICKER: u8 = 4;
+
+/// A market-data record in its binary form. Every message is a header followed by the body's
+/// fields in declaration order, little-endian and fixed width:
+///
+/// - decimals are an i64 mantissa and a u8 scale, so `1.50` keeps its trailing zero
+/// - optional decimals use a scale of 255 for None, with a zero mantissa
+/// - timestamps are i64 nanoseconds since the Unix epoch
+/// - sides are one byte, 0 for buy and 1 for sell
+/// - counts and sequences are u32 and u64
+/// - symbols are their u32 id from the `SymbolSpec` registry, mapped through a `SymbolTable`
+/// - order and trade ids, traders and trade types are UTF-8 padded with NULs to `ID_LEN`,
+///   `TRADER_LEN` and `TRADE_TYPE_LEN` bytes
+/// - snapshots carry a u16 level count before each side's levels
+#[derive(Debug, Clone)]
+pub enum MarketDataMessage {
+    DepthUpdate(DepthUpdate),
+    DepthSnapshot(DepthSnapshot),
+    Trade(Trade),
+    Ticker(Ticker),
+}
+
+/// Maps symbols to the ids they are encoded as and back, built from the engine's symbol specs.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    ids: HashMap<String, u32>,
+    symbols: HashMap<u32, String>,
+}
+
+impl SymbolTable {
+    pub fn new(specs: &[SymbolSpec]) -> Self {
+        Self {
+            ids: specs.iter().map(|spec| (spec.symbol.clone(), spec.id)).collect(),
+            symbols: specs.iter().map(|spec| (spec.id, spec.symbol.clone())).collect(),
+        }
+    }
+
+    fn id(&self, symbol: &str) -> Result<u32, String> {
+        self.ids.get(symbol).copied().ok_or_else(|| format!("Symbol {} has no id", symbol))
+    }
+
+    fn symbol(&self, id: u32) -> Result<String, String> {
+        self.symbols.get(&id).cloned().ok_or_else(|| format!("Unknown symbol id {}", id))
+    }
+}
+
+impl MarketDataMessage {
+    fn message_type(&self) -> u8 {
+        match self {
+            MarketDataMessage::DepthUpdate(_) => DEPTH_UPDATE,
+            MarketDataMessage::DepthSnapshot(_) => DEPTH_SNAPSHOT,
+            MarketDataMessage::Trade(_) => TRADE,
+            MarketDataMessage::Ticker(_) => TICKER,
+        }
+    }
+}
+
+/// Encodes one message into a fresh buffer.
+pub fn encode(message: &MarketDataMessage, symbols: &SymbolTable) -> Result<Vec<u8>, String> {
+    let mut buffer = Vec::new();
+    encode_into(message, symbols, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Appends one message to `buffer`, so a stream of them can share an allocation. On error the
+/// buffer is left as it was.
+pub fn encode_into(message: &MarketDataMessage, symbols: &SymbolTable, buffer: &mut Vec<u8>)
+    -> Result<(), String> {
+    let start = buffer.len();
+    let result = write_message(message, symbols, buffer);
+    if result.is_err() {
+        buffer.truncate(start);
+    }
+    result
+}
+
+fn write_message(message: &MarketDataMessage, symbols: &SymbolTable, buffer: &mut Vec<u8>) -> Result<(), String> {
+    let start = buffer.len();
+    buffer.extend_from_slice(&SCHEMA_VERSION.to_le_bytes());
+    buffer.push(message.message_type());
+    buffer.extend_from_slice(&0u32.to_le_bytes()); // Body length, filled in below
+
+    let mut writer = Writer { buffer, symbols };
+    match message {
+        MarketDataMessage::DepthUpdate(update) => {
+            writer.symbol(&update.symbol)?;
+            writer.u64(update.sequence);
+            writer.side(&update.side);
+            writer.decimal(update.price)?;
+            writer.decimal(update.quantity)?;
+            writer.count(update.order_count)?;
+        }
+        MarketDataMessage::DepthSnapshot(snapshot) => {
+            writer.symbol(&snapshot.symbol)?;
+            writer.u64(snapshot.sequence);
+            writer.levels(&snapshot.bids)?;
+            writer.levels(&snapshot.asks)?;
+        }
+        MarketDataMessage::Trade(trade) => {
+            writer.fixed_string(&trade.id, ID_LEN)?;
+            writer.symbol(&trade.symbol)?;
+            writer.decimal(trade.price)?;
+            writer.decimal(trade.quantity)?;
+            writer.fixed_string(&trade.buy_order_id, ID_LEN)?;
+            writer.fixed_string(&trade.sell_order_id, ID_LEN)?;
+            writer.fixed_string(&trade.buyer, TRADER_LEN)?;
+            writer.fixed_string(&trade.seller, TRADER_LEN)?;
+            writer.timestamp(trade.timestamp)?;
+            writer.fixed_string(&trade.trade_type, TRADE_TYPE_LEN)?;
+            writer.side(&trade.taker_side);
+            writer.decimal(trade.buyer_fee)?;
+            writer.decimal(trade.seller_fee)?;
+        }
+        MarketDataMessage::Ticker(ticker) => {
+            writer.symbol(&ticker.symbol)?;
+            for value in [ticker.open, ticker.high, ticker.low, ticker.last, ticker.change, ticker.change_percent,
+                          ticker.vwap, ticker.volume, ticker.quote_volume] {
+                writer.decimal(value)?;
+            }
+            writer.u64(ticker.trade_count);
+            writer.optional_decimal(ticker.best_bid)?;
+            writer.decimal(ticker.best_bid_quantity)?;
+            writer.optional_decimal(ticker.best_ask)?;
+            writer.decimal(ticker.best_ask_quantity)?;
+            writer.timestamp(ticker.timestamp)?;
+        }
+    }
+
+    let body_len = u32::try_from(buffer.len() - start - HEADER_LEN)
+        .map_err(|_| "Message body too long".to_string())?;
+    buffer[start + 3..start + HEADER_LEN].copy_from_slice(&body_len.to_le_bytes());
+    Ok(())
+}
+
+/// Decodes the message at the front of `bytes`, returning it with the number of bytes it took so
+/// messages can be read back to back.
+pub fn decode(bytes: &[u8], symbols: &SymbolTable) -> Result<(MarketDataMessage, usize), String> {
+    if bytes.len() < HEADER_LEN {
+        return Err("Truncated header".to_string());
+    }
+    let version = u16::from_le_bytes([bytes[0], bytes[1]]);
+    if version != SCHEMA_VERSION {
+        return Err(format!("Unsupported schema version {}", version));
+    }
+    let message_type = bytes[2];
+    let body_len = u32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]) as usize;
+    let body = bytes.get(HEADER_LEN..HEADER_LEN + body_len)
+        .ok_or_else(|| "Truncated body".to_string())?;
+
+    let mut reader = Reader { bytes: body, symbols };
+    let message = match message_type {
+        DEPTH_UPDATE => MarketDataMessage::DepthUpdate(DepthUpdate {
+            symbol: reader.symbol()?,
+            sequence: reader.u64()?,
+            side: reader.side()?,
+            price: reader.decimal()?,
+            quantity: reader.decimal()?,
+            order_count: reader.count()?,
+        }),
+        DEPTH_SNAPSHOT => MarketDataMessage::DepthSnapshot(DepthSnapshot {
+            symbol: reader.symbol()?,
+            sequence: reader.u64()?,
+            bids: reader.levels()?,
+            asks: reader.levels()?,
+        }),
+        TRADE => MarketDataMessage::Trade(Trade {
+            id: reader.fixed_string(ID_LEN)?,
+            symbol: reader.symbol()?,
+            price: reader.decimal()?,
+            quantity: reader.decimal()?,
+            buy_order_id: reader.fixed_string(ID_LEN)?,
+            sell_order_id: reader.fixed_string(ID_LEN)?,
+            buyer: reader.fixed_string(TRADER_LEN)?,
+            seller: reader.fixed_string(TRADER_LEN)?,
+            timestamp: reader.timestamp()?,
+            trade_type: reader.fixed_string(TRADE_TYPE_LEN)?,
+            taker_side: reader.side()?,
+            buyer_fee: reader.decimal()?,
+            seller_fee: reader.decimal()?,
+        }),
+        TICKER => MarketDataMessage::Ticker(Ticker {
+            symbol: reader.symbol()?,
+            open: reader.decimal()?,
+            high: reader.decimal()?,
+            low: reader.decimal()?,
+            last: reader.decimal()?,
+            change: reader.decimal()?,
+            change_percent: reader.decimal()?,
+            vwap: reader.decimal()?,
+            volume: reader.decimal()?,
+            quote_volume: reader.decimal()?,
+            trade_count: reader.u64()?,
+            best_bid: reader.optional_decimal()?,
+            best_bid_quantity: reader.decimal()?,
+            best_ask: reader.optional_decimal()?,
+            best_ask_quantity: reader.decimal()?,
+            timestamp: reader.timestamp()?,
+        }),
+        other => return Err(format!("Unknown message type {}", other)),
+    };
+    if !reader.bytes.is_empty() {
+        return Err("Trailing bytes in message body".to_string());
+    }
+    Ok((message, HEADER_LEN + body_len))
+}
+
+struct Writer<'a> {
+    buffer: &'a mut Vec<u8>,
+    symbols: &'a SymbolTable,
+}
+
+impl Writer<'_> {
+    fn u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn count(&mut self, value: usize) -> Result<(), String> {
+        let value = u32::try_from(value).map_err(|_| format!("Count {} does not fit in u32", value))?;
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    fn side(&mut self, side: &OrderSide) {
+        self.buffer.push(match side {
+            OrderSide::Buy => 0,
+            OrderSide::Sell => 1,
+        });
+    }
+
+    fn symbol(&mut self, symbol: &str) -> Result<(), String> {
+        let id = self.symbols.id(symbol)?;
+        self.buffer.extend_from_slice(&id.to_le_bytes());
+        Ok(())
+    }
+
+    fn fixed_string(&mut self, value: &str, len: usize) -> Result<(), String> {
+        if value.len() > len {
+            return Err(format!("{} is longer than {} bytes", value, len));
+        }
+        if value.contains('\0') {
+            return Err(format!("{:?} contains a NUL byte", value));
+        }
+        self.buffer.extend_from_slice(value.as_bytes());
+        self.buffer.resize(self.buffer.len() + len - value.len(), 0);
+        Ok(())
+    }
+
+    fn decimal(&mut self, value: Decimal) -> Result<(), String> {
+        let mantissa = i64::try_from(value.mantissa())
+            .map_err(|_| format!("{} does not fit a 64-bit mantissa", value))?;
+        self.buffer.extend_from_slice(&mantissa.to_le_bytes());
+        self.buffer.push(value.scale() as u8);
+        Ok(())
+    }
+
+    fn optional_decimal(&mut self, value: Option<Decimal>) -> Result<(), String> {
+        match value {
+            Some(value) => self.decimal(value),
+            None => {
+                self.buffer.extend_from_slice(&0i64.to_le_bytes());
+                self.buffer.push(NULL_SCALE);
+                Ok(())
+            }
+        }
+    }
+
+    fn timestamp(&mut self, value: DateTime<Utc>) -> Result<(), String> {
+        let nanos = value.timestamp_nanos_opt()
+            .ok_or_else(|| format!("Timestamp {} is out of range", value))?;
+        self.buffer.extend_from_slice(&nanos.to_le_bytes());
+        Ok(())
+    }
+
+    fn levels(&mut self, levels: &[OrderBookLevel]) -> Result<(), String> {
+        let count = u16::try_from(levels.len()).map_err(|_| format!("{} levels is too many", levels.len()))?;
+        self.buffer.extend_from_slice(&count.to_le_bytes());
+        for level in levels {
+            self.decimal(level.price)?;
+            self.decimal(level.quantity)?;
+            self.count(level.order_count)?;
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    symbols: &'a SymbolTable,
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let (head, rest) = self.bytes.split_first_chunk::<N>()
+            .ok_or_else(|| "Truncated body".to_string())?;
+        self.bytes = rest;
+        Ok(*head)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn count(&mut self) -> Result<usize, String> {
+        Ok(u32::from_le_bytes(self.take()?) as usize)
+    }
+
+    fn side(&mut self) -> Result<OrderSide, String> {
+        match self.take::<1>()?[0] {
+            0 => Ok(OrderSide::Buy),
+            1 => Ok(OrderSide::Sell),
+            other => Err(format!("Unknown side {}", other)),
+        }
+    }
+
+    fn symbol(&mut self) -> Result<String, String> {
+        self.symbols.symbol(u32::from_le_bytes(self.take()?))
+    }
+
+    fn fixed_string(&mut self, len: usize) -> Result<String, String> {
+        if self.bytes.len() < len {
+            return Err("Truncated body".to_string());
+        }
+        let (field, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        let end = field.iter().rposition(|&byte| byte != 0).map_or(0, |last| last + 1);
+        String::from_utf8(field[..end].to_vec()).map_err(|e| format!("Invalid UTF-8 in text field: {}", e))
+    }
+
+    fn optional_decimal(&mut self) -> Result<Option<Decimal>, String> {
+        let mantissa = i64::from_le_bytes(self.take()?);
+        match self.take::<1>()?[0] {
+            NULL_SCALE => Ok(None),
+            scale if scale <= MAX_SCALE => Ok(Some(Decimal::new(mantissa, scale as u32))),
+            scale => Err(format!("Decimal scale {} is out of range", scale)),
+        }
+    }
+
+    fn decimal(&mut self) -> Result<Decimal, String> {
+        self.optional_decimal()?.ok_or_else(|| "Missing required decimal".to_string())
+    }
+
+    fn timestamp(&mut self) -> Result<DateTime<Utc>, String> {
+        Ok(DateTime::from_timestamp_nanos(i64::from_le_bytes(self.take()?)))
+    }
+
+    fn levels(&mut self) -> Result<Vec<OrderBookLevel>, String> {
+        let count = u16::from_le_bytes(self.take()?);
+        (0..count)
+            .map(|_| Ok(OrderBookLevel {
+                price: self.decimal()?,
+                quantity: self.decimal()?,
+                order_count: self.count()?,
+            }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex_engine::{DEXEngine, OrderRequest, OrderType, TimeInForce};
+    use serde_json::Value;
+
+    fn as_json(message: &MarketDataMessage) -> Value {
+        match message {
+            MarketDataMessage::DepthUpdate(update) => serde_json::to_value(update),
+            MarketDataMessage::DepthSnapshot(snapshot) => serde_json::to_value(snapshot),
+            MarketDataMessage::Trade(trade) => serde_json::to_value(trade),
+            MarketDataMessage::Ticker(ticker) => serde_json::to_value(ticker),
+        }.unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_matches_serde() {
+        let mut engine = DEXEngine::new();
+        engine.add_symbol("BTC/USDT".to_string());
+        let depth = engine.subscribe_depth("BTC/USDT").unwrap();
+        engine.deposit("alice", "BTC", Decimal::new(5, 0));
+        engine.deposit("bob", "USDT", Decimal::new(1_000_000, 0));
+        engine.place_order(OrderRequest::new("alice".to_string(), "BTC/USDT".to_string(), OrderSide::Sell,
+                                             OrderType::Limit, Decimal::new(150, 2), Some(Decimal::new(3000050, 2)),
+                                             None)).unwrap();
+        engine.place_order(OrderRequest::new("bob".to_string(), "BTC/USDT".to_string(), OrderSide::Buy,
+                                             OrderType::Limit, Decimal::new(1, 0), Some(Decimal::new(2999900, 2)),
+                                             None)).unwrap();
+        engine.place_order(OrderRequest {
+            time_in_force: TimeInForce::IOC,
+            ..OrderRequest::new("bob".to_string(), "BTC/USDT".to_string(), OrderSide::Buy, OrderType::Market,
+                                Decimal::new(25, 2), None, None)
+        }).unwrap();
+
+        let symbols = SymbolTable::new(&engine.get_symbol_specs());
+        let mut messages: Vec<MarketDataMessage> = depth.try_iter().map(MarketDataMessage::DepthUpdate).collect();
+        messages.push(MarketDataMessage::DepthSnapshot(engine.get_depth_snapshot("BTC/USDT", 10).unwrap()));
+        messages.extend(engine.get_trades().iter().cloned().map(MarketDataMessage::Trade));
+        let mut ticker = engine.get_ticker("BTC/USDT").unwrap();
+        messages.push(MarketDataMessage::Ticker(ticker.clone()));
+        ticker.best_ask = None; // Absent optional decimals survive too
+        messages.push(MarketDataMessage::Ticker(ticker));
+        assert!(messages.len() >= 6);
+
+        // Concatenated frames decode back one after another
+        let mut stream = Vec::new();
+        for message in &messages {
+            encode_into(message, &symbols, &mut stream).unwrap();
+        }
+        let mut position = 0;
+        for message in &messages {
+            let encoded = encode(message, &symbols).unwrap();
+            let json = serde_json::to_vec(&as_json(message)).unwrap();
+            assert!(encoded.len() < json.len(), "{} bytes against {} of JSON", encoded.len(), json.len());
+
+            let (decoded, len) = decode(&stream[position..], &symbols).unwrap();
+            assert_eq!(len, encoded.len());
+            assert_eq!(as_json(&decoded), as_json(message));
+            position += len;
+        }
+        assert_eq!(position, stream.len());
+    }
+
+    #[test]
+    fn test_rejects_malformed_frames() {
+        let mut engine = DEXEngine::new();
+        engine.add_symbol("BTC/USDT".to_string());
+        engine.add_symbol("ETH/USDT".to_string());
+        let symbols = SymbolTable::new(&engine.get_symbol_specs());
+        let update = DepthUpdate {
+            symbol: "ETH/USDT".to_string(),
+            sequence: 7,
+            side: OrderSide::Buy,
+            price: Decimal::new(180025, 2),
+            quantity: Decimal::new(3, 0),
+            order_count: 2,
+        };
+        let encoded = encode(&MarketDataMessage::DepthUpdate(update.clone()), &symbols).unwrap();
+        assert_eq!(encoded.len(), HEADER_LEN + 4 + 8 + 1 + 9 + 9 + 4);
+        assert_eq!(encoded[HEADER_LEN..HEADER_LEN + 4], 2u32.to_le_bytes()); // ETH/USDT was listed second
+
+        let mut newer = encoded.clone();
+        newer[..2].copy_from_slice(&(SCHEMA_VERSION + 1).to_le_bytes());
+        assert_eq!(decode(&newer, &symbols).unwrap_err(), format!("Unsupported schema version {}", SCHEMA_VERSION + 1));
+        assert_eq!(decode(&encoded[..encoded.len() - 1], &symbols).unwrap_err(), "Truncated body");
+        let mut unknown = encoded.clone();
+        unknown[2] = 99;
+        assert_eq!(decode(&unknown, &symbols).unwrap_err(), "Unknown message type 99");
+        let mut unlisted = encoded.clone();
+        unlisted[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&9u32.to_le_bytes());
+        assert_eq!(decode(&unlisted, &symbols).unwrap_err(), "Unknown symbol id 9");
+        let other = DepthUpdate { symbol: "SOL/USDT".to_string(), ..update.clone() };
+        assert_eq!(encode(&MarketDataMessage::DepthUpdate(other), &symbols).unwrap_err(), "Symbol SOL/USDT has no id");
+
+        // Mantissas wider than 64 bits are refused rather than truncated, and the buffer is untouched
+        let mut buffer = encoded.clone();
+        let wide = MarketDataMessage::DepthUpdate(DepthUpdate {
+            price: Decimal::MAX,
+            ..update
+        });
+        assert!(encode_into(&wide, &symbols, &mut buffer).is_err());
+        assert_eq!(buffer, encoded);
+    }
+
+    #[test]
+    fn test_rejects_malformed_text_fields() {
+        let mut engine = DEXEngine::new();
+        engine.add_symbol("BTC/USDT".to_string());
+        let symbols = SymbolTable::new(&engine.get_symbol_specs());
+        let trade = Trade {
+            id: "trade_1".to_string(),
+            symbol: "BTC/USDT".to_string(),
+            price: Decimal::new(3000050, 2),
+            quantity: Decimal::new(1, 0),
+            buy_order_id: "order_2".to_string(),
+            sell_order_id: "order_1".to_string(),
+            buyer: "bob".to_string(),
+            seller: "alice".to_string(),
+            timestamp: Utc::now(),
+            trade_type: "limit".to_string(),
+            taker_side: OrderSide::Buy,
+            buyer_fee: Decimal::ZERO,
+            seller_fee: Decimal::ZERO,
+        };
+        let encoded = encode(&MarketDataMessage::Trade(trade.clone()), &symbols).unwrap();
+        assert_eq!(encoded.len(), HEADER_LEN + ID_LEN + 4 + 9 + 9 + 2 * ID_LEN + 2 * TRADER_LEN + 8
+                                   + TRADE_TYPE_LEN + 1 + 9 + 9);
+        let buyer_at = HEADER_LEN + ID_LEN + 4 + 9 + 9 + 2 * ID_LEN;
+        assert_eq!(&encoded[buyer_at..buyer_at + 3], b"bob");
+        assert!(encoded[buyer_at + 3..buyer_at + TRADER_LEN].iter().all(|&byte| byte == 0));
+
+        // A body that ends partway through a text field
+        let mut truncated = encoded[..buyer_at + 10].to_vec();
+        truncated[3..HEADER_LEN].copy_from_slice(&((buyer_at + 10 - HEADER_LEN) as u32).to_le_bytes());
+        assert_eq!(decode(&truncated, &symbols).unwrap_err(), "Truncated body");
+
+        // Bytes that are not UTF-8, whether at the start of a field or just before its padding
+        let mut invalid = encoded.clone();
+        invalid[buyer_at] = 0xFF;
+        assert!(decode(&invalid, &symbols).unwrap_err().starts_with("Invalid UTF-8 in text field"));
+        let mut split = encoded.clone();
+        split[buyer_at + 3] = 0xE2; // First byte of a three-byte sequence, cut short by the padding
+        assert!(decode(&split, &symbols).unwrap_err().starts_with("Invalid UTF-8 in text field"));
+
+        // Values that do not fit their field, or would be cut short by the padding, are refused
+        let long = Trade { buyer: "b".repeat(TRADER_LEN + 1), ..trade.clone() };
+        assert_eq!(encode(&MarketDataMessage::Trade(long), &symbols).unwrap_err(),
+                   format!("{} is longer than {} bytes", "b".repeat(TRADER_LEN + 1), TRADER_LEN));
+        let nul = Trade { seller: "al\0ice".to_string(), ..trade.clone() };
+        assert!(encode(&MarketDataMessage::Trade(nul), &symbols).is_err());
+        let full = Trade { buyer: "b".repeat(TRADER_LEN), ..trade };
+        let encoded = encode(&MarketDataMessage::Trade(full.clone()), &symbols).unwrap();
+        let (decoded, _) = decode(&encoded, &symbols).unwrap();
+        assert_eq!(as_json(&decoded), as_json(&MarketDataMessage::Trade(full)));
+    }
+}
@@ -1,6 +1,5 @@
+use std::cmp::Reverse;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
@@ -42,7 +41,7 @@ pub enum ListingType {
     DutchAuction,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum ListingStatus {
     Active,
     Sold,
@@ -121,6 +120,12 @@ pub struct NFTMarketplace {
     transaction_counter: u64,
 }
 
+impl Default for NFTMarketplace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NFTMarketplace {
     pub fn new() -> Self {
         Self {
@@ -233,7 +238,10 @@ impl NFTMarketplace {
         Ok(listing_id)
     }
 
+    /// Takes the bid amount from the bidder's balance and holds it until the bid is accepted, or
+    /// hands it back when the bid is outbid, withdrawn or the listing closes without it.
     pub fn place_bid(&mut self, listing_id: &str, bidder: &str, amount: Decimal, currency: &str) -> Result<String, String> {
+        let bidder_balance = self.get_user_balance(bidder, currency);
         let listing = self.listings.get_mut(listing_id)
             .ok_or_else(|| "Listing not found".to_string())?;
 
@@ -245,7 +253,7 @@ impl NFTMarketplace {
             return Err("Cannot bid on own listing".to_string());
         }
 
-        if currency != &listing.currency {
+        if currency != listing.currency {
             return Err("Currency mismatch".to_string());
         }
 
@@ -271,7 +279,6 @@ impl NFTMarketplace {
         }
 
         // Check bidder balance
-        let bidder_balance = self.get_user_balance(bidder, currency);
         if bidder_balance < amount {
             return Err("Insufficient balance".to_string());
         }
@@ -289,57 +296,103 @@ impl NFTMarketplace {
             is_active: true,
         };
 
-        // Update listing with new highest bid
-        if matches!(listing.listing_type, ListingType::Auction) {
+        // Update listing with new highest bid; the bid it beats gets its funds back
+        let outbid = matches!(listing.listing_type, ListingType::Auction);
+        if outbid {
             listing.highest_bid = Some(amount);
             listing.highest_bidder = Some(bidder.to_string());
         }
+        if outbid {
+            self.release_bids(listing_id, None);
+        }
 
+        self.update_balance(bidder, currency, bidder_balance - amount);
         self.bids.get_mut(listing_id).unwrap().push(bid);
 
         Ok(bid_id)
     }
 
+    /// Withdraws an active bid and returns its funds to the bidder.
+    pub fn cancel_bid(&mut self, listing_id: &str, bid_id: &str, bidder: &str) -> Result<(), String> {
+        let bid = self.bids.get(listing_id)
+            .and_then(|bids| bids.iter().find(|b| b.id == bid_id && b.is_active))
+            .ok_or_else(|| "Bid not found or not active".to_string())?;
+
+        if bid.bidder != bidder {
+            return Err("Not the bidder".to_string());
+        }
+
+        if let Some(listing) = self.listings.get_mut(listing_id) {
+            if listing.highest_bidder.as_deref() == Some(bidder) {
+                listing.highest_bid = None;
+                listing.highest_bidder = None;
+            }
+        }
+        self.release_bids(listing_id, Some(bid_id));
+        Ok(())
+    }
+
+    /// Deactivates the listing's active bids, or just `only` when given, and refunds their holds.
+    fn release_bids(&mut self, listing_id: &str, only: Option<&str>) {
+        let mut refunds = Vec::new();
+        if let Some(bids) = self.bids.get_mut(listing_id) {
+            for bid in bids.iter_mut().filter(|b| b.is_active && only.is_none_or(|id| b.id == id)) {
+                bid.is_active = false;
+                refunds.push((bid.bidder.clone(), bid.currency.clone(), bid.amount));
+            }
+        }
+        for (bidder, currency, amount) in refunds {
+            let balance = self.get_user_balance(&bidder, &currency);
+            self.update_balance(&bidder, &currency, balance + amount);
+        }
+    }
+
     pub fn accept_bid(&mut self, listing_id: &str, bid_id: &str, seller: &str) -> Result<String, String> {
-        let listing = self.listings.get_mut(listing_id)
+        let listing = self.listings.get(listing_id)
             .ok_or_else(|| "Listing not found".to_string())?;
 
         if listing.seller != seller {
             return Err("Not the seller".to_string());
         }
 
-        let bids = self.bids.get_mut(listing_id)
+        if listing.status != ListingStatus::Active {
+            return Err("Listing not active".to_string());
+        }
+
+        let token_id = listing.token_id.clone();
+        let contract_address = listing.contract_address.clone();
+
+        let bids = self.bids.get(listing_id)
             .ok_or_else(|| "Bids not found".to_string())?;
 
-        let bid_index = bids.iter().position(|b| b.id == bid_id && b.is_active)
+        let bid = bids.iter().find(|b| b.id == bid_id && b.is_active)
+            .cloned()
             .ok_or_else(|| "Bid not found or not active".to_string())?;
 
-        let bid = &bids[bid_index];
-
         // Transfer NFT ownership
-        let nft = self.nfts.get_mut(&listing.token_id)
+        let nft = self.nfts.get_mut(&token_id)
             .ok_or_else(|| "NFT not found".to_string())?;
 
         let previous_owner = nft.owner.clone();
         nft.owner = bid.bidder.clone();
         nft.is_listed = false;
+        let creator = nft.creator.clone();
 
         // Calculate fees
         let royalty_amount = bid.amount * nft.royalty_percentage;
         let platform_fee = bid.amount * self.platform_fee_percentage;
         let seller_amount = bid.amount - royalty_amount - platform_fee;
 
-        // Update balances
-        self.update_balance(&bid.bidder, &bid.currency, self.get_user_balance(&bid.bidder, &bid.currency) - bid.amount);
-        self.update_balance(&nft.creator, &bid.currency, self.get_user_balance(&nft.creator, &bid.currency) + royalty_amount);
+        // Update balances; the bidder paid when the bid was placed
+        self.update_balance(&creator, &bid.currency, self.get_user_balance(&creator, &bid.currency) + royalty_amount);
         self.update_balance(seller, &bid.currency, self.get_user_balance(seller, &bid.currency) + seller_amount);
 
         // Record transaction
         self.transaction_counter += 1;
         let transaction = Transaction {
             id: format!("tx_{}", self.transaction_counter),
-            token_id: listing.token_id.clone(),
-            contract_address: listing.contract_address.clone(),
+            token_id,
+            contract_address: contract_address.clone(),
             from_address: previous_owner,
             to_address: bid.bidder.clone(),
             price: Some(bid.amount),
@@ -353,17 +406,18 @@ impl NFTMarketplace {
         self.transactions.push(transaction);
 
         // Update listing status
-        listing.status = ListingStatus::Sold;
+        if let Some(listing) = self.listings.get_mut(listing_id) {
+            listing.status = ListingStatus::Sold;
+        }
 
-        // Deactivate all other bids
-        for b in bids.iter_mut() {
-            if b.id != bid_id {
-                b.is_active = false;
-            }
+        // Spend the accepted bid and refund all other bids
+        if let Some(b) = self.bids.get_mut(listing_id).and_then(|bids| bids.iter_mut().find(|b| b.id == bid_id)) {
+            b.is_active = false;
         }
+        self.release_bids(listing_id, None);
 
         // Update collection stats
-        if let Some(collection) = self.collections.get_mut(&listing.contract_address) {
+        if let Some(collection) = self.collections.get_mut(&contract_address) {
             collection.volume_traded += bid.amount;
             if collection.floor_price.is_none() || Some(bid.amount) < collection.floor_price {
                 collection.floor_price = Some(bid.amount);
@@ -394,6 +448,7 @@ impl NFTMarketplace {
         if buyer_balance < listing.price {
             return Err("Insufficient balance".to_string());
         }
+        let listing = listing.clone();
 
         // Transfer NFT ownership
         let nft = self.nfts.get_mut(&listing.token_id)
@@ -402,6 +457,7 @@ impl NFTMarketplace {
         let previous_owner = nft.owner.clone();
         nft.owner = buyer.to_string();
         nft.is_listed = false;
+        let creator = nft.creator.clone();
 
         // Calculate fees
         let royalty_amount = listing.price * nft.royalty_percentage;
@@ -410,7 +466,7 @@ impl NFTMarketplace {
 
         // Update balances
         self.update_balance(buyer, &listing.currency, buyer_balance - listing.price);
-        self.update_balance(&nft.creator, &listing.currency, self.get_user_balance(&nft.creator, &listing.currency) + royalty_amount);
+        self.update_balance(&creator, &listing.currency, self.get_user_balance(&creator, &listing.currency) + royalty_amount);
         self.update_balance(&listing.seller, &listing.currency, self.get_user_balance(&listing.seller, &listing.currency) + seller_amount);
 
         // Record transaction
@@ -432,6 +488,7 @@ impl NFTMarketplace {
         self.transactions.push(transaction);
 
         // Update listing status
+        self.release_bids(listing_id, None);
         let listing = self.listings.get_mut(listing_id).unwrap();
         listing.status = ListingStatus::Sold;
 
@@ -465,12 +522,8 @@ impl NFTMarketplace {
             nft.is_listed = false;
         }
 
-        // Deactivate all bids
-        if let Some(bids) = self.bids.get_mut(listing_id) {
-            for bid in bids.iter_mut() {
-                bid.is_active = false;
-            }
-        }
+        // Deactivate all bids and refund them
+        self.release_bids(listing_id, None);
 
         Ok(())
     }
@@ -575,7 +628,7 @@ impl NFTMarketplace {
     pub fn update_balance(&mut self, user: &str, currency: &str, amount: Decimal) {
         self.user_balances
             .entry(user.to_string())
-            .or_insert_with(HashMap::new)
+            .or_default()
             .insert(currency.to_string(), amount);
     }
 
@@ -637,7 +690,7 @@ impl NFTMarketplace {
 
     pub fn get_top_collections(&self, limit: usize) -> Vec<Collection> {
         let mut collections: Vec<Collection> = self.collections.values().cloned().collect();
-        collections.sort_by(|a, b| b.volume_traded.cmp(&a.volume_traded));
+        collections.sort_by_key(|collection| Reverse(collection.volume_traded));
         collections.into_iter().take(limit).collect()
     }
 
@@ -714,4 +767,49 @@ mod tests {
         let nft = marketplace.get_nft(&token_id).unwrap();
         assert_eq!(nft.owner, "buyer1");
     }
+
+    #[test]
+    fn test_bids_hold_funds() {
+        let mut marketplace = NFTMarketplace::new();
+
+        let metadata = NFTMetadata {
+            name: "Test NFT".to_string(),
+            description: "A test NFT".to_string(),
+            image: "ipfs://test".to_string(),
+            attributes: vec![],
+            external_url: None,
+            animation_url: None,
+        };
+
+        let token_id = marketplace.mint_nft("0x123", "creator1", metadata, Decimal::new(5, 2)).unwrap();
+        let listing_id = marketplace.create_listing(&token_id, "creator1", ListingType::Auction, Decimal::new(100, 0), "ETH".to_string(), Some(7)).unwrap();
+
+        marketplace.deposit_funds("bidder1", "ETH", Decimal::new(150, 0));
+        marketplace.deposit_funds("bidder2", "ETH", Decimal::new(200, 0));
+
+        // A bid's amount is held, so it cannot be withdrawn while the bid stands
+        let first = marketplace.place_bid(&listing_id, "bidder1", Decimal::new(120, 0), "ETH").unwrap();
+        assert_eq!(marketplace.get_user_balance("bidder1", "ETH"), Decimal::new(30, 0));
+        assert!(marketplace.withdraw_funds("bidder1", "ETH", Decimal::new(120, 0)).is_err());
+
+        // Being outbid hands the hold back
+        let second = marketplace.place_bid(&listing_id, "bidder2", Decimal::new(160, 0), "ETH").unwrap();
+        assert_eq!(marketplace.get_user_balance("bidder1", "ETH"), Decimal::new(150, 0));
+        assert_eq!(marketplace.get_user_balance("bidder2", "ETH"), Decimal::new(40, 0));
+        assert!(marketplace.accept_bid(&listing_id, &first, "creator1").is_err());
+
+        // Withdrawing a bid refunds it too
+        marketplace.cancel_bid(&listing_id, &second, "bidder2").unwrap();
+        assert_eq!(marketplace.get_user_balance("bidder2", "ETH"), Decimal::new(200, 0));
+        assert!(marketplace.accept_bid(&listing_id, &second, "creator1").is_err());
+
+        // Accepting pays the seller from the hold without charging the bidder twice
+        let third = marketplace.place_bid(&listing_id, "bidder2", Decimal::new(200, 0), "ETH").unwrap();
+        assert!(marketplace.withdraw_funds("bidder2", "ETH", Decimal::ONE).is_err());
+        marketplace.accept_bid(&listing_id, &third, "creator1").unwrap();
+        assert_eq!(marketplace.get_user_balance("bidder2", "ETH"), Decimal::ZERO);
+        assert_eq!(marketplace.get_user_balance("creator1", "ETH"), Decimal::new(195, 0));
+        assert_eq!(marketplace.get_nft(&token_id).unwrap().owner, "bidder2");
+        assert!(marketplace.accept_bid(&listing_id, &third, "creator1").is_err());
+    }
 }
\ No newline at end of file